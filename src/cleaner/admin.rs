@@ -0,0 +1,142 @@
+use anyhow::{Context, Result};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use log::{error, info};
+use std::{collections::BTreeMap, net::SocketAddr, sync::Arc};
+use tokio::net::TcpListener;
+
+use crate::cleaner::{
+    config::FullConfig,
+    dead_letter::{dead_letters, DeadLetter},
+    metrics::metrics,
+    run_history::{run_history, RunRecord},
+};
+
+#[derive(Clone)]
+struct AdminState {
+    /// Already redacted, so handlers can return it as is
+    config: Arc<FullConfig>,
+}
+
+fn router(state: AdminState) -> Router {
+    Router::new()
+        .route("/config", get(get_config))
+        .route("/jobs", get(get_jobs))
+        .route("/metrics", get(get_metrics))
+        .route("/dead_letters", get(get_dead_letters))
+        .route("/dead_letters/:task/enable", post(enable_dead_letter))
+        .with_state(state)
+}
+
+/// The effective configuration as loaded, with secrets redacted.
+async fn get_config(State(state): State<AdminState>) -> Json<FullConfig> {
+    Json(state.config.as_ref().clone())
+}
+
+/// The recent runs of every task that ran since the history was started, oldest first.
+async fn get_jobs() -> Json<BTreeMap<String, Vec<RunRecord>>> {
+    Json(
+        run_history()
+            .map(|history| history.snapshot())
+            .unwrap_or_default(),
+    )
+}
+
+/// Every metric, such as `kiyoshi_db_pool_connections`, in the Prometheus text format.
+async fn get_metrics() -> String {
+    metrics().render()
+}
+
+/// The tasks disabled after repeated failures, by name.
+async fn get_dead_letters() -> Json<BTreeMap<String, DeadLetter>> {
+    Json(
+        dead_letters()
+            .map(|dead_letters| dead_letters.snapshot())
+            .unwrap_or_default(),
+    )
+}
+
+/// Re-enables a task disabled after repeated failures, so it runs again from its next schedule.
+async fn enable_dead_letter(Path(task): Path<String>) -> StatusCode {
+    if dead_letters().is_some_and(|dead_letters| dead_letters.enable(&task)) {
+        info!("Task {} re-enabled through the admin server", task);
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+/// Binds the admin server to `listen_address` and serves it in the background. Binding errors are
+/// returned so a misconfigured address fails startup.
+pub async fn start(listen_address: &str, config: &FullConfig) -> Result<SocketAddr> {
+    let listener = TcpListener::bind(listen_address)
+        .await
+        .with_context(|| format!("Failed to bind admin server to {}", listen_address))?;
+    let local_addr = listener.local_addr()?;
+    let app = router(AdminState {
+        config: Arc::new(config.redacted()),
+    });
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            error!("Admin server stopped: {}", e);
+        }
+    });
+    info!("Admin server listening on http://{}", local_addr);
+    Ok(local_addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cleaner::config::{generate_example_config, DeadLetterConfig};
+
+    #[tokio::test]
+    async fn test_admin_config_endpoint() {
+        let mut config = FullConfig::load_from_str(&generate_example_config()).unwrap();
+        config.config.database_config.password = "hunter2".to_string();
+        let address = start("127.0.0.1:0", &config).await.unwrap();
+
+        let response = reqwest::get(format!("http://{}/config", address))
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["config"]["database_config"]["password"], "[REDACTED]");
+        assert_eq!(
+            body["cleanup_tasks"].as_array().unwrap().len(),
+            config.cleanup_tasks.len()
+        );
+
+        metrics().set_gauge("kiyoshi_admin_test", &[], 1.0);
+        let response = reqwest::get(format!("http://{}/metrics", address))
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+        let body = response.text().await.unwrap();
+        assert!(body.contains("kiyoshi_admin_test 1\n"), "{}", body);
+
+        let dead_letters = crate::cleaner::dead_letter::init(&DeadLetterConfig {
+            max_consecutive_failures: Some(1),
+            path: None,
+        })
+        .unwrap();
+        dead_letters.record_failure("purge_logs", "boom", chrono::Utc::now());
+        let response = reqwest::get(format!("http://{}/dead_letters", address))
+            .await
+            .unwrap();
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["purge_logs"]["last_error"], "boom");
+
+        let client = reqwest::Client::new();
+        let enable_url = format!("http://{}/dead_letters/purge_logs/enable", address);
+        let response = client.post(&enable_url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(!dead_letters.is_disabled("purge_logs"));
+        let response = client.post(&enable_url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
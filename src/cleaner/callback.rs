@@ -0,0 +1,181 @@
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use serde::Serialize;
+use std::time::Duration;
+
+use crate::cleaner::{config::Config, run_history::RunRecord};
+
+/// Version of the [`RunResult`] payload, bumped on incompatible changes so consumers can evolve.
+pub const RUN_RESULT_SCHEMA_VERSION: u32 = 1;
+
+/// Time limit of each attempt to post a run result
+const CALLBACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Delay before the first retry of a failed post, doubled on every further retry
+const CALLBACK_RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+
+/// The machine-readable result of a task run, posted to `result_callback_url`.
+#[derive(Debug, Serialize)]
+pub struct RunResult<'a> {
+    pub schema_version: u32,
+    pub task: &'a str,
+    pub batches: u32,
+    #[serde(flatten)]
+    pub run: &'a RunRecord,
+}
+
+/// Posts a run's result to `result_callback_url`, if configured. Failures are logged, not
+/// returned, so the callback never fails a task.
+pub async fn send_run_result(config: &Config, task_name: &str, batches: u32, run: &RunRecord) {
+    let Some(url) = &config.result_callback_url else {
+        return;
+    };
+    let result = RunResult {
+        schema_version: RUN_RESULT_SCHEMA_VERSION,
+        task: task_name,
+        batches,
+        run,
+    };
+    let client = reqwest::Client::new();
+    match post_with_retries(
+        &client,
+        url,
+        &result,
+        config.result_callback_retries,
+        CALLBACK_RETRY_BASE_DELAY,
+    )
+    .await
+    {
+        Ok(()) => info!("Run result of task {} posted to callback", task_name),
+        Err(e) => warn!(
+            "Failed to post run result of task {} to callback: {:#}",
+            task_name,
+            config.redact_secrets(&format!("{:#}", e))
+        ),
+    }
+}
+
+/// Posts `body` as JSON, retrying network errors and server errors up to `retries` times with
+/// exponential backoff. Client errors are returned without retrying.
+async fn post_with_retries<T: Serialize>(
+    client: &reqwest::Client,
+    url: &str,
+    body: &T,
+    retries: u32,
+    base_delay: Duration,
+) -> Result<()> {
+    let mut attempt = 0;
+    loop {
+        let (error, retryable) = match client
+            .post(url)
+            .timeout(CALLBACK_TIMEOUT)
+            .json(body)
+            .send()
+            .await
+        {
+            Ok(response) if response.status().is_success() => return Ok(()),
+            Ok(response) => (
+                anyhow!("Callback returned HTTP {}", response.status()),
+                response.status().is_server_error(),
+            ),
+            Err(e) => (anyhow!("Failed to call callback: {}", e), true),
+        };
+        if !retryable || attempt >= retries {
+            return Err(error.context(format!("Callback failed after {} attempts", attempt + 1)));
+        }
+        let delay = base_delay * 2u32.saturating_pow(attempt);
+        warn!(
+            "Callback failed, retrying in {:?} ({}/{}): {:#}",
+            delay,
+            attempt + 1,
+            retries,
+            error
+        );
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{http::StatusCode, routing::post, Json, Router};
+    use chrono::Utc;
+    use std::sync::{Arc, Mutex};
+
+    /// Accepts posted results, failing the first `failures` calls with `status`.
+    async fn serve_callback(
+        failures: usize,
+        status: StatusCode,
+    ) -> (String, Arc<Mutex<Vec<serde_json::Value>>>) {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let calls = Arc::clone(&received);
+        let app = Router::new().route(
+            "/runs",
+            post(move |Json(body): Json<serde_json::Value>| {
+                let mut calls = calls.lock().unwrap();
+                calls.push(body);
+                let status = if calls.len() <= failures {
+                    status
+                } else {
+                    StatusCode::OK
+                };
+                async move { status }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (format!("http://{}/runs", address), received)
+    }
+
+    #[tokio::test]
+    async fn test_post_with_retries() {
+        let started_at = Utc::now();
+        let run = RunRecord::new(
+            started_at,
+            started_at + chrono::Duration::seconds(3),
+            1500,
+            &Err(anyhow!("boom")),
+        );
+        let result = RunResult {
+            schema_version: RUN_RESULT_SCHEMA_VERSION,
+            task: "purge_logs",
+            batches: 2,
+            run: &run,
+        };
+
+        // (failures, status, retries, expected success, expected calls)
+        let test_cases = vec![
+            (0, StatusCode::OK, 3, true, 1),
+            (2, StatusCode::BAD_GATEWAY, 3, true, 3),
+            (3, StatusCode::SERVICE_UNAVAILABLE, 2, false, 3),
+            // Not retried, another attempt would be rejected the same way
+            (1, StatusCode::BAD_REQUEST, 3, false, 1),
+        ];
+        for (failures, status, retries, success, expected_calls) in test_cases {
+            let (url, received) = serve_callback(failures, status).await;
+            let sent = post_with_retries(
+                &reqwest::Client::new(),
+                &url,
+                &result,
+                retries,
+                Duration::ZERO,
+            )
+            .await;
+            assert_eq!(sent.is_ok(), success, "{} {}", failures, status);
+            let received = received.lock().unwrap();
+            assert_eq!(received.len(), expected_calls, "{} {}", failures, status);
+            let payload = &received[0];
+            assert_eq!(payload["schema_version"], 1);
+            assert_eq!(payload["task"], "purge_logs");
+            assert_eq!(payload["status"], "failure");
+            assert_eq!(payload["rows_deleted"], 1500);
+            assert_eq!(payload["duration_seconds"], 3.0);
+            assert_eq!(payload["batches"], 2);
+            assert_eq!(payload["error"], "boom");
+            assert!(payload["started_at"].is_string());
+            assert!(payload["finished_at"].is_string());
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use chrono::{DateTime, Duration, Utc};
+use std::{collections::HashMap, sync::Mutex};
+
+use crate::cleaner::config::CircuitBreakerConfig;
+
+/// State of a task's circuit breaker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    /// Runs normally
+    Closed,
+    /// Skipped until the cooldown ends
+    Open { until: DateTime<Utc> },
+    /// The cooldown ended and a single trial run is in progress
+    HalfOpen,
+}
+
+/// A state change worth notifying about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerTransition {
+    /// The task failed too often and is paused for `cooldown`
+    Opened { cooldown: Duration },
+    /// A run succeeded after the breaker had opened
+    Recovered,
+}
+
+#[derive(Debug)]
+struct TaskBreaker {
+    consecutive_failures: u32,
+    /// How many times the breaker opened since the last success, used for the backoff
+    trips: u32,
+    state: BreakerState,
+}
+
+impl Default for TaskBreaker {
+    fn default() -> Self {
+        Self {
+            consecutive_failures: 0,
+            trips: 0,
+            state: BreakerState::Closed,
+        }
+    }
+}
+
+/// Per-task, in-memory circuit breakers. After `failure_threshold` consecutive failures a task is
+/// skipped for a cooldown that doubles every time the breaker re-opens, then allowed one trial run.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    tasks: Mutex<HashMap<String, TaskBreaker>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config,
+            tasks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn state(&self, task_name: &str) -> BreakerState {
+        self.tasks
+            .lock()
+            .unwrap()
+            .get(task_name)
+            .map_or(BreakerState::Closed, |breaker| breaker.state)
+    }
+
+    /// Whether the task may run at `now`. An open breaker whose cooldown has passed becomes
+    /// half-open and lets this run through as the trial.
+    pub fn allow_run(&self, task_name: &str, now: DateTime<Utc>) -> bool {
+        if !self.config.enabled {
+            return true;
+        }
+        let mut tasks = self.tasks.lock().unwrap();
+        let Some(breaker) = tasks.get_mut(task_name) else {
+            return true;
+        };
+        match breaker.state {
+            BreakerState::Closed | BreakerState::HalfOpen => true,
+            BreakerState::Open { until } if now >= until => {
+                breaker.state = BreakerState::HalfOpen;
+                true
+            }
+            BreakerState::Open { .. } => false,
+        }
+    }
+
+    pub fn record_success(&self, task_name: &str) -> Option<BreakerTransition> {
+        if !self.config.enabled {
+            return None;
+        }
+        let breaker = self.tasks.lock().unwrap().remove(task_name)?;
+        (breaker.trips > 0).then_some(BreakerTransition::Recovered)
+    }
+
+    pub fn record_failure(&self, task_name: &str, now: DateTime<Utc>) -> Option<BreakerTransition> {
+        if !self.config.enabled {
+            return None;
+        }
+        let mut tasks = self.tasks.lock().unwrap();
+        let breaker = tasks.entry(task_name.to_string()).or_default();
+        breaker.consecutive_failures += 1;
+
+        let trial_failed = breaker.state == BreakerState::HalfOpen;
+        if !trial_failed && breaker.consecutive_failures < self.config.failure_threshold {
+            return None;
+        }
+        breaker.trips += 1;
+        let cooldown = self.cooldown(breaker.trips);
+        breaker.state = BreakerState::Open {
+            until: now + cooldown,
+        };
+        Some(BreakerTransition::Opened { cooldown })
+    }
+
+    /// `cooldown_seconds` doubled for every trip after the first, capped at `max_cooldown_seconds`.
+    fn cooldown(&self, trips: u32) -> Duration {
+        let factor = 2f64.powi(trips.saturating_sub(1).min(30) as i32);
+        let seconds = (self.config.cooldown_seconds * factor).min(self.config.max_cooldown_seconds);
+        Duration::milliseconds((seconds * 1000.0) as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker() -> CircuitBreaker {
+        CircuitBreaker::new(CircuitBreakerConfig {
+            enabled: true,
+            failure_threshold: 3,
+            cooldown_seconds: 60.0,
+            max_cooldown_seconds: 200.0,
+        })
+    }
+
+    #[test]
+    fn test_circuit_breaker_opens_after_threshold() {
+        let breaker = breaker();
+        let now = Utc::now();
+
+        assert_eq!(breaker.record_failure("task", now), None);
+        assert_eq!(breaker.record_failure("task", now), None);
+        assert_eq!(breaker.state("task"), BreakerState::Closed);
+        assert_eq!(
+            breaker.record_failure("task", now),
+            Some(BreakerTransition::Opened {
+                cooldown: Duration::seconds(60)
+            })
+        );
+        assert!(!breaker.allow_run("task", now + Duration::seconds(59)));
+        assert!(breaker.allow_run("other_task", now));
+    }
+
+    #[test]
+    fn test_circuit_breaker_half_open_trial() {
+        let breaker = breaker();
+        let now = Utc::now();
+        for _ in 0..3 {
+            breaker.record_failure("task", now);
+        }
+
+        // A failed trial re-opens with a doubled cooldown
+        let now = now + Duration::seconds(60);
+        assert!(breaker.allow_run("task", now));
+        assert_eq!(breaker.state("task"), BreakerState::HalfOpen);
+        assert_eq!(
+            breaker.record_failure("task", now),
+            Some(BreakerTransition::Opened {
+                cooldown: Duration::seconds(120)
+            })
+        );
+
+        // The cooldown is capped at max_cooldown_seconds
+        let now = now + Duration::seconds(120);
+        assert!(breaker.allow_run("task", now));
+        assert_eq!(
+            breaker.record_failure("task", now),
+            Some(BreakerTransition::Opened {
+                cooldown: Duration::seconds(200)
+            })
+        );
+
+        // A successful trial closes the breaker and resets the backoff
+        let now = now + Duration::seconds(200);
+        assert!(breaker.allow_run("task", now));
+        assert_eq!(
+            breaker.record_success("task"),
+            Some(BreakerTransition::Recovered)
+        );
+        assert_eq!(breaker.state("task"), BreakerState::Closed);
+        assert_eq!(breaker.record_failure("task", now), None);
+    }
+
+    #[test]
+    fn test_circuit_breaker_success_resets_failures() {
+        let breaker = breaker();
+        let now = Utc::now();
+        breaker.record_failure("task", now);
+        breaker.record_failure("task", now);
+        assert_eq!(breaker.record_success("task"), None);
+        assert_eq!(breaker.record_failure("task", now), None);
+        assert_eq!(breaker.record_failure("task", now), None);
+        assert_eq!(breaker.state("task"), BreakerState::Closed);
+    }
+
+    #[test]
+    fn test_circuit_breaker_disabled() {
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig::default());
+        let now = Utc::now();
+        for _ in 0..10 {
+            assert_eq!(breaker.record_failure("task", now), None);
+        }
+        assert!(breaker.allow_run("task", now));
+    }
+}
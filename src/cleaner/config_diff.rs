@@ -0,0 +1,236 @@
+use serde_json::Value;
+use std::{collections::BTreeMap, fmt::Write};
+
+use crate::cleaner::config::FullConfig;
+
+/// A setting that differs between two configs, `None` when it is absent on that side.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldChange {
+    /// Dotted path of the setting, e.g. `safe_mode.retention_days`
+    pub field: String,
+    pub old: Option<Value>,
+    pub new: Option<Value>,
+}
+
+/// Semantic differences between two loaded configs. Tasks are matched by name and compared
+/// after loading, so normalizations such as 5-field cron schedules are taken into account.
+#[derive(Debug, Default)]
+pub struct ConfigDiff {
+    pub added_tasks: Vec<String>,
+    pub removed_tasks: Vec<String>,
+    pub changed_tasks: Vec<(String, Vec<FieldChange>)>,
+    pub config_changes: Vec<FieldChange>,
+}
+
+impl ConfigDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added_tasks.is_empty()
+            && self.removed_tasks.is_empty()
+            && self.changed_tasks.is_empty()
+            && self.config_changes.is_empty()
+    }
+}
+
+/// Compares two configs. Secrets are redacted on both sides, so a changed password or bot token
+/// does not show up.
+pub fn diff_configs(old: &FullConfig, new: &FullConfig) -> ConfigDiff {
+    let old = serde_json::to_value(old.redacted()).unwrap_or_default();
+    let new = serde_json::to_value(new.redacted()).unwrap_or_default();
+
+    let mut diff = ConfigDiff::default();
+    diff_values("", &old["config"], &new["config"], &mut diff.config_changes);
+
+    let old_tasks = tasks_by_name(&old);
+    let new_tasks = tasks_by_name(&new);
+    for (name, new_task) in &new_tasks {
+        match old_tasks.get(name) {
+            None => diff.added_tasks.push(name.clone()),
+            Some(old_task) => {
+                let mut changes = Vec::new();
+                diff_values("", old_task, new_task, &mut changes);
+                if !changes.is_empty() {
+                    diff.changed_tasks.push((name.clone(), changes));
+                }
+            }
+        }
+    }
+    diff.removed_tasks = old_tasks
+        .keys()
+        .filter(|name| !new_tasks.contains_key(*name))
+        .cloned()
+        .collect();
+    diff
+}
+
+fn tasks_by_name(config: &Value) -> BTreeMap<String, &Value> {
+    config["cleanup_tasks"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|task| Some((task["name"].as_str()?.to_string(), task)))
+        .collect()
+}
+
+/// Records the leaf settings that differ between `old` and `new`, descending into objects.
+fn diff_values(path: &str, old: &Value, new: &Value, changes: &mut Vec<FieldChange>) {
+    if let (Value::Object(old_map), Value::Object(new_map)) = (old, new) {
+        let mut keys: Vec<_> = old_map.keys().chain(new_map.keys()).collect();
+        keys.sort();
+        keys.dedup();
+        for key in keys {
+            let field = if path.is_empty() {
+                key.clone()
+            } else {
+                format!("{}.{}", path, key)
+            };
+            match (old_map.get(key), new_map.get(key)) {
+                (Some(old), Some(new)) => diff_values(&field, old, new, changes),
+                (old, new) => changes.push(FieldChange {
+                    field,
+                    old: old.cloned(),
+                    new: new.cloned(),
+                }),
+            }
+        }
+    } else if old != new {
+        changes.push(FieldChange {
+            field: path.to_string(),
+            old: Some(old.clone()),
+            new: Some(new.clone()),
+        });
+    }
+}
+
+/// Renders the diff for `--diff`.
+pub fn render_diff(diff: &ConfigDiff) -> String {
+    if diff.is_empty() {
+        return "No differences".to_string();
+    }
+    let mut output = String::new();
+    for name in &diff.added_tasks {
+        let _ = writeln!(output, "+ task `{}`", name);
+    }
+    for name in &diff.removed_tasks {
+        let _ = writeln!(output, "- task `{}`", name);
+    }
+    for (name, changes) in &diff.changed_tasks {
+        let _ = writeln!(output, "~ task `{}`", name);
+        render_changes(&mut output, changes);
+    }
+    if !diff.config_changes.is_empty() {
+        let _ = writeln!(output, "~ config");
+        render_changes(&mut output, &diff.config_changes);
+    }
+    output.trim_end().to_string()
+}
+
+fn render_changes(output: &mut String, changes: &[FieldChange]) {
+    let show = |value: &Option<Value>| {
+        value
+            .as_ref()
+            .map_or("(unset)".to_string(), |value| value.to_string())
+    };
+    for change in changes {
+        let _ = writeln!(
+            output,
+            "    {}: {} -> {}",
+            change.field,
+            show(&change.old),
+            show(&change.new)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(extra_config: &str, tasks: &str) -> FullConfig {
+        FullConfig::load_from_str(&format!(
+            r#"
+config:
+  database_config:
+    host: localhost
+    username: root
+    password: secret
+    database: my_db
+  slack_config:
+    bot_token: xoxb-test
+    channel_id: C0123
+    enabled: false
+  safe_mode:
+    enabled: true
+    retention_days: 30
+{}
+cleanup_tasks:
+{}"#,
+            extra_config, tasks
+        ))
+        .unwrap()
+    }
+
+    fn task(name: &str, cron_schedule: &str, batch_size: u32) -> String {
+        format!(
+            r#"
+  - name: "{}"
+    description: "test task"
+    cron_schedule: "{}"
+    enabled: true
+    template_query: "DELETE FROM t WHERE created_at < DATE_SUB(NOW(), INTERVAL 30 DAY)"
+    parameters: {{}}
+    batch_size: {}
+    retry_attempts: 3
+    retry_delay_seconds: 5
+"#,
+            name, cron_schedule, batch_size
+        )
+    }
+
+    #[test]
+    fn test_diff_configs() {
+        let old = config(
+            "",
+            &[
+                task("kept", "0 0 * * *", 1000),
+                task("changed", "0 0 * * *", 1000),
+                task("removed", "0 0 * * *", 1000),
+            ]
+            .concat(),
+        );
+        let new = config(
+            "  slow_query_threshold_seconds: 5",
+            &[
+                // Normalized to the same 6-field schedule as the old 5-field one
+                task("kept", "0 0 0 * * *", 1000),
+                task("changed", "30 0 * * *", 500),
+                task("added", "0 0 * * *", 1000),
+            ]
+            .concat(),
+        );
+
+        let diff = diff_configs(&old, &new);
+        assert_eq!(diff.added_tasks, vec!["added"]);
+        assert_eq!(diff.removed_tasks, vec!["removed"]);
+        assert_eq!(diff.changed_tasks.len(), 1);
+        let (name, changes) = &diff.changed_tasks[0];
+        assert_eq!(name, "changed");
+        let fields: Vec<_> = changes.iter().map(|c| c.field.as_str()).collect();
+        assert_eq!(fields, vec!["batch_size", "cron_schedule"]);
+        assert_eq!(
+            diff.config_changes,
+            vec![FieldChange {
+                field: "slow_query_threshold_seconds".to_string(),
+                old: Some(Value::Null),
+                new: Some(5.0.into()),
+            }]
+        );
+
+        let rendered = render_diff(&diff);
+        assert!(rendered.contains("+ task `added`"));
+        assert!(rendered.contains("- task `removed`"));
+        assert!(rendered.contains("    cron_schedule: \"0 0 0 * * *\" -> \"0 30 0 * * *\""));
+        assert!(!rendered.contains("kept"));
+
+        assert_eq!(render_diff(&diff_configs(&old, &old)), "No differences");
+    }
+}
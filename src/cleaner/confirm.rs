@@ -0,0 +1,214 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use log::info;
+use std::io::{BufRead, IsTerminal, Write};
+
+use crate::cleaner::{
+    config::{CleanupTask, Config},
+    db::Database,
+    dry_run,
+    task::{render_task_query, task_target},
+};
+
+/// What a manual `--run-task` is about to do, shown before asking for confirmation.
+#[derive(Debug)]
+pub struct RunPlan {
+    pub task: String,
+    pub target: String,
+    pub as_of: DateTime<Utc>,
+    /// The rendered DELETE with secrets redacted, or why it could not be rendered
+    pub sql: Result<String, String>,
+    /// The dry-run count of the rows the DELETE would remove, or why it could not be counted
+    pub estimated_rows: Result<i64, String>,
+    /// What has to be typed to confirm the run
+    pub confirmation: String,
+}
+
+/// The word a manual run has to be confirmed with: the task's table, or its name if it has none.
+pub fn confirmation_word(task: &CleanupTask) -> String {
+    task.parameters
+        .get("table_name")
+        .or(task.tables.first())
+        .unwrap_or(&task.name)
+        .clone()
+}
+
+/// Renders the task's query as of `as_of` and counts the rows it would delete. Failures are kept
+/// in the plan instead of returned, the run itself reports them.
+pub async fn build_plan(config: &Config, task: &CleanupTask, as_of: DateTime<Utc>) -> RunPlan {
+    let sql = match &task.partition_drop {
+        Some(partition_drop) => Err(format!(
+            "drops the partitions older than {} days",
+            partition_drop.retention_days
+        )),
+        None => render_task_query(config, task, as_of).map_err(|e| format!("{:#}", e)),
+    };
+    let estimated_rows = match &sql {
+        Ok(sql) => async {
+            let count_sql = dry_run::count_query(sql, config.database_config.dialect)?;
+            let db = Database::new_for_task(&config.database_config, &task.name).await?;
+            db.fetch_scalar::<i64>(&count_sql).await
+        }
+        .await
+        .map_err(|e| config.redact_secrets(&format!("{:#}", e))),
+        Err(_) => Err("no DELETE to count".to_string()),
+    };
+    RunPlan {
+        task: task.name.clone(),
+        target: task_target(config, task),
+        as_of,
+        sql: sql.map(|sql| config.redact_secrets(&sql)),
+        estimated_rows,
+        confirmation: confirmation_word(task),
+    }
+}
+
+/// Formats the plan for the terminal.
+pub fn render_plan(plan: &RunPlan) -> String {
+    let sql = match &plan.sql {
+        Ok(sql) => sql.trim().to_string(),
+        Err(reason) => format!("({})", reason),
+    };
+    let estimated_rows = match &plan.estimated_rows {
+        Ok(rows) => rows.to_string(),
+        Err(reason) => format!("unknown ({})", reason),
+    };
+    format!(
+        "Task:           {}\nTarget:         {}\nAs of:          {}\nEstimated rows: {}\nSQL:\n{}\n",
+        plan.task,
+        plan.target,
+        plan.as_of.to_rfc3339(),
+        estimated_rows,
+        sql
+    )
+}
+
+/// Whether a run of the task deletes nothing, so there is nothing to confirm: the rolled back
+/// trial of `trial_rollback_first` without `trial_then_commit`, or the dry-run count of a
+/// `require_first_run_ack` task that is not acknowledged yet.
+pub fn is_dry_run(config: &Config, task: &CleanupTask) -> bool {
+    let trial_only = task.trial_rollback_first && !task.trial_then_commit;
+    let unacknowledged = task.require_first_run_ack
+        && task.partition_drop.is_none()
+        && !config.acked_tasks.contains(&task.name);
+    trial_only || unacknowledged
+}
+
+/// Whether the typed answer confirms the run, ignoring surrounding whitespace.
+pub fn is_confirmed(answer: &str, confirmation: &str) -> bool {
+    answer.trim() == confirmation
+}
+
+/// Shows the plan of a manual run and asks for its table name on stdin, failing unless it is
+/// typed exactly. Without a terminal there is nobody to ask, so the run is refused and `--yes`
+/// has to be passed instead. A dry run, see [`is_dry_run`], is not asked about.
+pub async fn confirm_run(config: &Config, task: &CleanupTask, as_of: DateTime<Utc>) -> Result<()> {
+    if is_dry_run(config, task) {
+        info!(
+            "Not asking to confirm the run of task `{}` as it deletes nothing",
+            task.name
+        );
+        return Ok(());
+    }
+    if !std::io::stdin().is_terminal() {
+        return Err(anyhow!(
+            "Refusing to run task `{}` without confirmation as stdin is not a terminal, pass \
+             --yes to run it non-interactively",
+            task.name
+        ));
+    }
+    let plan = build_plan(config, task, as_of).await;
+    let prompt = format!(
+        "{}\nType `{}` to run this task: ",
+        render_plan(&plan),
+        plan.confirmation
+    );
+    // Reading stdin blocks, so it must not hold up a runtime worker thread
+    let answer = tokio::task::spawn_blocking(move || -> Result<String> {
+        let mut stderr = std::io::stderr();
+        write!(stderr, "{}", prompt)?;
+        stderr.flush()?;
+        let mut answer = String::new();
+        std::io::stdin().lock().read_line(&mut answer)?;
+        Ok(answer)
+    })
+    .await??;
+    if !is_confirmed(&answer, &plan.confirmation) {
+        return Err(anyhow!(
+            "Run of task `{}` not confirmed, nothing was deleted",
+            task.name
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_plan() {
+        let mut task: CleanupTask = serde_yaml::from_str(
+            r#"
+name: purge_events
+description: test task
+cron_schedule: "0 0 * * * *"
+enabled: true
+template_query: "DELETE FROM events LIMIT 1000"
+parameters: {table_name: events}
+batch_size: 1000
+retry_attempts: 3
+retry_delay_seconds: 0
+"#,
+        )
+        .unwrap();
+        assert_eq!(confirmation_word(&task), "events");
+        task.parameters.clear();
+        task.tables = vec!["logs_2024".to_string()];
+        assert_eq!(confirmation_word(&task), "logs_2024");
+        task.tables.clear();
+        assert_eq!(confirmation_word(&task), "purge_events");
+
+        let plan = RunPlan {
+            task: "purge_events".to_string(),
+            target: "db.events".to_string(),
+            as_of: DateTime::parse_from_rfc3339("2024-03-20T00:00:00Z")
+                .unwrap()
+                .with_timezone(&Utc),
+            sql: Ok("DELETE FROM events LIMIT 1000\n".to_string()),
+            estimated_rows: Err("connection refused".to_string()),
+            confirmation: "events".to_string(),
+        };
+        assert_eq!(
+            render_plan(&plan),
+            "Task:           purge_events\nTarget:         db.events\nAs of:          \
+             2024-03-20T00:00:00+00:00\nEstimated rows: unknown (connection refused)\nSQL:\n\
+             DELETE FROM events LIMIT 1000\n"
+        );
+
+        // (answer, confirmed)
+        let test_cases = vec![
+            ("events\n", true),
+            ("  events  \r\n", true),
+            ("Events\n", false),
+            ("event\n", false),
+            ("y\n", false),
+            ("", false),
+        ];
+        for (answer, confirmed) in test_cases {
+            assert_eq!(is_confirmed(answer, "events"), confirmed, "{:?}", answer);
+        }
+
+        // Runs that delete nothing are not confirmed
+        let mut config = Config::default();
+        assert!(!is_dry_run(&config, &task));
+        task.trial_rollback_first = true;
+        assert!(is_dry_run(&config, &task));
+        task.trial_then_commit = true;
+        assert!(!is_dry_run(&config, &task));
+        task.require_first_run_ack = true;
+        assert!(is_dry_run(&config, &task));
+        config.acked_tasks.push("purge_events".to_string());
+        assert!(!is_dry_run(&config, &task));
+    }
+}
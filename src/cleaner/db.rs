@@ -1,49 +1,799 @@
-use super::config::DatabaseConfig;
+use super::{config::DatabaseConfig, partition::PartitionInfo, ssh_tunnel::SshTunnel};
 use anyhow::{anyhow, Result};
-use log::debug;
-use sqlx::mysql::{MySqlPool, MySqlPoolOptions};
+use log::{debug, trace, warn};
+use sqlx::{
+    mysql::{MySqlConnectOptions, MySqlPool, MySqlPoolOptions, MySqlQueryResult, MySqlRow},
+    pool::PoolConnection,
+    Column, MySql, Row,
+};
+use std::{future::Future, pin::Pin, sync::Arc, time::Duration};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// Executes cleanup queries, returning the affected rows and the elapsed seconds.
+pub trait QueryExecutor {
+    fn execute_query(&self, query: &str) -> impl Future<Output = Result<(u64, f64)>> + Send;
+
+    /// Like [`QueryExecutor::execute_query`], also returning the statement's execution time on
+    /// the server in seconds if the server reports it.
+    fn execute_query_with_server_time(
+        &self,
+        query: &str,
+    ) -> impl Future<Output = Result<(u64, f64, Option<f64>)>> + Send {
+        let result = self.execute_query(query);
+        async move {
+            let (affected_rows, elapsed_in_secs) = result.await?;
+            Ok((affected_rows, elapsed_in_secs, None))
+        }
+    }
+
+    /// Runs a statement in a transaction that is always rolled back, returning the rows it would
+    /// have affected and the elapsed seconds.
+    fn trial_execute_query(&self, _query: &str) -> impl Future<Output = Result<(u64, f64)>> + Send {
+        async { Err(anyhow!("Trial runs are not supported by this executor")) }
+    }
+
+    /// Returns the query plan for a query, as JSON.
+    fn explain(&self, _query: &str) -> impl Future<Output = Result<String>> + Send {
+        async { Err(anyhow!("EXPLAIN is not supported by this executor")) }
+    }
+
+    /// Returns the columns that lead an index on `schema.table`.
+    fn indexed_columns(
+        &self,
+        _schema: &str,
+        _table: &str,
+    ) -> impl Future<Output = Result<Vec<String>>> + Send {
+        async { Err(anyhow!("Index lookup is not supported by this executor")) }
+    }
+
+    /// Returns the storage engine's estimate of the row count of `schema.table`.
+    fn estimated_rows(
+        &self,
+        _schema: &str,
+        _table: &str,
+    ) -> impl Future<Output = Result<u64>> + Send {
+        async { Err(anyhow!("Row estimates are not supported by this executor")) }
+    }
+
+    /// Runs a query returning a single count, such as `SELECT COUNT(*) ...`.
+    fn fetch_count(&self, _query: &str) -> impl Future<Output = Result<u64>> + Send {
+        async { Err(anyhow!("Count queries are not supported by this executor")) }
+    }
+
+    /// Returns the current connection counts of the executor's pool, if it has one.
+    fn pool_status(&self) -> Option<PoolStatus> {
+        None
+    }
+
+    /// Returns the partitions of `schema.table` in order, empty if it is not partitioned.
+    fn partitions(
+        &self,
+        _schema: &str,
+        _table: &str,
+    ) -> impl Future<Output = Result<Vec<PartitionInfo>>> + Send {
+        async {
+            Err(anyhow!(
+                "Partition lookup is not supported by this executor"
+            ))
+        }
+    }
+}
+
+/// The execution time of the connection's last completed statement, in picoseconds. Needs
+/// MySQL 8.0.16 or later with the statement history consumer of performance_schema enabled.
+const SERVER_TIME_QUERY: &str =
+    "SELECT TIMER_WAIT FROM performance_schema.events_statements_history \
+     WHERE THREAD_ID = PS_CURRENT_THREAD_ID() ORDER BY EVENT_ID DESC LIMIT 1";
 
 pub struct Database {
     pool: MySqlPool,
+    /// Prefixed to every query as a comment, see [`connection_label`]
+    label: String,
+    /// Kept open for as long as the pool, closed on drop
+    _ssh_tunnel: Option<SshTunnel>,
+    permits: QueryPermits,
+    /// Statements running longer are killed on the server, see [`run_with_query_timeout`]
+    query_timeout: Option<Duration>,
+}
+
+/// A snapshot of a connection pool's connections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolStatus {
+    /// Open connections, idle or in use
+    pub size: u32,
+    pub idle: usize,
+}
+
+impl PoolStatus {
+    pub fn in_use(&self) -> usize {
+        (self.size as usize).saturating_sub(self.idle)
+    }
+}
+
+/// Bounds how many queries run at the same time through one [`Database`], independent of the
+/// pool size, so a single task cannot take every connection.
+#[derive(Debug, Clone, Default)]
+pub struct QueryPermits(Option<Arc<Semaphore>>);
+
+impl QueryPermits {
+    /// Allows `max_concurrent` queries at a time, or any number when `None`.
+    pub fn new(max_concurrent: Option<u32>) -> Self {
+        Self(max_concurrent.map(|max| Arc::new(Semaphore::new(max as usize))))
+    }
+
+    /// Waits for a free slot, held until the returned permit is dropped.
+    pub async fn acquire(&self) -> Option<SemaphorePermit<'_>> {
+        // The semaphore is never closed, so acquiring only fails without a limit
+        self.0.as_ref()?.acquire().await.ok()
+    }
 }
 
 impl Database {
     pub async fn new(config: &DatabaseConfig) -> Result<Self, anyhow::Error> {
+        Self::connect(config, None).await
+    }
+
+    /// Connects with the task name in the connection label, so DBAs can attribute load to it.
+    pub async fn new_for_task(config: &DatabaseConfig, task_name: &str) -> Result<Self> {
+        Self::connect(config, Some(task_name)).await
+    }
+
+    /// Connects, retrying failed attempts up to `connect_retries` times so a database that is not
+    /// reachable yet, e.g. a container still starting, does not fail the run right away.
+    async fn connect(config: &DatabaseConfig, task_name: Option<&str>) -> Result<Self> {
         // Validate required configuration values
         if config.password.is_empty() {
             return Err(anyhow!("Database password is required but not provided"));
         }
 
-        // Log database connection details (excluding sensitive info)
-        debug!(
-            "Connecting to database: host={}, port={}, user={}, database={}",
-            config.host, config.port, config.username, config.database
-        );
+        with_connect_retries(
+            config.connect_retries,
+            Duration::from_secs_f64(config.connect_retry_delay_seconds),
+            || Self::connect_once(config, task_name),
+        )
+        .await
+    }
 
-        let connection_string = format!(
-            "mysql://{}:{}@{}:{}/{}",
-            config.username, config.password, config.host, config.port, config.database
-        );
+    async fn connect_once(config: &DatabaseConfig, task_name: Option<&str>) -> Result<Self> {
+        let options = MySqlConnectOptions::new()
+            .username(&config.username)
+            .password(&config.password)
+            .database(&config.database);
 
-        let pool = MySqlPoolOptions::new()
-            .max_connections(5)
-            .connect(&connection_string)
-            .await;
+        // Log database connection details (excluding sensitive info)
+        let mut ssh_tunnel = None;
+        let options = match (&config.socket, &config.ssh_tunnel) {
+            (Some(socket), _) => {
+                debug!(
+                    "Connecting to database over unix socket: socket={}, user={}, database={}",
+                    socket, config.username, config.database
+                );
+                options.socket(socket)
+            }
+            (None, Some(ssh_tunnel_config)) => {
+                let tunnel = SshTunnel::open(ssh_tunnel_config, &config.host, config.port).await?;
+                debug!(
+                    "Connecting to database over SSH tunnel: host={}, port={}, user={}, database={}",
+                    config.host, config.port, config.username, config.database
+                );
+                let options = options.host("127.0.0.1").port(tunnel.local_port());
+                ssh_tunnel = Some(tunnel);
+                options
+            }
+            (None, None) => {
+                debug!(
+                    "Connecting to database over TCP: host={}, port={}, user={}, database={}",
+                    config.host, config.port, config.username, config.database
+                );
+                options.host(&config.host).port(config.port)
+            }
+        };
+
+        let label = connection_label(config, task_name);
+        let mut pool_options = MySqlPoolOptions::new().max_connections(5);
+        if !label.is_empty() {
+            // Also visible per connection in performance_schema.user_variables_by_thread
+            let session_label = label.clone();
+            pool_options = pool_options.after_connect(move |connection, _| {
+                let session_label = session_label.clone();
+                Box::pin(async move {
+                    sqlx::query("SET @kiyoshi_connection_label = ?")
+                        .bind(session_label)
+                        .execute(connection)
+                        .await?;
+                    Ok(())
+                })
+            });
+        }
+        let pool = pool_options.connect_with(options).await;
 
         match pool {
-            Ok(pool) => Ok(Self { pool }),
+            Ok(pool) => Ok(Self {
+                pool,
+                label,
+                _ssh_tunnel: ssh_tunnel,
+                permits: QueryPermits::default(),
+                query_timeout: None,
+            }),
             Err(e) => Err(anyhow!("Failed to connect to database: {}", e)),
         }
     }
 
+    /// Limits this connection's concurrent queries to `max_connections`, see [`QueryPermits`].
+    pub fn with_max_connections(mut self, max_connections: Option<u32>) -> Self {
+        self.permits = QueryPermits::new(max_connections);
+        self
+    }
+
+    /// Kills statements that run longer than `query_timeout` on the server.
+    pub fn with_query_timeout(mut self, query_timeout: Option<Duration>) -> Self {
+        self.query_timeout = query_timeout;
+        self
+    }
+
+    /// The connections of the pool right now, to diagnose queries waiting for a connection.
+    pub fn pool_status(&self) -> PoolStatus {
+        PoolStatus {
+            size: self.pool.size(),
+            idle: self.pool.num_idle(),
+        }
+    }
+
+    /// Runs a statement outside of any transaction. The pool connection is borrowed for this
+    /// statement only, so nothing is held between batches or during `query_interval_seconds`.
     pub async fn execute_query(&self, query: &str) -> Result<(u64, f64)> {
+        let _permit = self.permits.acquire().await;
+        let status = self.pool_status();
+        trace!(
+            "Acquiring a connection ({} open, {} idle) for statement:\n{}",
+            status.size,
+            status.idle,
+            query
+        );
         let start = std::time::Instant::now();
-        let result = sqlx::query(query).execute(&self.pool).await;
-        let elapsed = start.elapsed().as_secs_f64();
+        let query = label_query(&self.label, query);
+        let result = match self.query_timeout {
+            Some(query_timeout) => {
+                let connection = self
+                    .pool
+                    .acquire()
+                    .await
+                    .map_err(|e| anyhow!("Database query failed: {:?}", e))?;
+                self.execute_with_timeout(connection, &query, query_timeout)
+                    .await
+                    .map(|(result, _)| result)?
+            }
+            None => sqlx::query(&query)
+                .execute(&self.pool)
+                .await
+                .map_err(|e| anyhow!("Database query failed: {:?}", e))?,
+        };
+        Ok((result.rows_affected(), start.elapsed().as_secs_f64()))
+    }
 
+    /// Runs a statement on `connection` within `query_timeout`, see [`run_with_query_timeout`].
+    /// The connection is handed back for further statements unless the statement was killed.
+    async fn execute_with_timeout(
+        &self,
+        mut connection: PoolConnection<MySql>,
+        query: &str,
+        query_timeout: Duration,
+    ) -> Result<(MySqlQueryResult, PoolConnection<MySql>)> {
+        let connection_id = sqlx::query_scalar::<_, u64>("SELECT CONNECTION_ID()")
+            .fetch_one(&mut *connection)
+            .await
+            .map_err(|e| anyhow!("Database query failed: {:?}", e))?;
+        let result = run_with_query_timeout(
+            query_timeout,
+            sqlx::query(query).execute(&mut *connection),
+            kill_query(self.pool.clone(), connection_id),
+        )
+        .await;
         match result {
-            Ok(result) => Ok((result.rows_affected(), elapsed)),
-            Err(e) => Err(anyhow!("Database query failed: {:?}", e)),
+            Ok(result) => {
+                let result = result.map_err(|e| anyhow!("Database query failed: {:?}", e))?;
+                Ok((result, connection))
+            }
+            Err(e) => {
+                // The statement was interrupted mid-flight, so the connection is not reused
+                let _ = connection.close().await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Runs a statement in a transaction and rolls it back, see
+    /// [`QueryExecutor::trial_execute_query`]. Only transactional engines such as InnoDB undo the
+    /// statement, MyISAM tables keep the deleted rows deleted.
+    pub async fn trial_execute_query(&self, query: &str) -> Result<(u64, f64)> {
+        let _permit = self.permits.acquire().await;
+        let start = std::time::Instant::now();
+        let query = label_query(&self.label, query);
+        let mut transaction = self
+            .pool
+            .begin()
+            .await
+            .map_err(|e| anyhow!("Failed to begin transaction: {:?}", e))?;
+        let result = match self.query_timeout {
+            Some(query_timeout) => {
+                let connection_id = sqlx::query_scalar::<_, u64>("SELECT CONNECTION_ID()")
+                    .fetch_one(&mut *transaction)
+                    .await
+                    .map_err(|e| anyhow!("Database query failed: {:?}", e))?;
+                run_with_query_timeout(
+                    query_timeout,
+                    sqlx::query(&query).execute(&mut *transaction),
+                    kill_query(self.pool.clone(), connection_id),
+                )
+                .await?
+            }
+            None => sqlx::query(&query).execute(&mut *transaction).await,
+        }
+        .map_err(|e| anyhow!("Database query failed: {:?}", e));
+        // Dropping the transaction would roll it back too, but only once its connection is reused
+        transaction
+            .rollback()
+            .await
+            .map_err(|e| anyhow!("Failed to roll back transaction: {:?}", e))?;
+        Ok((result?.rows_affected(), start.elapsed().as_secs_f64()))
+    }
+
+    /// Runs a query returning a single row and returns its first column.
+    pub async fn fetch_scalar<T>(&self, query: &str) -> Result<T>
+    where
+        T: for<'r> sqlx::Decode<'r, MySql> + sqlx::Type<MySql> + Send + Unpin,
+    {
+        let _permit = self.permits.acquire().await;
+        let query = label_query(&self.label, query);
+        sqlx::query_scalar::<_, T>(&query)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|e| anyhow!("Database query failed: {:?}", e))
+    }
+}
+
+/// Column names and rows of a query, with every value converted to text.
+pub type TextRows = (Vec<String>, Vec<Vec<Option<String>>>);
+
+impl Database {
+    /// Runs a query and returns its rows as text, for showing them to people.
+    pub async fn fetch_text_rows(&self, query: &str) -> Result<TextRows> {
+        let _permit = self.permits.acquire().await;
+        let query = label_query(&self.label, query);
+        let rows = sqlx::query(&query)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| anyhow!("Database query failed: {:?}", e))?;
+        let columns = rows
+            .first()
+            .map(|row| {
+                row.columns()
+                    .iter()
+                    .map(|column| column.name().to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let rows = rows
+            .iter()
+            .map(|row| (0..row.len()).map(|i| text_value(row, i)).collect())
+            .collect();
+        Ok((columns, rows))
+    }
+}
+
+/// A column value as text, trying the types MySQL columns commonly decode to.
+fn text_value(row: &MySqlRow, index: usize) -> Option<String> {
+    fn get<T>(row: &MySqlRow, index: usize) -> Option<Option<String>>
+    where
+        T: for<'r> sqlx::Decode<'r, MySql> + sqlx::Type<MySql> + ToString,
+    {
+        row.try_get::<Option<T>, _>(index)
+            .ok()
+            .map(|value| value.map(|value| value.to_string()))
+    }
+    get::<String>(row, index)
+        .or_else(|| get::<i64>(row, index))
+        .or_else(|| get::<u64>(row, index))
+        .or_else(|| get::<f64>(row, index))
+        .or_else(|| get::<chrono::NaiveDateTime>(row, index))
+        .or_else(|| get::<chrono::NaiveDate>(row, index))
+        .or_else(|| get::<chrono::NaiveTime>(row, index))
+        .or_else(|| {
+            row.try_get::<Option<Vec<u8>>, _>(index)
+                .ok()
+                .map(|value| value.map(|bytes| format!("<{} bytes>", bytes.len())))
+        })
+        .unwrap_or_else(|| Some("<unsupported>".to_string()))
+}
+
+impl QueryExecutor for Database {
+    fn execute_query(&self, query: &str) -> impl Future<Output = Result<(u64, f64)>> + Send {
+        Database::execute_query(self, query)
+    }
+
+    fn trial_execute_query(&self, query: &str) -> impl Future<Output = Result<(u64, f64)>> + Send {
+        Database::trial_execute_query(self, query)
+    }
+
+    async fn fetch_count(&self, query: &str) -> Result<u64> {
+        let count = self.fetch_scalar::<i64>(query).await?;
+        Ok(count.max(0) as u64)
+    }
+
+    fn pool_status(&self) -> Option<PoolStatus> {
+        Some(Database::pool_status(self))
+    }
+
+    async fn execute_query_with_server_time(&self, query: &str) -> Result<(u64, f64, Option<f64>)> {
+        let _permit = self.permits.acquire().await;
+        let start = std::time::Instant::now();
+        let query = label_query(&self.label, query);
+        // The timing is read on the connection that ran the statement
+        let mut connection = self
+            .pool
+            .acquire()
+            .await
+            .map_err(|e| anyhow!("Database query failed: {:?}", e))?;
+        let result = match self.query_timeout {
+            Some(query_timeout) => {
+                let (result, returned) = self
+                    .execute_with_timeout(connection, &query, query_timeout)
+                    .await?;
+                connection = returned;
+                result
+            }
+            None => sqlx::query(&query)
+                .execute(&mut *connection)
+                .await
+                .map_err(|e| anyhow!("Database query failed: {:?}", e))?,
+        };
+        let elapsed = start.elapsed().as_secs_f64();
+
+        let server_time = match sqlx::query_scalar::<_, Option<u64>>(SERVER_TIME_QUERY)
+            .fetch_optional(&mut *connection)
+            .await
+        {
+            Ok(picoseconds) => picoseconds.flatten().map(|ps| ps as f64 / 1e12),
+            Err(e) => {
+                debug!(
+                    "Server time is not available, reporting wall time only: {}",
+                    e
+                );
+                None
+            }
+        };
+        Ok((result.rows_affected(), elapsed, server_time))
+    }
+
+    fn explain(&self, query: &str) -> impl Future<Output = Result<String>> + Send {
+        let explain_query = format!("EXPLAIN FORMAT=JSON {}", query);
+        async move { self.fetch_scalar::<String>(&explain_query).await }
+    }
+
+    fn indexed_columns(
+        &self,
+        schema: &str,
+        table: &str,
+    ) -> impl Future<Output = Result<Vec<String>>> + Send {
+        // Only the first column of an index can be used to seek on its own
+        let query = label_query(
+            &self.label,
+            "SELECT DISTINCT COLUMN_NAME FROM information_schema.statistics \
+             WHERE TABLE_SCHEMA = ? AND TABLE_NAME = ? AND SEQ_IN_INDEX = 1",
+        );
+        let schema = schema.to_string();
+        let table = table.to_string();
+        async move {
+            let _permit = self.permits.acquire().await;
+            sqlx::query_scalar::<_, String>(&query)
+                .bind(schema)
+                .bind(table)
+                .fetch_all(&self.pool)
+                .await
+                .map_err(|e| anyhow!("Database query failed: {:?}", e))
+        }
+    }
+
+    fn estimated_rows(
+        &self,
+        schema: &str,
+        table: &str,
+    ) -> impl Future<Output = Result<u64>> + Send {
+        // TABLE_ROWS is an estimate for InnoDB, but avoids scanning the table
+        let query = label_query(
+            &self.label,
+            "SELECT TABLE_ROWS FROM information_schema.tables \
+             WHERE TABLE_SCHEMA = ? AND TABLE_NAME = ?",
+        );
+        let schema = schema.to_string();
+        let table = table.to_string();
+        async move {
+            let _permit = self.permits.acquire().await;
+            sqlx::query_scalar::<_, Option<u64>>(&query)
+                .bind(&schema)
+                .bind(&table)
+                .fetch_optional(&self.pool)
+                .await
+                .map_err(|e| anyhow!("Database query failed: {:?}", e))?
+                .flatten()
+                .ok_or_else(|| anyhow!("No row estimate available for {}.{}", schema, table))
+        }
+    }
+
+    fn partitions(
+        &self,
+        schema: &str,
+        table: &str,
+    ) -> impl Future<Output = Result<Vec<PartitionInfo>>> + Send {
+        // Subpartitions repeat their partition's row, hence the GROUP BY
+        let query = label_query(
+            &self.label,
+            "SELECT PARTITION_NAME, PARTITION_METHOD, PARTITION_EXPRESSION, PARTITION_DESCRIPTION \
+             FROM information_schema.partitions \
+             WHERE TABLE_SCHEMA = ? AND TABLE_NAME = ? AND PARTITION_NAME IS NOT NULL \
+             GROUP BY PARTITION_NAME, PARTITION_METHOD, PARTITION_EXPRESSION, \
+             PARTITION_DESCRIPTION, PARTITION_ORDINAL_POSITION \
+             ORDER BY PARTITION_ORDINAL_POSITION",
+        );
+        let schema = schema.to_string();
+        let table = table.to_string();
+        async move {
+            let _permit = self.permits.acquire().await;
+            let rows = sqlx::query_as::<_, (String, String, String, Option<String>)>(&query)
+                .bind(schema)
+                .bind(table)
+                .fetch_all(&self.pool)
+                .await
+                .map_err(|e| anyhow!("Database query failed: {:?}", e))?;
+            Ok(rows
+                .into_iter()
+                .map(|(name, method, expression, description)| PartitionInfo {
+                    name,
+                    method,
+                    expression,
+                    description,
+                })
+                .collect())
+        }
+    }
+}
+
+/// Kills the statement running on connection `connection_id`, leaving the connection itself open.
+/// MySQL has no server-side time limit for DELETE statements, as `max_execution_time` only
+/// applies to SELECT.
+async fn kill_query(pool: MySqlPool, connection_id: u64) {
+    match sqlx::query(&format!("KILL QUERY {}", connection_id))
+        .execute(&pool)
+        .await
+    {
+        Ok(_) => warn!(
+            "Killed the statement running on connection {}",
+            connection_id
+        ),
+        Err(e) => warn!(
+            "Failed to kill the statement running on connection {}: {}",
+            connection_id, e
+        ),
+    }
+}
+
+/// Runs `kill` when dropped while armed.
+struct KillOnDrop(Option<Pin<Box<dyn Future<Output = ()> + Send>>>);
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        if let (Some(kill), Ok(runtime)) = (self.0.take(), tokio::runtime::Handle::try_current()) {
+            runtime.spawn(kill);
+        }
+    }
+}
+
+/// Runs `statement`, then `kill` if it takes longer than `query_timeout`. When the statement is
+/// cancelled before it completes, e.g. by `task_timeout_seconds`, `kill` runs in the background
+/// instead, so the server does not keep running a statement nobody waits for.
+async fn run_with_query_timeout<T>(
+    query_timeout: Duration,
+    statement: impl Future<Output = T>,
+    kill: impl Future<Output = ()> + Send + 'static,
+) -> Result<T> {
+    let mut kill_on_drop = KillOnDrop(Some(Box::pin(kill)));
+    let result = tokio::time::timeout(query_timeout, statement).await;
+    // Disarmed either way, a statement that timed out is killed right here
+    if let Some(kill) = kill_on_drop.0.take().filter(|_| result.is_err()) {
+        kill.await;
+    }
+    result.map_err(|_| {
+        anyhow!(
+            "Statement exceeded query_timeout_seconds of {} and was killed",
+            query_timeout.as_secs_f64()
+        )
+    })
+}
+
+/// Runs `connect` until it succeeds or `retries` retries have failed, waiting `base_delay` before
+/// the first retry and doubling it on every further retry.
+async fn with_connect_retries<T, F, Fut>(
+    retries: u32,
+    base_delay: Duration,
+    mut connect: F,
+) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut attempt = 0;
+    loop {
+        match connect().await {
+            Ok(connection) => return Ok(connection),
+            Err(e) if attempt >= retries => {
+                return Err(e.context(format!(
+                    "Database connection failed after {} attempts",
+                    attempt + 1
+                )))
+            }
+            Err(e) => {
+                let delay = base_delay * 2u32.saturating_pow(attempt);
+                warn!(
+                    "Database connection failed, retrying in {:?} ({}/{}): {:#}",
+                    delay,
+                    attempt + 1,
+                    retries,
+                    e
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// `connection_label`, followed by `/<task_name>` for task connections, e.g. `kiyoshi/purge_logs`.
+/// Empty when the label is disabled.
+fn connection_label(config: &DatabaseConfig, task_name: Option<&str>) -> String {
+    match task_name {
+        _ if config.connection_label.is_empty() => String::new(),
+        Some(task_name) => format!("{}/{}", config.connection_label, task_name),
+        None => config.connection_label.clone(),
+    }
+}
+
+/// Prefixes a query with the connection label as a comment, which shows up in
+/// `SHOW PROCESSLIST` and `performance_schema` statement history.
+fn label_query(label: &str, query: &str) -> String {
+    if label.is_empty() {
+        return query.to_string();
+    }
+    format!("/* {} */ {}", label.replace("*/", "* /"), query)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cleaner::config::Config;
+
+    #[tokio::test]
+    async fn test_query_permits() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        for (max_connections, expected_peak) in [(Some(2), 2), (Some(1), 1), (None, 6)] {
+            let permits = Arc::new(QueryPermits::new(max_connections));
+            let running = Arc::new(AtomicUsize::new(0));
+            let peak = Arc::new(AtomicUsize::new(0));
+            let queries: Vec<_> = (0..6)
+                .map(|_| {
+                    let (permits, running, peak) = (
+                        Arc::clone(&permits),
+                        Arc::clone(&running),
+                        Arc::clone(&peak),
+                    );
+                    tokio::spawn(async move {
+                        let _permit = permits.acquire().await;
+                        let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+                        peak.fetch_max(now_running, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(20)).await;
+                        running.fetch_sub(1, Ordering::SeqCst);
+                    })
+                })
+                .collect();
+            for query in queries {
+                query.await.unwrap();
+            }
+            assert_eq!(
+                peak.load(Ordering::SeqCst),
+                expected_peak,
+                "{:?}",
+                max_connections
+            );
         }
     }
+
+    #[tokio::test]
+    async fn test_with_connect_retries() {
+        let test_cases = vec![
+            // (retries, failures before success, expected attempts, expected success)
+            (3, 0, 1, true),
+            (3, 2, 3, true),
+            (1, 2, 2, false),
+            (0, 1, 1, false),
+        ];
+        for (retries, failures, expected_attempts, expected_ok) in test_cases {
+            let mut attempts = 0;
+            let result = with_connect_retries(retries, Duration::ZERO, || {
+                attempts += 1;
+                let attempt = attempts;
+                async move {
+                    if attempt <= failures {
+                        Err(anyhow!("Connection refused"))
+                    } else {
+                        Ok(attempt)
+                    }
+                }
+            })
+            .await;
+            assert_eq!(attempts, expected_attempts, "{} {}", retries, failures);
+            assert_eq!(result.is_ok(), expected_ok, "{} {}", retries, failures);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_with_query_timeout() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let kills = Arc::new(AtomicUsize::new(0));
+        let kill = || {
+            let kills = Arc::clone(&kills);
+            async move {
+                kills.fetch_add(1, Ordering::SeqCst);
+            }
+        };
+        let statement = |seconds: f64| async move {
+            tokio::time::sleep(Duration::from_secs_f64(seconds)).await;
+            42
+        };
+
+        let result = run_with_query_timeout(Duration::from_secs(5), statement(0.0), kill()).await;
+        assert_eq!(result.unwrap(), 42);
+        assert_eq!(kills.load(Ordering::SeqCst), 0);
+
+        let result =
+            run_with_query_timeout(Duration::from_millis(10), statement(5.0), kill()).await;
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("exceeded query_timeout_seconds of 0.01"));
+        assert_eq!(kills.load(Ordering::SeqCst), 1);
+
+        // Cancelled by an outer timeout such as task_timeout_seconds first
+        let cancelled = tokio::time::timeout(
+            Duration::from_millis(10),
+            run_with_query_timeout(Duration::from_secs(5), statement(5.0), kill()),
+        )
+        .await;
+        assert!(cancelled.is_err());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(kills.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_label_query() {
+        let mut config = Config::default().database_config;
+        assert_eq!(
+            label_query(&connection_label(&config, Some("purge_logs")), "SELECT 1"),
+            "/* kiyoshi/purge_logs */ SELECT 1"
+        );
+        assert_eq!(
+            label_query(&connection_label(&config, None), "SELECT 1"),
+            "/* kiyoshi */ SELECT 1"
+        );
+        assert_eq!(
+            label_query("evil */ DROP TABLE t; /*", "SELECT 1"),
+            "/* evil * / DROP TABLE t; /* */ SELECT 1"
+        );
+
+        config.connection_label = String::new();
+        assert_eq!(
+            label_query(&connection_label(&config, Some("purge_logs")), "SELECT 1"),
+            "SELECT 1"
+        );
+    }
 }
@@ -0,0 +1,208 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    sync::{Mutex, OnceLock},
+};
+
+use crate::cleaner::config::DeadLetterConfig;
+
+/// Why a task was disabled.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub disabled_at: DateTime<Utc>,
+    pub consecutive_failures: u32,
+    pub last_error: String,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    consecutive_failures: HashMap<String, u32>,
+    dead: BTreeMap<String, DeadLetter>,
+}
+
+/// Tasks disabled after `max_consecutive_failures` failed runs in a row. Disabled tasks are
+/// skipped on every schedule, across restarts when persisted, until they are re-enabled.
+#[derive(Debug)]
+pub struct DeadLetters {
+    path: Option<PathBuf>,
+    max_consecutive_failures: Option<u32>,
+    state: Mutex<State>,
+}
+
+static DEAD_LETTERS: OnceLock<DeadLetters> = OnceLock::new();
+
+/// Sets up the process-wide dead letters, loading disabled tasks from `config.path` if it exists.
+pub fn init(config: &DeadLetterConfig) -> Result<&'static DeadLetters> {
+    let dead_letters = DeadLetters::load(config)?;
+    Ok(DEAD_LETTERS.get_or_init(|| dead_letters))
+}
+
+/// The process-wide dead letters, if [`init`] was called.
+pub fn dead_letters() -> Option<&'static DeadLetters> {
+    DEAD_LETTERS.get()
+}
+
+impl DeadLetters {
+    pub fn load(config: &DeadLetterConfig) -> Result<Self> {
+        let path = config.path.as_ref().map(PathBuf::from);
+        let dead = match &path {
+            Some(path) if path.exists() => {
+                let content = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read dead letters: {}", path.display()))?;
+                serde_json::from_str(&content)
+                    .with_context(|| format!("Failed to parse dead letters: {}", path.display()))?
+            }
+            _ => BTreeMap::new(),
+        };
+        Ok(Self {
+            path,
+            max_consecutive_failures: config.max_consecutive_failures,
+            state: Mutex::new(State {
+                consecutive_failures: HashMap::new(),
+                dead,
+            }),
+        })
+    }
+
+    pub fn is_disabled(&self, task_name: &str) -> bool {
+        self.state.lock().unwrap().dead.contains_key(task_name)
+    }
+
+    pub fn record_success(&self, task_name: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .consecutive_failures
+            .remove(task_name);
+    }
+
+    /// Counts a failed run, returning the dead letter if this failure disabled the task. It is
+    /// only returned once, so the task's disablement is notified once.
+    pub fn record_failure(
+        &self,
+        task_name: &str,
+        error: &str,
+        now: DateTime<Utc>,
+    ) -> Option<DeadLetter> {
+        let max_consecutive_failures = self.max_consecutive_failures?;
+        let mut state = self.state.lock().unwrap();
+        if state.dead.contains_key(task_name) {
+            return None;
+        }
+        let failures = state
+            .consecutive_failures
+            .entry(task_name.to_string())
+            .or_default();
+        *failures += 1;
+        if *failures < max_consecutive_failures {
+            return None;
+        }
+        let dead_letter = DeadLetter {
+            disabled_at: now,
+            consecutive_failures: *failures,
+            last_error: error.to_string(),
+        };
+        state.consecutive_failures.remove(task_name);
+        state
+            .dead
+            .insert(task_name.to_string(), dead_letter.clone());
+        self.persist(&state.dead);
+        Some(dead_letter)
+    }
+
+    /// Re-enables a disabled task, returning whether it was disabled.
+    pub fn enable(&self, task_name: &str) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures.remove(task_name);
+        let enabled = state.dead.remove(task_name).is_some();
+        if enabled {
+            self.persist(&state.dead);
+        }
+        enabled
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, DeadLetter> {
+        self.state.lock().unwrap().dead.clone()
+    }
+
+    /// Writes the disabled tasks to `path`. Failing to write the file is logged, not returned, so
+    /// it never fails a task.
+    fn persist(&self, dead: &BTreeMap<String, DeadLetter>) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        // Write to a temporary file first so a crash never leaves a truncated file
+        let tmp_path = path.with_extension("tmp");
+        let result = serde_json::to_string_pretty(dead)
+            .map_err(anyhow::Error::from)
+            .and_then(|content| {
+                std::fs::write(&tmp_path, content)
+                    .with_context(|| format!("Failed to write {}", tmp_path.display()))
+            })
+            .and_then(|()| {
+                std::fs::rename(&tmp_path, path)
+                    .with_context(|| format!("Failed to replace {}", path.display()))
+            });
+        if let Err(e) = result {
+            warn!("Failed to persist dead letters: {:#}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dead_letters() {
+        let path = std::env::temp_dir().join(format!("kiyoshi_dead_{}.json", std::process::id()));
+        let config = DeadLetterConfig {
+            max_consecutive_failures: Some(3),
+            path: Some(path.to_string_lossy().to_string()),
+        };
+        let dead_letters = DeadLetters::load(&config).unwrap();
+        let now = Utc::now();
+
+        // A success resets the count
+        assert_eq!(dead_letters.record_failure("purge_logs", "boom", now), None);
+        assert_eq!(dead_letters.record_failure("purge_logs", "boom", now), None);
+        dead_letters.record_success("purge_logs");
+        assert_eq!(dead_letters.record_failure("purge_logs", "boom", now), None);
+        assert_eq!(dead_letters.record_failure("purge_logs", "boom", now), None);
+        assert!(!dead_letters.is_disabled("purge_logs"));
+
+        let dead_letter = dead_letters.record_failure("purge_logs", "bad credentials", now);
+        assert_eq!(
+            dead_letter,
+            Some(DeadLetter {
+                disabled_at: now,
+                consecutive_failures: 3,
+                last_error: "bad credentials".to_string(),
+            })
+        );
+        assert!(dead_letters.is_disabled("purge_logs"));
+        assert!(!dead_letters.is_disabled("purge_events"));
+        // Only notified once
+        assert_eq!(dead_letters.record_failure("purge_logs", "boom", now), None);
+
+        // Disabled tasks survive a restart
+        let reloaded = DeadLetters::load(&config).unwrap();
+        assert!(reloaded.is_disabled("purge_logs"));
+        assert!(reloaded.enable("purge_logs"));
+        assert!(!reloaded.enable("purge_logs"));
+        assert!(!DeadLetters::load(&config)
+            .unwrap()
+            .is_disabled("purge_logs"));
+
+        // Never disabled without max_consecutive_failures
+        let dead_letters = DeadLetters::load(&DeadLetterConfig::default()).unwrap();
+        for _ in 0..10 {
+            assert_eq!(dead_letters.record_failure("purge_logs", "boom", now), None);
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+}
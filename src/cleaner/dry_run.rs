@@ -0,0 +1,208 @@
+use anyhow::{anyhow, Result};
+use sqlparser::{
+    ast::{self, FromTable},
+    parser::Parser,
+};
+
+use crate::cleaner::config::SqlDialect;
+
+/// Shown instead of the value of a masked column in a sample
+const MASKED_VALUE: &str = "***";
+
+/// Builds a `SELECT COUNT(*)` over the rows a DELETE statement would remove. The DELETE's
+/// `ORDER BY` and `LIMIT` are dropped so the count covers every eligible row, not one batch.
+pub fn count_query(sql: &str, dialect: SqlDialect) -> Result<String> {
+    let delete = parse_delete(sql, dialect)?;
+    let from = delete_tables(&delete)?;
+    Ok(match &delete.selection {
+        Some(selection) => format!("SELECT COUNT(*) FROM {} WHERE {}", from, selection),
+        None => format!("SELECT COUNT(*) FROM {}", from),
+    })
+}
+
+/// Builds a `SELECT *` of up to `limit` rows a DELETE statement would remove, in the DELETE's
+/// `ORDER BY` so the sample comes from the first batch.
+pub fn sample_query(sql: &str, dialect: SqlDialect, limit: usize) -> Result<String> {
+    let delete = parse_delete(sql, dialect)?;
+    let mut query = format!("SELECT * FROM {}", delete_tables(&delete)?);
+    if let Some(selection) = &delete.selection {
+        query.push_str(&format!(" WHERE {}", selection));
+    }
+    if !delete.order_by.is_empty() {
+        let order_by = delete
+            .order_by
+            .iter()
+            .map(|order_by| order_by.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        query.push_str(&format!(" ORDER BY {}", order_by));
+    }
+    query.push_str(&format!(" LIMIT {}", limit));
+    Ok(query)
+}
+
+fn parse_delete(sql: &str, dialect: SqlDialect) -> Result<ast::Delete> {
+    let mut ast = Parser::parse_sql(dialect.parser_dialect().as_ref(), sql)
+        .map_err(|e| anyhow!("Failed to parse SQL: {}", e))?;
+    match (ast.pop(), ast.is_empty()) {
+        (Some(ast::Statement::Delete(delete)), true) => Ok(delete),
+        _ => Err(anyhow!("Only a single DELETE statement can be dry-run")),
+    }
+}
+
+/// The tables a DELETE removes rows from, including its USING tables.
+fn delete_tables(delete: &ast::Delete) -> Result<String> {
+    let mut tables = match &delete.from {
+        FromTable::WithFromKeyword(tables) | FromTable::WithoutKeyword(tables) => tables.clone(),
+    };
+    if let Some(using) = &delete.using {
+        tables.extend(using.iter().cloned());
+    }
+    if tables.is_empty() {
+        return Err(anyhow!("DELETE statement has no FROM clause"));
+    }
+
+    Ok(tables
+        .iter()
+        .map(|table| table.to_string())
+        .collect::<Vec<_>>()
+        .join(", "))
+}
+
+/// Renders sampled rows as an aligned text table, replacing the values of `masked_columns`
+/// (matched case-insensitively) with `***`. `NULL`s are shown as `NULL`.
+pub fn format_sample(
+    columns: &[String],
+    rows: &[Vec<Option<String>>],
+    masked_columns: &[String],
+) -> String {
+    let masked: Vec<bool> = columns
+        .iter()
+        .map(|column| {
+            masked_columns
+                .iter()
+                .any(|masked| masked.eq_ignore_ascii_case(column))
+        })
+        .collect();
+    let cells: Vec<Vec<&str>> = rows
+        .iter()
+        .map(|row| {
+            row.iter()
+                .zip(&masked)
+                .map(|(value, masked)| match (value, masked) {
+                    (_, true) => MASKED_VALUE,
+                    (Some(value), false) => value.as_str(),
+                    (None, false) => "NULL",
+                })
+                .collect()
+        })
+        .collect();
+    let widths: Vec<usize> = columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| {
+            cells
+                .iter()
+                .map(|row| row[i].chars().count())
+                .chain([column.chars().count()])
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+
+    let format_row = |values: Vec<&str>| {
+        values
+            .iter()
+            .zip(&widths)
+            .map(|(value, width)| format!("{:<width$}", value, width = width))
+            .collect::<Vec<_>>()
+            .join(" | ")
+            .trim_end()
+            .to_string()
+    };
+    let mut lines = vec![
+        format_row(columns.iter().map(String::as_str).collect()),
+        widths
+            .iter()
+            .map(|width| "-".repeat(*width))
+            .collect::<Vec<_>>()
+            .join("-+-"),
+    ];
+    lines.extend(cells.into_iter().map(format_row));
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_count_query() {
+        let test_cases = vec![
+            (
+                "DELETE FROM events WHERE created_at < DATE_SUB('2024-03-20 00:00:00', INTERVAL 30 DAY) LIMIT 1000;",
+                "SELECT COUNT(*) FROM events WHERE created_at < DATE_SUB('2024-03-20 00:00:00', INTERVAL 30 DAY)",
+            ),
+            (
+                "DELETE FROM events WHERE created_at < DATE_SUB(NOW(), INTERVAL 30 DAY) ORDER BY id LIMIT 10",
+                "SELECT COUNT(*) FROM events WHERE created_at < DATE_SUB(NOW(), INTERVAL 30 DAY)",
+            ),
+            ("DELETE FROM events", "SELECT COUNT(*) FROM events"),
+        ];
+
+        for (sql, expected) in test_cases {
+            assert_eq!(count_query(sql, SqlDialect::Mysql).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_sample_query() {
+        let test_cases = vec![
+            (
+                "DELETE FROM events WHERE created_at < DATE_SUB(NOW(), INTERVAL 30 DAY) ORDER BY id LIMIT 1000",
+                "SELECT * FROM events WHERE created_at < DATE_SUB(NOW(), INTERVAL 30 DAY) ORDER BY id LIMIT 5",
+            ),
+            (
+                "DELETE FROM `db`.`events` WHERE id < 10 LIMIT 1000",
+                "SELECT * FROM `db`.`events` WHERE id < 10 LIMIT 5",
+            ),
+            ("DELETE FROM events", "SELECT * FROM events LIMIT 5"),
+        ];
+
+        for (sql, expected) in test_cases {
+            assert_eq!(sample_query(sql, SqlDialect::Mysql, 5).unwrap(), expected);
+        }
+        assert!(sample_query("UPDATE events SET a = 1", SqlDialect::Mysql, 5).is_err());
+    }
+
+    #[test]
+    fn test_format_sample() {
+        let columns = vec!["id".to_string(), "email".to_string(), "note".to_string()];
+        let rows = vec![
+            vec![
+                Some("1".to_string()),
+                Some("a@example.com".to_string()),
+                None,
+            ],
+            vec![
+                Some("10".to_string()),
+                Some("b@example.com".to_string()),
+                Some("hello".to_string()),
+            ],
+        ];
+        assert_eq!(
+            format_sample(&columns, &rows, &["EMAIL".to_string()]),
+            "id | email | note\n---+-------+------\n1  | ***   | NULL\n10 | ***   | hello"
+        );
+    }
+
+    #[test]
+    fn test_count_query_rejects_non_delete() {
+        assert!(count_query("SELECT * FROM events", SqlDialect::Mysql).is_err());
+        assert!(count_query(
+            "DELETE FROM a WHERE id = 1; DELETE FROM b WHERE id = 1",
+            SqlDialect::Mysql
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,126 @@
+use chrono::{DateTime, Utc};
+use std::{
+    collections::BTreeSet,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+/// Process-level record of which tasks have failed since their last successful run.
+#[derive(Debug, Default)]
+pub struct TaskHealth {
+    failing: Mutex<BTreeSet<String>>,
+}
+
+impl TaskHealth {
+    pub fn record_success(&self, task_name: &str) {
+        self.failing.lock().unwrap().remove(task_name);
+    }
+
+    pub fn record_failure(&self, task_name: &str) {
+        self.failing.lock().unwrap().insert(task_name.to_string());
+    }
+
+    /// Names of tasks whose most recent run failed, in sorted order.
+    pub fn failing_tasks(&self) -> Vec<String> {
+        self.failing.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+/// Counters of the task runs since Kiyoshi started, for the shutdown summary.
+#[derive(Debug)]
+pub struct SessionStats {
+    started_at: DateTime<Utc>,
+    runs: AtomicU64,
+    failed_runs: AtomicU64,
+    rows_deleted: AtomicU64,
+}
+
+impl Default for SessionStats {
+    fn default() -> Self {
+        Self::new(Utc::now())
+    }
+}
+
+/// What happened during a session, reported on shutdown.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionSummary {
+    pub uptime_seconds: f64,
+    pub runs: u64,
+    pub failed_runs: u64,
+    pub rows_deleted: u64,
+    pub failing_tasks: Vec<String>,
+}
+
+impl SessionStats {
+    pub fn new(started_at: DateTime<Utc>) -> Self {
+        Self {
+            started_at,
+            runs: AtomicU64::new(0),
+            failed_runs: AtomicU64::new(0),
+            rows_deleted: AtomicU64::new(0),
+        }
+    }
+
+    /// Counts a finished run. Rows deleted by failed runs count too, as they are gone all the same.
+    pub fn record_run(&self, rows_deleted: u64, succeeded: bool) {
+        self.runs.fetch_add(1, Ordering::Relaxed);
+        if !succeeded {
+            self.failed_runs.fetch_add(1, Ordering::Relaxed);
+        }
+        self.rows_deleted.fetch_add(rows_deleted, Ordering::Relaxed);
+    }
+
+    pub fn summary(&self, health: &TaskHealth, now: DateTime<Utc>) -> SessionSummary {
+        SessionSummary {
+            uptime_seconds: (now - self.started_at).num_milliseconds() as f64 / 1000.0,
+            runs: self.runs.load(Ordering::Relaxed),
+            failed_runs: self.failed_runs.load(Ordering::Relaxed),
+            rows_deleted: self.rows_deleted.load(Ordering::Relaxed),
+            failing_tasks: health.failing_tasks(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_task_health_tracks_failures_until_success() {
+        let health = TaskHealth::default();
+        health.record_failure("b_task");
+        health.record_failure("a_task");
+        health.record_failure("a_task");
+        assert_eq!(health.failing_tasks(), vec!["a_task", "b_task"]);
+
+        health.record_success("a_task");
+        assert_eq!(health.failing_tasks(), vec!["b_task"]);
+
+        health.record_success("b_task");
+        assert!(health.failing_tasks().is_empty());
+    }
+
+    #[test]
+    fn test_session_stats_summary() {
+        let started_at = Utc::now();
+        let stats = SessionStats::new(started_at);
+        let health = TaskHealth::default();
+        stats.record_run(100, true);
+        stats.record_run(50, false);
+        health.record_failure("purge_logs");
+        stats.record_run(0, true);
+
+        assert_eq!(
+            stats.summary(&health, started_at + chrono::Duration::seconds(90)),
+            SessionSummary {
+                uptime_seconds: 90.0,
+                runs: 3,
+                failed_runs: 1,
+                rows_deleted: 150,
+                failing_tasks: vec!["purge_logs".to_string()],
+            }
+        );
+    }
+}
@@ -0,0 +1,195 @@
+use anyhow::{anyhow, Context, Result};
+use log::LevelFilter;
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+/// The log level of Kiyoshi and of `sqlx` for the number of `--verbose` flags: `-v` logs debug,
+/// `-vv` trace, and `-vvv` also every statement sqlx runs.
+pub fn log_levels(verbosity: u8) -> (LevelFilter, LevelFilter) {
+    match verbosity {
+        0 => (LevelFilter::Info, LevelFilter::Warn),
+        1 => (LevelFilter::Debug, LevelFilter::Warn),
+        2 => (LevelFilter::Trace, LevelFilter::Warn),
+        _ => (LevelFilter::Trace, LevelFilter::Trace),
+    }
+}
+
+/// A log file that is renamed to `<path>.1` once writing to it would exceed `max_bytes`, shifting
+/// older files to `<path>.2` and so on. Only `max_files` rotated files are kept.
+pub struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    file: BufWriter<File>,
+    size: u64,
+}
+
+impl RotatingFile {
+    /// Opens `path` for appending, creating it if needed.
+    pub fn open(path: impl AsRef<Path>, max_bytes: u64, max_files: usize) -> Result<Self> {
+        if max_bytes == 0 {
+            return Err(anyhow!("Log rotation size must be greater than 0"));
+        }
+        let path = path.as_ref().to_path_buf();
+        let file = open_append(&path)
+            .with_context(|| format!("Failed to open log file: {}", path.display()))?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_bytes,
+            max_files,
+            file: BufWriter::new(file),
+            size,
+        })
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        path.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.max_files == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            for index in (1..self.max_files).rev() {
+                let from = self.rotated_path(index);
+                if from.exists() {
+                    std::fs::rename(&from, self.rotated_path(index + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated_path(1))?;
+        }
+        self.file = BufWriter::new(open_append(&self.path)?);
+        self.size = 0;
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // A record is never split, so a single record larger than max_bytes gets its own file
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// The log target for `--log-file`, writing every record to the log file and optionally to stderr.
+pub struct LogWriter {
+    file: Arc<Mutex<RotatingFile>>,
+    stderr: bool,
+}
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.stderr {
+            io::stderr().write_all(buf)?;
+        }
+        self.file.lock().unwrap().write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.lock().unwrap().flush()
+    }
+}
+
+/// Flushes the log file when dropped, so the last records are written on shutdown.
+pub struct LogFileGuard(Arc<Mutex<RotatingFile>>);
+
+impl Drop for LogFileGuard {
+    fn drop(&mut self) {
+        if let Ok(mut file) = self.0.lock() {
+            let _ = file.flush();
+        }
+    }
+}
+
+/// Opens a rotating log file and returns the writer to log to along with a guard to keep until
+/// shutdown.
+pub fn open_log_file(
+    path: &str,
+    rotate_size_mb: u64,
+    max_files: usize,
+    stderr: bool,
+) -> Result<(LogWriter, LogFileGuard)> {
+    let file = RotatingFile::open(path, rotate_size_mb.saturating_mul(1024 * 1024), max_files)?;
+    let file = Arc::new(Mutex::new(file));
+    Ok((
+        LogWriter {
+            file: Arc::clone(&file),
+            stderr,
+        },
+        LogFileGuard(file),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_levels() {
+        let test_cases = vec![
+            (0, LevelFilter::Info, LevelFilter::Warn),
+            (1, LevelFilter::Debug, LevelFilter::Warn),
+            (2, LevelFilter::Trace, LevelFilter::Warn),
+            (3, LevelFilter::Trace, LevelFilter::Trace),
+            (5, LevelFilter::Trace, LevelFilter::Trace),
+        ];
+        for (verbosity, level, sqlx_level) in test_cases {
+            assert_eq!(log_levels(verbosity), (level, sqlx_level), "{}", verbosity);
+        }
+    }
+
+    #[test]
+    fn test_rotating_file() {
+        let dir = std::env::temp_dir().join(format!("kiyoshi_log_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("kiyoshi.log");
+
+        let mut file = RotatingFile::open(&path, 10, 2).unwrap();
+        for line in ["line 1\n", "line 2\n", "line 3\n", "line 4\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        file.flush().unwrap();
+
+        let read = |path: PathBuf| std::fs::read_to_string(path).unwrap();
+        assert_eq!(read(path.clone()), "line 4\n");
+        assert_eq!(read(file.rotated_path(1)), "line 3\n");
+        assert_eq!(read(file.rotated_path(2)), "line 2\n");
+        // Only max_files rotated files are kept
+        assert!(!file.rotated_path(3).exists());
+
+        // Reopening appends to the existing file and counts its size
+        drop(file);
+        let mut file = RotatingFile::open(&path, 10, 2).unwrap();
+        file.write_all(b"5\n").unwrap();
+        file.flush().unwrap();
+        assert_eq!(read(path.clone()), "line 4\n5\n");
+        file.write_all(b"6\n").unwrap();
+        file.flush().unwrap();
+        assert_eq!(read(path.clone()), "6\n");
+        assert_eq!(read(file.rotated_path(1)), "line 4\n5\n");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,115 @@
+use anyhow::{Context, Result};
+use log::info;
+use std::collections::{HashMap, HashSet};
+
+use crate::cleaner::{
+    config::{CleanupTask, LookupConfig},
+    db::Database,
+    template::TemplateEngine,
+};
+
+/// A looked up value as a SQL literal: integers as they are, anything else as a quoted string.
+pub fn sql_literal(value: &str) -> String {
+    if value.parse::<i64>().is_ok() {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\\', "\\\\").replace('\'', "''"))
+    }
+}
+
+/// Splits the values into comma-separated lists of SQL literals, each with at most `max_ids`
+/// values and `max_bytes` bytes. A single value longer than `max_bytes` gets a list of its own.
+pub fn chunk_id_lists(ids: &[String], max_ids: usize, max_bytes: usize) -> Vec<String> {
+    let mut lists = Vec::new();
+    let mut list = String::new();
+    let mut count = 0;
+    for id in ids {
+        let literal = sql_literal(id);
+        let separator = if count == 0 { 0 } else { 2 };
+        if count > 0 && (count >= max_ids || list.len() + separator + literal.len() > max_bytes) {
+            lists.push(std::mem::take(&mut list));
+            count = 0;
+        }
+        if count > 0 {
+            list.push_str(", ");
+        }
+        list.push_str(&literal);
+        count += 1;
+    }
+    if count > 0 {
+        lists.push(list);
+    }
+    lists
+}
+
+/// Runs the task's `lookup.query` on the secondary database and returns the `lookup_ids` lists
+/// to delete by, one DELETE loop each. NULLs and duplicates are skipped.
+pub async fn fetch_lookup_lists(
+    lookup: &LookupConfig,
+    task: &CleanupTask,
+    template_engine: &TemplateEngine,
+    template_parameters: &HashMap<String, String>,
+    data_interval_end: &str,
+) -> Result<Vec<String>> {
+    let query = template_engine
+        .render(&lookup.query, template_parameters, data_interval_end)
+        .context("failed to render lookup.query")?;
+    let db = Database::new_for_task(&lookup.database_config, &task.name)
+        .await
+        .context("failed to connect to the lookup database")?;
+    let (_, rows) = db
+        .fetch_text_rows(&query)
+        .await
+        .context("lookup.query failed")?;
+
+    let mut seen = HashSet::new();
+    let ids: Vec<String> = rows
+        .into_iter()
+        .filter_map(|row| row.into_iter().next().flatten())
+        .filter(|id| seen.insert(id.clone()))
+        .collect();
+    let max_ids = lookup
+        .max_ids_per_statement
+        .unwrap_or(task.batch_size as usize);
+    let lists = chunk_id_lists(&ids, max_ids.max(1), lookup.max_list_bytes);
+    info!(
+        "Lookup of task {} returned {} values in {} lists",
+        task.name,
+        ids.len(),
+        lists.len()
+    );
+    Ok(lists)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn test_chunk_id_lists() {
+        // (ids, max ids, max bytes, expected lists)
+        let test_cases = vec![
+            (ids(&[]), 10, 100, vec![]),
+            (ids(&["1", "2", "3"]), 10, 100, vec!["1, 2, 3"]),
+            (ids(&["1", "2", "3"]), 2, 100, vec!["1, 2", "3"]),
+            // "1, 2" is 4 bytes, adding ", 3" would make it 7
+            (ids(&["1", "2", "3"]), 10, 6, vec!["1, 2", "3"]),
+            (ids(&["a'b", "12345678"]), 10, 5, vec!["'a''b'", "12345678"]),
+            (ids(&["-7", "x\\y"]), 10, 100, vec!["-7, 'x\\\\y'"]),
+        ];
+        for (ids, max_ids, max_bytes, expected) in test_cases {
+            assert_eq!(
+                chunk_id_lists(&ids, max_ids, max_bytes),
+                expected,
+                "{:?} {} {}",
+                ids,
+                max_ids,
+                max_bytes
+            );
+        }
+    }
+}
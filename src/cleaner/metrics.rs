@@ -0,0 +1,108 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Mutex, OnceLock},
+};
+
+type Labels = Vec<(String, String)>;
+
+/// In-process counters and gauges, rendered in the Prometheus text format.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    counters: Mutex<BTreeMap<(String, Labels), f64>>,
+    gauges: Mutex<BTreeMap<(String, Labels), f64>>,
+}
+
+/// The process-wide metrics registry.
+pub fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::default)
+}
+
+fn to_key(name: &str, labels: &[(&str, &str)]) -> (String, Labels) {
+    let labels = labels
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+    (name.to_string(), labels)
+}
+
+impl Metrics {
+    pub fn increment(&self, name: &str, labels: &[(&str, &str)], value: f64) {
+        *self
+            .counters
+            .lock()
+            .unwrap()
+            .entry(to_key(name, labels))
+            .or_default() += value;
+    }
+
+    pub fn set_gauge(&self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.gauges
+            .lock()
+            .unwrap()
+            .insert(to_key(name, labels), value);
+    }
+
+    #[cfg(test)]
+    pub fn get(&self, name: &str, labels: &[(&str, &str)]) -> Option<f64> {
+        let key = to_key(name, labels);
+        let counter = self.counters.lock().unwrap().get(&key).copied();
+        counter.or_else(|| self.gauges.lock().unwrap().get(&key).copied())
+    }
+
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+        for (kind, values) in [("counter", &self.counters), ("gauge", &self.gauges)] {
+            let mut last_name = None;
+            for ((name, labels), value) in values.lock().unwrap().iter() {
+                if last_name != Some(name) {
+                    let _ = writeln!(output, "# TYPE {} {}", name, kind);
+                    last_name = Some(name);
+                }
+                let labels = labels
+                    .iter()
+                    .map(|(key, value)| format!("{}=\"{}\"", key, escape_label_value(value)))
+                    .collect::<Vec<_>>();
+                if labels.is_empty() {
+                    let _ = writeln!(output, "{} {}", name, value);
+                } else {
+                    let _ = writeln!(output, "{}{{{}}} {}", name, labels.join(","), value);
+                }
+            }
+        }
+        output
+    }
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics_render() {
+        let metrics = Metrics::default();
+        metrics.increment("kiyoshi_slow_queries_total", &[("task", "a")], 1.0);
+        metrics.increment("kiyoshi_slow_queries_total", &[("task", "a")], 1.0);
+        metrics.set_gauge("kiyoshi_up", &[], 1.0);
+
+        assert_eq!(
+            metrics.get("kiyoshi_slow_queries_total", &[("task", "a")]),
+            Some(2.0)
+        );
+        assert_eq!(
+            metrics.render(),
+            "# TYPE kiyoshi_slow_queries_total counter\n\
+             kiyoshi_slow_queries_total{task=\"a\"} 2\n\
+             # TYPE kiyoshi_up gauge\n\
+             kiyoshi_up 1\n"
+        );
+    }
+}
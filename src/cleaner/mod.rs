@@ -1,5 +1,25 @@
-pub mod config;
-mod db;
-mod sql_validate;
-pub mod task;
-mod template;
+pub(crate) mod admin;
+pub(crate) mod callback;
+pub(crate) mod circuit_breaker;
+pub(crate) mod config;
+pub(crate) mod config_diff;
+pub(crate) mod confirm;
+pub(crate) mod db;
+pub(crate) mod dead_letter;
+pub(crate) mod dry_run;
+pub(crate) mod health;
+pub(crate) mod log_file;
+pub(crate) mod lookup;
+pub(crate) mod metrics;
+pub(crate) mod partition;
+pub(crate) mod query_plan;
+pub(crate) mod run_history;
+pub(crate) mod run_id;
+pub(crate) mod run_report;
+pub(crate) mod schema;
+pub(crate) mod slack;
+pub(crate) mod sql_validate;
+pub(crate) mod ssh_tunnel;
+pub(crate) mod task;
+pub(crate) mod task_list;
+pub(crate) mod template;
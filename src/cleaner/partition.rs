@@ -0,0 +1,323 @@
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Timelike};
+use log::{info, warn};
+
+use crate::cleaner::{
+    config::{CleanupTask, Config},
+    db::QueryExecutor,
+    template::quote_identifier,
+};
+
+/// A row of `information_schema.partitions`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionInfo {
+    pub name: String,
+    /// `RANGE`, `RANGE COLUMNS`, `LIST`, ...
+    pub method: String,
+    pub expression: String,
+    /// The exclusive upper bound of a range partition, e.g. `739252`, `'2024-01-01'` or `MAXVALUE`
+    pub description: Option<String>,
+}
+
+/// How a range partition's bound relates to the partitioning column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PartitionKey {
+    /// `RANGE COLUMNS(column)` on a DATE or DATETIME column
+    Column,
+    /// `RANGE (TO_DAYS(column))`
+    Days,
+    /// `RANGE (TO_SECONDS(column))`
+    Seconds,
+    /// `RANGE (YEAR(column))`
+    Year,
+}
+
+fn partition_key(partition: &PartitionInfo, column: &str) -> Result<PartitionKey> {
+    let expression = partition.expression.replace(['`', ' '], "").to_lowercase();
+    let column = column.to_lowercase();
+    match partition.method.to_uppercase().as_str() {
+        "RANGE COLUMNS" if expression == column => Ok(PartitionKey::Column),
+        "RANGE" if expression == format!("to_days({})", column) => Ok(PartitionKey::Days),
+        "RANGE" if expression == format!("to_seconds({})", column) => Ok(PartitionKey::Seconds),
+        "RANGE" if expression == format!("year({})", column) => Ok(PartitionKey::Year),
+        method => Err(anyhow!(
+            "Unsupported partitioning {} ({}) for partition_drop on column {}, expected RANGE \
+             COLUMNS({}) or RANGE over TO_DAYS, TO_SECONDS or YEAR of it",
+            method,
+            partition.expression,
+            column,
+            column
+        )),
+    }
+}
+
+/// MySQL's `TO_DAYS`, the number of days since year 0.
+fn to_days(datetime: NaiveDateTime) -> i64 {
+    datetime.date().num_days_from_ce() as i64 + 365
+}
+
+/// Whether every row of a partition with this upper bound is older than `boundary`. `MAXVALUE`
+/// partitions never are.
+fn is_older_than(key: PartitionKey, description: &str, boundary: NaiveDateTime) -> Result<bool> {
+    let description = description.trim();
+    if description.eq_ignore_ascii_case("MAXVALUE") {
+        return Ok(false);
+    }
+    let invalid = || anyhow!("Unexpected partition bound '{}'", description);
+    let bound = || -> Result<i64> { description.parse().map_err(|_| invalid()) };
+    Ok(match key {
+        PartitionKey::Column => {
+            let value = description.trim_matches('\'');
+            let bound = NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S")
+                .or_else(|_| {
+                    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                        .map(|date| date.and_hms_opt(0, 0, 0).unwrap())
+                })
+                .map_err(|_| invalid())?;
+            bound <= boundary
+        }
+        PartitionKey::Days => bound()? <= to_days(boundary),
+        PartitionKey::Seconds => {
+            bound()? <= to_days(boundary) * 86400 + boundary.num_seconds_from_midnight() as i64
+        }
+        PartitionKey::Year => bound()? <= boundary.year() as i64,
+    })
+}
+
+/// The names of the partitions whose rows are all older than `boundary`, oldest first. The newest
+/// partition is always kept, as MySQL cannot drop every partition of a table.
+pub fn partitions_to_drop(
+    partitions: &[PartitionInfo],
+    column: &str,
+    boundary: NaiveDateTime,
+) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    for partition in partitions {
+        let key = partition_key(partition, column)?;
+        let Some(description) = &partition.description else {
+            continue;
+        };
+        if is_older_than(key, description, boundary)? {
+            names.push(partition.name.clone());
+        }
+    }
+    if !partitions.is_empty() && names.len() == partitions.len() {
+        let kept = names.pop().unwrap_or_default();
+        warn!(
+            "Every partition is older than the retention boundary, keeping the newest one: {}",
+            kept
+        );
+    }
+    Ok(names)
+}
+
+/// Drops the partitions of the task's table that only hold rows older than the task's
+/// `partition_drop.retention_days` before `data_interval_end`, returning their names.
+pub async fn drop_old_partitions<E: QueryExecutor>(
+    executor: &E,
+    config: &Config,
+    task: &CleanupTask,
+    data_interval_end: NaiveDateTime,
+) -> Result<Vec<String>> {
+    let partition_drop = task
+        .partition_drop
+        .as_ref()
+        .ok_or_else(|| anyhow!("Task {} has no partition_drop settings", task.name))?;
+    let database_config = &config.database_config;
+    let schema = task
+        .parameters
+        .get("schema_name")
+        .unwrap_or(&database_config.database);
+    let table = task
+        .parameters
+        .get("table_name")
+        .ok_or_else(|| anyhow!("partition_drop requires a table_name parameter"))?;
+
+    let boundary = data_interval_end - chrono::Duration::days(partition_drop.retention_days as i64);
+    let partitions = executor.partitions(schema, table).await?;
+    if partitions.is_empty() {
+        return Err(anyhow!("Table {}.{} is not partitioned", schema, table));
+    }
+    let names = partitions_to_drop(&partitions, &partition_drop.column, boundary)?;
+    if names.is_empty() {
+        info!(
+            "No partitions of {}.{} are entirely older than {} for task: {}",
+            schema, table, boundary, task.name
+        );
+        return Ok(names);
+    }
+
+    let quote = database_config.dialect.identifier_quote();
+    let quoted_names = names
+        .iter()
+        .map(|name| quote_identifier(name, quote))
+        .collect::<Result<Vec<_>, _>>()?;
+    let sql = format!(
+        "ALTER TABLE {} DROP PARTITION {}",
+        quote_identifier(&format!("{}.{}", schema, table), quote)?,
+        quoted_names.join(", ")
+    );
+    info!("Dropping partitions for task {}: \n{}", task.name, sql);
+    executor.execute_query(&sql).await?;
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    fn partition(name: &str, method: &str, expression: &str, description: &str) -> PartitionInfo {
+        PartitionInfo {
+            name: name.to_string(),
+            method: method.to_string(),
+            expression: expression.to_string(),
+            description: Some(description.to_string()),
+        }
+    }
+
+    fn datetime(value: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn test_partitions_to_drop() {
+        let boundary = datetime("2024-02-15 12:00:00");
+        let test_cases = vec![
+            (
+                "RANGE COLUMNS",
+                "`created_at`",
+                vec![
+                    "'2024-01-01'",
+                    "'2024-02-01 00:00:00'",
+                    "'2024-03-01'",
+                    "MAXVALUE",
+                ],
+                vec!["p0", "p1"],
+            ),
+            (
+                "RANGE",
+                "to_days(`created_at`)",
+                // TO_DAYS of 2024-02-15, 2024-02-16 and 2024-03-01
+                vec!["739296", "739297", "739311"],
+                vec!["p0"],
+            ),
+            (
+                "RANGE",
+                "to_seconds(`created_at`)",
+                // TO_SECONDS of 2024-02-15 12:00:00 and 12:00:01
+                vec!["63875217600", "63875217601", "MAXVALUE"],
+                vec!["p0"],
+            ),
+            (
+                "RANGE",
+                "year(`created_at`)",
+                vec!["2023", "2024", "2025"],
+                vec!["p0", "p1"],
+            ),
+            // The newest partition is always kept
+            (
+                "RANGE",
+                "year(`created_at`)",
+                vec!["2022", "2023"],
+                vec!["p0"],
+            ),
+        ];
+        for (method, expression, descriptions, expected) in test_cases {
+            let partitions: Vec<_> = descriptions
+                .iter()
+                .enumerate()
+                .map(|(i, description)| {
+                    partition(&format!("p{}", i), method, expression, description)
+                })
+                .collect();
+            assert_eq!(
+                partitions_to_drop(&partitions, "created_at", boundary).unwrap(),
+                expected,
+                "{} {} {:?}",
+                method,
+                expression,
+                descriptions
+            );
+        }
+    }
+
+    #[test]
+    fn test_partitions_to_drop_rejects_other_partitioning() {
+        let boundary = datetime("2024-02-15 12:00:00");
+        let test_cases = vec![
+            partition("p0", "RANGE COLUMNS", "`updated_at`", "'2024-01-01'"),
+            partition("p0", "RANGE", "unix_timestamp(`created_at`)", "1704067200"),
+            partition("p0", "LIST", "`region_id`", "1,2"),
+            partition("p0", "HASH", "`id`", "1"),
+        ];
+        for partition in test_cases {
+            assert!(
+                partitions_to_drop(std::slice::from_ref(&partition), "created_at", boundary)
+                    .is_err(),
+                "{:?}",
+                partition
+            );
+        }
+    }
+
+    struct MockPartitions {
+        partitions: Vec<PartitionInfo>,
+        queries: Mutex<Vec<String>>,
+    }
+
+    impl QueryExecutor for MockPartitions {
+        async fn execute_query(&self, query: &str) -> Result<(u64, f64)> {
+            self.queries.lock().unwrap().push(query.to_string());
+            Ok((0, 0.01))
+        }
+
+        async fn partitions(&self, _schema: &str, _table: &str) -> Result<Vec<PartitionInfo>> {
+            Ok(self.partitions.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_drop_old_partitions() {
+        let task: CleanupTask = serde_yaml::from_str(
+            r#"
+name: drop_old_events
+description: test task
+cron_schedule: "0 0 * * * *"
+enabled: true
+parameters: {table_name: events}
+batch_size: 1000
+retry_attempts: 3
+retry_delay_seconds: 0
+partition_drop:
+  column: created_at
+  retention_days: 30
+"#,
+        )
+        .unwrap();
+        let executor = MockPartitions {
+            partitions: ["'2024-01-01'", "'2024-02-01'", "'2024-03-01'", "MAXVALUE"]
+                .iter()
+                .enumerate()
+                .map(|(i, bound)| {
+                    partition(&format!("p{}", i), "RANGE COLUMNS", "`created_at`", bound)
+                })
+                .collect(),
+            queries: Mutex::new(Vec::new()),
+        };
+
+        let dropped = drop_old_partitions(
+            &executor,
+            &Config::default(),
+            &task,
+            datetime("2024-03-15 00:00:00"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(dropped, vec!["p0", "p1"]);
+        assert_eq!(
+            *executor.queries.lock().unwrap(),
+            vec!["ALTER TABLE `my_database`.`events` DROP PARTITION `p0`, `p1`"]
+        );
+    }
+}
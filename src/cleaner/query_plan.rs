@@ -0,0 +1,146 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+
+use crate::cleaner::{
+    config::{CleanupTask, Config},
+    db::{Database, TextRows},
+    dry_run,
+    task::render_task_query,
+};
+
+/// The value of `column` in an EXPLAIN row, `None` for NULL or a missing column.
+fn plan_value<'a>(columns: &[String], row: &'a [Option<String>], column: &str) -> Option<&'a str> {
+    let index = columns
+        .iter()
+        .position(|name| name.eq_ignore_ascii_case(column))?;
+    row.get(index)?.as_deref()
+}
+
+/// Warnings about the steps of a traditional `EXPLAIN` plan that read the whole table or use no
+/// index, one per step.
+pub fn plan_warnings((columns, rows): &TextRows) -> Vec<String> {
+    rows.iter()
+        .filter_map(|row| {
+            // Steps without a table, e.g. "Impossible WHERE", read no rows
+            let table = plan_value(columns, row, "table")?;
+            let access_type = plan_value(columns, row, "type");
+            let rows = plan_value(columns, row, "rows").unwrap_or("?");
+            if access_type.is_some_and(|access_type| access_type.eq_ignore_ascii_case("ALL")) {
+                Some(format!(
+                    "Full table scan of `{}`, reading about {} rows",
+                    table, rows
+                ))
+            } else if plan_value(columns, row, "key").is_none() {
+                Some(format!(
+                    "No index used on `{}`, reading about {} rows",
+                    table, rows
+                ))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Formats an `EXPLAIN` result as a table followed by its warnings.
+pub fn render_plan(sql: &str, plan: &TextRows) -> String {
+    let (columns, rows) = plan;
+    let mut output = format!(
+        "Query:\n{}\n\nPlan:\n{}\n",
+        sql.trim(),
+        dry_run::format_sample(columns, rows, &[])
+    );
+    let warnings = plan_warnings(plan);
+    if warnings.is_empty() {
+        output.push_str("\nEvery step uses an index.\n");
+    } else {
+        output.push('\n');
+        for warning in warnings {
+            output.push_str(&format!("WARNING: {}\n", warning));
+        }
+    }
+    output
+}
+
+/// Renders and validates the task's query as of now and returns its `EXPLAIN` plan, formatted
+/// by [`render_plan`]. The DELETE is explained, not executed.
+pub async fn profile_query(config: &Config, task: &CleanupTask) -> Result<String> {
+    if task.partition_drop.is_some() {
+        return Err(anyhow!(
+            "Task `{}` drops partitions, it has no query to profile",
+            task.name
+        ));
+    }
+    let sql = render_task_query(config, task, Utc::now())
+        .with_context(|| format!("Failed to render the query of task `{}`", task.name))?;
+    let db = Database::new_for_task(&config.database_config, &task.name).await?;
+    let plan = db
+        .fetch_text_rows(&format!("EXPLAIN {}", sql))
+        .await
+        .context("EXPLAIN failed")?;
+    Ok(render_plan(&config.redact_secrets(&sql), &plan))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plan(rows: &[[Option<&str>; 5]]) -> TextRows {
+        let columns = ["id", "table", "type", "key", "rows"]
+            .iter()
+            .map(|column| column.to_string())
+            .collect();
+        let rows = rows
+            .iter()
+            .map(|row| row.iter().map(|value| value.map(str::to_string)).collect())
+            .collect();
+        (columns, rows)
+    }
+
+    #[test]
+    fn test_plan_warnings() {
+        let test_cases = vec![
+            (
+                plan(&[[
+                    Some("1"),
+                    Some("events"),
+                    Some("range"),
+                    Some("idx_created_at"),
+                    Some("1000"),
+                ]]),
+                vec![],
+            ),
+            (
+                plan(&[[Some("1"), Some("events"), Some("ALL"), None, Some("52000")]]),
+                vec!["Full table scan of `events`, reading about 52000 rows"],
+            ),
+            (
+                plan(&[
+                    [
+                        Some("1"),
+                        Some("events"),
+                        Some("range"),
+                        Some("PRIMARY"),
+                        Some("10"),
+                    ],
+                    [Some("1"), Some("users"), Some("ref"), None, None],
+                ]),
+                vec!["No index used on `users`, reading about ? rows"],
+            ),
+            // Nothing to read
+            (plan(&[[Some("1"), None, None, None, None]]), vec![]),
+        ];
+        for (plan, expected) in test_cases {
+            assert_eq!(plan_warnings(&plan), expected, "{:?}", plan);
+        }
+
+        let rendered = render_plan(
+            "DELETE FROM events LIMIT 10\n",
+            &plan(&[[Some("1"), Some("events"), Some("ALL"), None, Some("5")]]),
+        );
+        assert_eq!(
+            rendered,
+            "Query:\nDELETE FROM events LIMIT 10\n\nPlan:\nid | table  | type | key  | rows\n---+--------+------+------+-----\n1  | events | ALL  | NULL | 5\n\nWARNING: Full table scan of `events`, reading about 5 rows\n"
+        );
+    }
+}
@@ -0,0 +1,173 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    path::PathBuf,
+    sync::{Mutex, OnceLock},
+};
+
+use crate::cleaner::{config::RunHistoryConfig, run_id::current_run_id};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RunStatus {
+    Success,
+    Failure,
+}
+
+/// The outcome of one task run.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunRecord {
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub duration_seconds: f64,
+    pub status: RunStatus,
+    pub rows_deleted: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Id of the run shared by its logs and reports, see [`crate::cleaner::run_id`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<String>,
+}
+
+impl RunRecord {
+    /// The record of a run, tagged with the id of the current run if there is one.
+    pub fn new(
+        started_at: DateTime<Utc>,
+        finished_at: DateTime<Utc>,
+        rows_deleted: u64,
+        result: &Result<()>,
+    ) -> Self {
+        Self {
+            started_at,
+            finished_at,
+            duration_seconds: (finished_at - started_at).num_milliseconds() as f64 / 1000.0,
+            status: if result.is_ok() {
+                RunStatus::Success
+            } else {
+                RunStatus::Failure
+            },
+            rows_deleted,
+            error: result.as_ref().err().map(|e| format!("{:#}", e)),
+            run_id: current_run_id(),
+        }
+    }
+}
+
+/// The last runs of every task, oldest first, optionally persisted to a JSON file so the history
+/// survives restarts.
+#[derive(Debug)]
+pub struct RunHistory {
+    path: Option<PathBuf>,
+    max_runs_per_task: usize,
+    runs: Mutex<BTreeMap<String, VecDeque<RunRecord>>>,
+}
+
+static RUN_HISTORY: OnceLock<RunHistory> = OnceLock::new();
+
+/// Sets up the process-wide run history, loading previous runs from `config.path` if it exists.
+pub fn init(config: &RunHistoryConfig) -> Result<&'static RunHistory> {
+    let history = RunHistory::load(config)?;
+    Ok(RUN_HISTORY.get_or_init(|| history))
+}
+
+/// The process-wide run history, if [`init`] was called.
+pub fn run_history() -> Option<&'static RunHistory> {
+    RUN_HISTORY.get()
+}
+
+impl RunHistory {
+    pub fn load(config: &RunHistoryConfig) -> Result<Self> {
+        let path = config.path.as_ref().map(PathBuf::from);
+        let runs = match &path {
+            Some(path) if path.exists() => {
+                let content = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read run history: {}", path.display()))?;
+                serde_json::from_str(&content)
+                    .with_context(|| format!("Failed to parse run history: {}", path.display()))?
+            }
+            _ => BTreeMap::new(),
+        };
+        Ok(Self {
+            path,
+            max_runs_per_task: config.max_runs_per_task,
+            runs: Mutex::new(runs),
+        })
+    }
+
+    /// Appends a run, dropping the task's oldest runs beyond `max_runs_per_task`, and persists
+    /// the history. Failing to write the file is logged, not returned, so it never fails a task.
+    pub fn record(&self, task_name: &str, record: RunRecord) {
+        let mut runs = self.runs.lock().unwrap();
+        let task_runs = runs.entry(task_name.to_string()).or_default();
+        task_runs.push_back(record);
+        while task_runs.len() > self.max_runs_per_task {
+            task_runs.pop_front();
+        }
+        if let Err(e) = self.persist(&runs) {
+            warn!("Failed to persist run history: {:#}", e);
+        }
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, Vec<RunRecord>> {
+        self.runs
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(task_name, runs)| (task_name.clone(), runs.iter().cloned().collect()))
+            .collect()
+    }
+
+    fn persist(&self, runs: &BTreeMap<String, VecDeque<RunRecord>>) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        // Write to a temporary file first so a crash never leaves a truncated history
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, serde_json::to_string_pretty(runs)?)
+            .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+        std::fs::rename(&tmp_path, path)
+            .with_context(|| format!("Failed to replace {}", path.display()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_run_history_trims_and_persists() {
+        let path = std::env::temp_dir().join(format!("kiyoshi_runs_{}.json", std::process::id()));
+        let config = RunHistoryConfig {
+            path: Some(path.to_string_lossy().to_string()),
+            max_runs_per_task: 2,
+        };
+        let history = RunHistory::load(&config).unwrap();
+        let started_at = Utc::now();
+        for (rows, result) in [
+            (100, Ok(())),
+            (200, Err(anyhow::anyhow!("boom"))),
+            (300, Ok(())),
+        ] {
+            history.record(
+                "purge_logs",
+                RunRecord::new(started_at, started_at + Duration::seconds(2), rows, &result),
+            );
+        }
+
+        let runs = &history.snapshot()["purge_logs"];
+        let rows: Vec<_> = runs.iter().map(|run| run.rows_deleted).collect();
+        assert_eq!(rows, vec![200, 300]);
+        assert_eq!(runs[0].status, RunStatus::Failure);
+        assert_eq!(runs[0].error.as_deref(), Some("boom"));
+        assert_eq!(runs[1].duration_seconds, 2.0);
+
+        let reloaded = RunHistory::load(&config).unwrap();
+        assert_eq!(reloaded.snapshot(), history.snapshot());
+        std::fs::remove_file(path).unwrap();
+    }
+}
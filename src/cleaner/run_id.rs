@@ -0,0 +1,78 @@
+use std::{
+    collections::hash_map::RandomState,
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+tokio::task_local! {
+    static RUN_ID: String;
+}
+
+/// A random UUID (version 4) identifying one task run.
+pub fn new_run_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    // Every RandomState is seeded with fresh random keys, the counter and clock only make two
+    // ids of the same process differ even if the keys repeat
+    let random = || {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+        hasher.write_u128(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos(),
+        );
+        hasher.finish()
+    };
+    let bits = (u128::from(random()) << 64) | u128::from(random());
+    // Set the version to 4 and the variant to RFC 4122
+    let bits = (bits & !(0xf << 76) & !(0x3 << 62)) | (0x4 << 76) | (0x2 << 62);
+    let hex = format!("{:032x}", bits);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Runs a task run's `future` with `run_id` as the id of the current run, see [`current_run_id`].
+pub async fn with_run_id<F: Future>(run_id: String, future: F) -> F::Output {
+    RUN_ID.scope(run_id, future).await
+}
+
+/// The id of the task run being executed, to tag its logs, reports and records with.
+pub fn current_run_id() -> Option<String> {
+    RUN_ID.try_with(|run_id| run_id.clone()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run_id() {
+        let run_id = new_run_id();
+        assert_eq!(run_id.len(), 36);
+        let groups: Vec<&str> = run_id.split('-').collect();
+        assert_eq!(
+            groups.iter().map(|group| group.len()).collect::<Vec<_>>(),
+            vec![8, 4, 4, 4, 12]
+        );
+        assert!(groups[2].starts_with('4'), "{}", run_id);
+        assert!(
+            matches!(groups[3].chars().next(), Some('8' | '9' | 'a' | 'b')),
+            "{}",
+            run_id
+        );
+        assert_ne!(new_run_id(), run_id);
+
+        assert_eq!(current_run_id(), None);
+        let scoped = with_run_id(run_id.clone(), async { current_run_id() }).await;
+        assert_eq!(scoped, Some(run_id));
+    }
+}
@@ -0,0 +1,182 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+use crate::cleaner::{
+    config::{CleanupTask, Config},
+    run_history::RunRecord,
+};
+
+/// Version of the [`RunReport`] file, bumped on incompatible changes so consumers can evolve.
+pub const RUN_REPORT_SCHEMA_VERSION: u32 = 1;
+
+/// The full result of a task run, written to `report_dir` as one JSON file per run.
+#[derive(Debug, Serialize)]
+pub struct RunReport<'a> {
+    pub schema_version: u32,
+    pub task: &'a str,
+    /// Changes whenever the global config or the task's config changes, see [`config_hash`]
+    pub config_hash: String,
+    pub data_interval_end: DateTime<Utc>,
+    /// The rendered DELETE of every delete loop of the run, with secrets redacted
+    pub sql: Vec<String>,
+    pub batches: u32,
+    /// Time spent in DELETE statements, excluding pauses between batches
+    pub query_seconds: f64,
+    #[serde(flatten)]
+    pub run: &'a RunRecord,
+}
+
+/// A 64-bit FNV-1a hash of the global config and the task's config, with secrets redacted, as 16
+/// hex digits. It is stable across releases, so reports of two runs can be compared.
+pub fn config_hash(config: &Config, task: &CleanupTask) -> String {
+    let json = serde_json::to_string(&(config, task)).unwrap_or_default();
+    let hash = config
+        .redact_secrets(&json)
+        .bytes()
+        .fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+    format!("{:016x}", hash)
+}
+
+/// The report file of a run, named after the task and the run's start time.
+fn report_path(report_dir: &Path, task_name: &str, started_at: DateTime<Utc>) -> PathBuf {
+    let task_name: String = task_name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    report_dir.join(format!(
+        "{}_{}.json",
+        task_name,
+        started_at.format("%Y%m%dT%H%M%S%.3fZ")
+    ))
+}
+
+/// Writes the report to its own file in `report_dir`, creating the directory if needed.
+pub fn write_run_report(report_dir: &Path, report: &RunReport) -> Result<PathBuf> {
+    std::fs::create_dir_all(report_dir)
+        .with_context(|| format!("Failed to create {}", report_dir.display()))?;
+    let path = report_path(report_dir, report.task, report.run.started_at);
+    // Write to a temporary file first so readers never see a partial report
+    let tmp_path = path.with_extension("json.tmp");
+    std::fs::write(&tmp_path, serde_json::to_string_pretty(report)?)
+        .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+    std::fs::rename(&tmp_path, &path)
+        .with_context(|| format!("Failed to replace {}", path.display()))?;
+    Ok(path)
+}
+
+/// Writes a run's report if `report_dir` is configured. Failures are logged, not returned, so the
+/// report never fails a task.
+pub fn save_run_report(
+    config: &Config,
+    task: &CleanupTask,
+    data_interval_end: DateTime<Utc>,
+    sql: &[String],
+    batches: u32,
+    query_seconds: f64,
+    run: &RunRecord,
+) {
+    let Some(report_dir) = &config.report_dir else {
+        return;
+    };
+    let report = RunReport {
+        schema_version: RUN_REPORT_SCHEMA_VERSION,
+        task: &task.name,
+        config_hash: config_hash(config, task),
+        data_interval_end,
+        sql: sql.iter().map(|sql| config.redact_secrets(sql)).collect(),
+        batches,
+        query_seconds,
+        run,
+    };
+    match write_run_report(Path::new(report_dir), &report) {
+        Ok(path) => info!(
+            "Run report of task {} written to {}",
+            task.name,
+            path.display()
+        ),
+        Err(e) => warn!("Failed to write run report of task {}: {:#}", task.name, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_run_report() {
+        let dir = std::env::temp_dir().join(format!("kiyoshi_reports_{}", std::process::id()));
+        let config = Config::default();
+        let task: CleanupTask = serde_yaml::from_str(
+            r#"
+name: purge/logs
+description: test task
+cron_schedule: "0 0 * * * *"
+enabled: true
+template_query: "DELETE FROM logs LIMIT 1000"
+parameters: {}
+batch_size: 1000
+retry_attempts: 3
+retry_delay_seconds: 0
+"#,
+        )
+        .unwrap();
+        let started_at = DateTime::parse_from_rfc3339("2024-03-20T01:02:03.456Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let run = RunRecord::new(
+            started_at,
+            started_at + chrono::Duration::seconds(3),
+            1500,
+            &Err(anyhow::anyhow!("boom")),
+        );
+        let report = RunReport {
+            schema_version: RUN_REPORT_SCHEMA_VERSION,
+            task: &task.name,
+            config_hash: config_hash(&config, &task),
+            data_interval_end: started_at,
+            sql: vec!["DELETE FROM logs LIMIT 1000".to_string()],
+            batches: 2,
+            query_seconds: 1.5,
+            run: &run,
+        };
+
+        let path = write_run_report(&dir, &report).unwrap();
+        assert_eq!(path, dir.join("purge_logs_20240320T010203.456Z.json"));
+        let written: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(written["schema_version"], 1);
+        assert_eq!(written["task"], "purge/logs");
+        assert_eq!(written["config_hash"].as_str().unwrap().len(), 16);
+        assert_eq!(written["data_interval_end"], "2024-03-20T01:02:03.456Z");
+        assert_eq!(written["sql"][0], "DELETE FROM logs LIMIT 1000");
+        assert_eq!(written["batches"], 2);
+        assert_eq!(written["query_seconds"], 1.5);
+        assert_eq!(written["rows_deleted"], 1500);
+        assert_eq!(written["duration_seconds"], 3.0);
+        assert_eq!(written["status"], "failure");
+        assert_eq!(written["error"], "boom");
+        assert!(written["started_at"].is_string());
+        assert!(written["finished_at"].is_string());
+        // Only the report itself is left behind
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        // The hash follows the config
+        let mut changed = task.clone();
+        changed.batch_size = 500;
+        assert_eq!(config_hash(&config, &task), report.config_hash);
+        assert_ne!(config_hash(&config, &changed), report.config_hash);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
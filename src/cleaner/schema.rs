@@ -0,0 +1,348 @@
+use anyhow::{anyhow, Context, Result};
+use serde_json::{json, Value};
+
+use crate::cleaner::config::{resolve_config_value, FullConfig};
+
+/// The JSON Schema (draft 7) of the config file, for editors and pre-commit checks. Unknown fields
+/// are rejected by the schema although Kiyoshi itself ignores them, so typos are caught. Rules
+/// between fields, such as a `where_clause` requiring a `table_name`, are only checked when the
+/// config is loaded.
+pub fn config_schema() -> Value {
+    let mut schema = serde_json::to_value(schemars::schema_for!(FullConfig))
+        .expect("the config schema serializes to JSON");
+    schema["title"] = json!("Kiyoshi configuration");
+    // `profiles` is merged away before the config is deserialized, see `apply_profile`
+    schema["properties"]["profiles"] = json!({
+        "description": "Named overrides merged over the base settings with --profile",
+        "type": "object",
+        "additionalProperties": {"type": "object"},
+    });
+    schema
+}
+
+/// Checks `value` against `schema` and returns every violation, prefixed with the path of the
+/// offending value. Only the keywords [`config_schema`] uses are supported, and `oneOf` is checked
+/// like `anyOf` as its branches never overlap there.
+pub fn validate(schema: &Value, value: &Value) -> Vec<String> {
+    let mut errors = Vec::new();
+    check(schema, schema, value, "", &mut errors);
+    errors
+}
+
+/// Validates the config text against [`config_schema`] after substituting environment variables
+/// and applying the profile, failing with all violations at once.
+pub fn check_config_schema(config_str: &str, profile: Option<&str>) -> Result<()> {
+    let value = serde_json::to_value(resolve_config_value(config_str, profile)?)
+        .context("Failed to convert the configuration to JSON")?;
+    let errors = validate(&config_schema(), &value);
+    if errors.is_empty() {
+        return Ok(());
+    }
+    Err(anyhow!(
+        "Configuration does not match the schema ({} error(s)):\n  {}",
+        errors.len(),
+        errors.join("\n  ")
+    ))
+}
+
+fn check(root: &Value, schema: &Value, value: &Value, path: &str, errors: &mut Vec<String>) {
+    let schema = match schema {
+        Value::Object(schema) => schema,
+        Value::Bool(false) => return errors.push(format!("{}: not allowed", display(path))),
+        _ => return,
+    };
+    if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+        match resolve(root, reference) {
+            Some(target) => check(root, target, value, path, errors),
+            None => errors.push(format!(
+                "{}: unknown schema reference {}",
+                display(path),
+                reference
+            )),
+        }
+    }
+    for branch in schema
+        .get("allOf")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+    {
+        check(root, branch, value, path, errors);
+    }
+    for keyword in ["anyOf", "oneOf"] {
+        if let Some(branches) = schema.get(keyword).and_then(Value::as_array) {
+            check_branches(root, branches, value, path, errors);
+        }
+    }
+
+    if let Some(types) = schema.get("type") {
+        let types: Vec<&str> = match types {
+            Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+            types => types.as_str().into_iter().collect(),
+        };
+        if !types.iter().any(|type_name| has_type(value, type_name)) {
+            return errors.push(format!(
+                "{}: expected {}, found {}",
+                display(path),
+                types.join(" or "),
+                describe(value)
+            ));
+        }
+    }
+    if let Some(allowed) = schema.get("enum").and_then(Value::as_array) {
+        if !allowed.contains(value) {
+            return errors.push(not_one_of(path, value, allowed.iter()));
+        }
+    }
+    if let Some(minimum) = schema.get("minimum").and_then(Value::as_f64) {
+        if value.as_f64().is_some_and(|number| number < minimum) {
+            errors.push(format!(
+                "{}: {} is less than the minimum of {}",
+                display(path),
+                value,
+                minimum
+            ));
+        }
+    }
+
+    match value {
+        Value::Object(fields) => {
+            for name in schema
+                .get("required")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+            {
+                if !fields.contains_key(name) {
+                    errors.push(format!(
+                        "{}: missing required field `{}`",
+                        display(path),
+                        name
+                    ));
+                }
+            }
+            let properties = schema.get("properties").and_then(Value::as_object);
+            for (name, field) in fields {
+                let field_path = if path.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{}.{}", path, name)
+                };
+                match (
+                    properties.and_then(|properties| properties.get(name)),
+                    schema.get("additionalProperties"),
+                ) {
+                    (Some(field_schema), _) => {
+                        check(root, field_schema, field, &field_path, errors)
+                    }
+                    (None, Some(Value::Bool(false))) => {
+                        errors.push(format!("{}: unknown field `{}`", display(path), name))
+                    }
+                    (None, Some(additional)) => check(root, additional, field, &field_path, errors),
+                    (None, None) => {}
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(item_schema) = schema.get("items") {
+                for (index, item) in items.iter().enumerate() {
+                    check(
+                        root,
+                        item_schema,
+                        item,
+                        &format!("{}[{}]", path, index),
+                        errors,
+                    );
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Passes when any branch matches. Otherwise the violations are reported as precisely as the
+/// branches allow: the values of an enum, or the errors of the only branch that is not `null`.
+fn check_branches(
+    root: &Value,
+    branches: &[Value],
+    value: &Value,
+    path: &str,
+    errors: &mut Vec<String>,
+) {
+    let mut results = Vec::with_capacity(branches.len());
+    for branch in branches {
+        let mut branch_errors = Vec::new();
+        check(root, branch, value, path, &mut branch_errors);
+        if branch_errors.is_empty() {
+            return;
+        }
+        results.push(branch_errors);
+    }
+    // Enum variants with doc comments are one branch per variant
+    let allowed: Option<Vec<&Value>> = branches
+        .iter()
+        .map(|branch| branch.get("enum").and_then(Value::as_array))
+        .collect::<Option<Vec<_>>>()
+        .map(|values| values.into_iter().flatten().collect());
+    if let Some(allowed) = allowed {
+        return errors.push(not_one_of(path, value, allowed.into_iter()));
+    }
+    // An optional struct is a reference to the struct or `null`
+    let mut candidates: Vec<Vec<String>> = branches
+        .iter()
+        .zip(results)
+        .filter(|(branch, _)| branch.get("type") != Some(&json!("null")))
+        .map(|(_, branch_errors)| branch_errors)
+        .collect();
+    if candidates.len() == 1 {
+        errors.append(&mut candidates[0]);
+    } else {
+        errors.push(format!(
+            "{}: {} matches none of the allowed forms",
+            display(path),
+            describe(value)
+        ));
+    }
+}
+
+/// Looks up a local reference such as `#/definitions/CleanupTask`.
+fn resolve<'a>(root: &'a Value, reference: &str) -> Option<&'a Value> {
+    root.pointer(reference.strip_prefix('#')?)
+}
+
+fn has_type(value: &Value, type_name: &str) -> bool {
+    match type_name {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "string" => value.is_string(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        _ => true,
+    }
+}
+
+fn describe(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Bool(value) => format!("boolean {}", value),
+        Value::Number(value) => format!("number {}", value),
+        Value::String(_) => format!("string {}", value),
+        Value::Array(_) => "array".to_string(),
+        Value::Object(_) => "object".to_string(),
+    }
+}
+
+fn not_one_of<'a>(path: &str, value: &Value, allowed: impl Iterator<Item = &'a Value>) -> String {
+    format!(
+        "{}: {} is not one of {}",
+        display(path),
+        value,
+        allowed.map(Value::to_string).collect::<Vec<_>>().join(", ")
+    )
+}
+
+fn display(path: &str) -> &str {
+    if path.is_empty() {
+        "<root>"
+    } else {
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_schema() {
+        let schema = config_schema();
+        assert_eq!(schema["title"], "Kiyoshi configuration");
+        assert_eq!(schema["required"], json!(["cleanup_tasks", "config"]));
+        let task = &schema["definitions"]["CleanupTask"];
+        assert_eq!(task["additionalProperties"], false);
+        assert_eq!(
+            task["properties"]["enabled"]["type"],
+            json!(["boolean", "integer", "string"])
+        );
+        assert!(task["required"]
+            .as_array()
+            .unwrap()
+            .contains(&json!("batch_size")));
+
+        // The published schema is kept up to date
+        let published: Value =
+            serde_json::from_str(include_str!("../../config/kiyoshi.schema.json")).unwrap();
+        assert!(
+            published == schema,
+            "config/kiyoshi.schema.json is outdated, regenerate it with --print-schema"
+        );
+
+        // The shipped configs match the schema
+        for config_str in [
+            include_str!("../../config/example_config.yaml").to_string(),
+            crate::cleaner::config::generate_example_config(),
+        ] {
+            check_config_schema(&config_str, None).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let schema = config_schema();
+        let config = |tasks: Value| {
+            json!({
+                "config": {
+                    "database_config": {"host": "localhost", "username": "u", "password": "p", "database": "d"},
+                    "slack_config": {"enabled": "off", "bot_token": "t", "channel_id": "c"},
+                    "safe_mode": {"enabled": true},
+                },
+                "cleanup_tasks": tasks,
+            })
+        };
+        let task = json!({
+            "name": "purge",
+            "description": "d",
+            "cron_schedule": "0 0 * * * *",
+            "enabled": true,
+            "template_query": "DELETE FROM t LIMIT 10",
+            "parameters": {},
+            "batch_size": 10,
+            "retry_attempts": 3,
+            "retry_delay_seconds": 0,
+        });
+        assert_eq!(
+            validate(&schema, &config(json!([task.clone()]))),
+            Vec::<String>::new()
+        );
+
+        let mut broken = task.clone();
+        broken["batch_size"] = json!("ten");
+        broken["retry_attempts"] = json!(-1);
+        broken["delete_order"] = json!("random");
+        broken["bacth_size"] = json!(10);
+        broken["partition_drop"] = json!({"column": "created_at", "retention_days": "30"});
+        broken.as_object_mut().unwrap().remove("name");
+        // Sorted, as the order of the fields depends on serde_json's features
+        let mut errors = validate(&schema, &config(json!([task, broken])));
+        errors.sort();
+        assert_eq!(
+            errors,
+            vec![
+                "cleanup_tasks[1].batch_size: expected integer, found string \"ten\"",
+                "cleanup_tasks[1].delete_order: \"random\" is not one of \"oldest_first\", \"newest_first\"",
+                "cleanup_tasks[1].partition_drop.retention_days: expected integer, found string \"30\"",
+                "cleanup_tasks[1].retry_attempts: -1 is less than the minimum of 0",
+                "cleanup_tasks[1]: missing required field `name`",
+                "cleanup_tasks[1]: unknown field `bacth_size`",
+            ]
+        );
+
+        assert_eq!(
+            validate(&schema, &json!({"config": null, "cleanup_tasks": []})),
+            vec!["config: expected object, found null"]
+        );
+    }
+}
@@ -0,0 +1,411 @@
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+use reqwest::{header::RETRY_AFTER, StatusCode};
+use serde::Deserialize;
+use slack_api_client::{CreateMessage, SlackClient};
+use std::{cell::Cell, future::Future, time::Duration};
+
+use crate::cleaner::config::SlackConfig;
+
+const SLACK_API_URL: &str = "https://slack.com/api";
+
+#[derive(Debug, Deserialize)]
+struct AuthTestResponse {
+    ok: bool,
+    error: Option<String>,
+    team: Option<String>,
+    user: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PostMessageResponse {
+    ok: bool,
+    error: Option<String>,
+}
+
+/// Delay before the first retry of a failed send, doubled on every further retry
+const SEND_RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+
+/// A failed send attempt, and how long to wait before the next one if it may be retried.
+struct SendFailure {
+    error: anyhow::Error,
+    retry_after: Option<Duration>,
+    retryable: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct ConversationInfoResponse {
+    ok: bool,
+    error: Option<String>,
+    channel: Option<ConversationInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ConversationInfo {
+    name: Option<String>,
+    #[serde(default)]
+    is_member: bool,
+}
+
+/// The Slack reports a task run may still send, and how many it has suppressed.
+struct NotificationBudget {
+    remaining: Cell<Option<u32>>,
+    suppressed: Cell<u32>,
+}
+
+tokio::task_local! {
+    static NOTIFICATION_BUDGET: NotificationBudget;
+}
+
+/// Runs a task run's `future`, allowing it to send at most `max_notifications` Slack reports.
+/// Further reports are dropped and their count is logged once the run finishes.
+pub async fn with_notification_budget<F: Future>(
+    max_notifications: Option<u32>,
+    task_name: &str,
+    future: F,
+) -> F::Output {
+    let budget = NotificationBudget {
+        remaining: Cell::new(max_notifications),
+        suppressed: Cell::new(0),
+    };
+    NOTIFICATION_BUDGET
+        .scope(budget, async {
+            let output = future.await;
+            let suppressed = NOTIFICATION_BUDGET.with(|budget| budget.suppressed.get());
+            if suppressed > 0 {
+                warn!(
+                    "Suppressed {} Slack notifications of task {} beyond max_notifications_per_run \
+                     of {}",
+                    suppressed,
+                    task_name,
+                    max_notifications.unwrap_or_default()
+                );
+            }
+            output
+        })
+        .await
+}
+
+/// Takes one notification from the current run's budget, returning false once it is used up.
+/// Reports sent outside of a task run are not limited.
+fn take_notification() -> bool {
+    NOTIFICATION_BUDGET
+        .try_with(|budget| match budget.remaining.get() {
+            None => true,
+            Some(0) => {
+                budget.suppressed.set(budget.suppressed.get() + 1);
+                false
+            }
+            Some(remaining) => {
+                budget.remaining.set(Some(remaining - 1));
+                true
+            }
+        })
+        .unwrap_or(true)
+}
+
+/// Checks that the bot token is valid and the bot is a member of every configured channel, so that
+/// notification misconfiguration is found at startup rather than mid-incident.
+pub async fn validate_slack_config(slack_config: &SlackConfig) -> Result<()> {
+    let client = reqwest::Client::new();
+
+    let auth: AuthTestResponse = client
+        .post(format!("{}/auth.test", SLACK_API_URL))
+        .bearer_auth(&slack_config.bot_token)
+        .send()
+        .await
+        .context("Failed to call Slack auth.test")?
+        .json()
+        .await
+        .context("Failed to parse Slack auth.test response")?;
+    if !auth.ok {
+        return Err(anyhow!(
+            "Slack bot token is invalid: {}",
+            auth.error.unwrap_or_else(|| "unknown error".to_string())
+        ));
+    }
+    info!(
+        "Slack bot token is valid for user {} in team {}",
+        auth.user.unwrap_or_default(),
+        auth.team.unwrap_or_default()
+    );
+
+    for channel_id in slack_config.all_channels() {
+        let conversation: ConversationInfoResponse = client
+            .get(format!("{}/conversations.info", SLACK_API_URL))
+            .bearer_auth(&slack_config.bot_token)
+            .query(&[("channel", &channel_id)])
+            .send()
+            .await
+            .context("Failed to call Slack conversations.info")?
+            .json()
+            .await
+            .context("Failed to parse Slack conversations.info response")?;
+        if !conversation.ok {
+            return Err(anyhow!(
+                "Slack channel {} is not accessible: {}",
+                channel_id,
+                conversation
+                    .error
+                    .unwrap_or_else(|| "unknown error".to_string())
+            ));
+        }
+        let channel = conversation
+            .channel
+            .ok_or_else(|| anyhow!("Slack conversations.info returned no channel"))?;
+        if !channel.is_member {
+            return Err(anyhow!(
+                "Slack bot is not a member of channel {} ({})",
+                channel_id,
+                channel.name.unwrap_or_default()
+            ));
+        }
+        info!("Slack bot is a member of channel {}", channel_id);
+    }
+    Ok(())
+}
+
+/// Sends a report to `channel`, giving each attempt `timeout_seconds` and retrying timeouts,
+/// server errors and rate limits up to `send_retries` times with exponential backoff. A 429's
+/// `Retry-After` is waited instead of the backoff. Errors such as an unknown channel are returned
+/// without retrying. Reports beyond the run's `max_notifications_per_run` are not sent.
+pub async fn send_report(
+    slack_client: &SlackClient,
+    slack_config: &SlackConfig,
+    channel: String,
+    report: &CreateMessage,
+) -> Result<()> {
+    if !take_notification() {
+        return Err(anyhow!(
+            "Report suppressed, max_notifications_per_run reached"
+        ));
+    }
+    let timeout = Duration::from_secs_f64(slack_config.timeout_seconds);
+    let CreateMessage::Blocks(blocks) = report else {
+        // Only block reports are sent directly, anything else goes through the client as is
+        return tokio::time::timeout(timeout, report.send_to_channel(slack_client, channel))
+            .await
+            .map_err(|_| anyhow!("Slack send timed out after {:?}", timeout))?
+            .map(|_| ());
+    };
+    let client = reqwest::Client::builder()
+        .timeout(timeout)
+        .build()
+        .context("Failed to build Slack HTTP client")?;
+    let body = serde_json::json!({ "channel": channel, "blocks": blocks });
+    send_with_retries(
+        &client,
+        SLACK_API_URL,
+        &slack_config.bot_token,
+        &body,
+        slack_config.send_retries,
+        SEND_RETRY_BASE_DELAY,
+    )
+    .await
+}
+
+async fn send_with_retries(
+    client: &reqwest::Client,
+    api_url: &str,
+    bot_token: &str,
+    body: &serde_json::Value,
+    retries: u32,
+    base_delay: Duration,
+) -> Result<()> {
+    let mut attempt = 0;
+    loop {
+        let failure = match post_message(client, api_url, bot_token, body).await {
+            Ok(()) => return Ok(()),
+            Err(failure) => failure,
+        };
+        if !failure.retryable || attempt >= retries {
+            return Err(failure
+                .error
+                .context(format!("Slack send failed after {} attempts", attempt + 1)));
+        }
+        let delay = failure
+            .retry_after
+            .unwrap_or(base_delay * 2u32.saturating_pow(attempt));
+        warn!(
+            "Slack send failed, retrying in {:?} ({}/{}): {:#}",
+            delay,
+            attempt + 1,
+            retries,
+            failure.error
+        );
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+async fn post_message(
+    client: &reqwest::Client,
+    api_url: &str,
+    bot_token: &str,
+    body: &serde_json::Value,
+) -> Result<(), SendFailure> {
+    let retryable = |error: anyhow::Error| SendFailure {
+        error,
+        retry_after: None,
+        retryable: true,
+    };
+    let response = client
+        .post(format!("{}/chat.postMessage", api_url))
+        .bearer_auth(bot_token)
+        .json(body)
+        .send()
+        .await
+        .map_err(|e| retryable(anyhow!("Failed to call Slack chat.postMessage: {}", e)))?;
+
+    let status = response.status();
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<u64>().ok())
+            .map(Duration::from_secs);
+        return Err(SendFailure {
+            error: anyhow!("Slack rate limited chat.postMessage"),
+            retry_after,
+            retryable: true,
+        });
+    }
+    if !status.is_success() {
+        return Err(SendFailure {
+            error: anyhow!("Slack chat.postMessage returned HTTP {}", status),
+            retry_after: None,
+            retryable: status.is_server_error(),
+        });
+    }
+
+    let response: PostMessageResponse = response.json().await.map_err(|e| {
+        retryable(anyhow!(
+            "Failed to parse Slack chat.postMessage response: {}",
+            e
+        ))
+    })?;
+    if response.ok {
+        return Ok(());
+    }
+    let error = response
+        .error
+        .unwrap_or_else(|| "unknown error".to_string());
+    Err(SendFailure {
+        retryable: error == "ratelimited",
+        error: anyhow!("Slack chat.postMessage failed: {}", error),
+        retry_after: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cleaner::config::Config;
+    use axum::{http::HeaderMap, routing::post, Json, Router};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    /// Serves `chat.postMessage`, rate limiting the first `rate_limited` calls.
+    async fn serve_slack(
+        rate_limited: usize,
+        error: Option<&'static str>,
+    ) -> (String, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let app = Router::new().route(
+            "/chat.postMessage",
+            post(move || {
+                let call = counter.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    let mut headers = HeaderMap::new();
+                    if call < rate_limited {
+                        headers.insert(RETRY_AFTER, "0".parse().unwrap());
+                        return (
+                            StatusCode::TOO_MANY_REQUESTS,
+                            headers,
+                            Json(serde_json::json!({})),
+                        );
+                    }
+                    let body = match error {
+                        Some(error) => serde_json::json!({ "ok": false, "error": error }),
+                        None => serde_json::json!({ "ok": true }),
+                    };
+                    (StatusCode::OK, headers, Json(body))
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (format!("http://{}", address), calls)
+    }
+
+    #[tokio::test]
+    async fn test_send_with_retries() {
+        // (rate limited calls, Slack error, retries, expected success, expected calls)
+        let test_cases = vec![
+            (0, None, 3, true, 1),
+            (2, None, 3, true, 3),
+            (3, None, 2, false, 3),
+            // Not retried, another attempt would fail the same way
+            (0, Some("channel_not_found"), 3, false, 1),
+            (0, Some("ratelimited"), 2, false, 3),
+        ];
+        for (rate_limited, error, retries, success, expected_calls) in test_cases {
+            let (api_url, calls) = serve_slack(rate_limited, error).await;
+            let result = send_with_retries(
+                &reqwest::Client::new(),
+                &api_url,
+                "xoxb-test",
+                &serde_json::json!({ "channel": "C0123", "blocks": [] }),
+                retries,
+                Duration::ZERO,
+            )
+            .await;
+            assert_eq!(result.is_ok(), success, "{} {:?}", rate_limited, error);
+            assert_eq!(
+                calls.load(Ordering::SeqCst),
+                expected_calls,
+                "{} {:?}",
+                rate_limited,
+                error
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_notification_budget() {
+        // Outside of a run nothing is limited
+        assert!(take_notification());
+
+        let taken = with_notification_budget(Some(2), "purge_logs", async {
+            (0..3).map(|_| take_notification()).collect::<Vec<_>>()
+        })
+        .await;
+        assert_eq!(taken, vec![true, true, false]);
+
+        let taken = with_notification_budget(None, "purge_logs", async {
+            (0..3).map(|_| take_notification()).collect::<Vec<_>>()
+        })
+        .await;
+        assert_eq!(taken, vec![true, true, true]);
+
+        // The third report of a run is suppressed without calling Slack
+        let slack_config = Config::default().slack_config;
+        let slack_client = SlackClient::new(slack_config.bot_token.clone());
+        let report = CreateMessage::Blocks(serde_json::json!([]));
+        let result = with_notification_budget(Some(2), "purge_logs", async {
+            take_notification();
+            take_notification();
+            send_report(&slack_client, &slack_config, "C0123".to_string(), &report).await
+        })
+        .await;
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("max_notifications_per_run"));
+    }
+}
@@ -1,37 +1,88 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use sqlparser::{
-    ast::{self},
-    dialect::MySqlDialect,
+    ast::{self, DateTimeField, FromTable},
     parser::Parser,
+    tokenizer::{Token, Tokenizer, Whitespace},
 };
 
-use crate::cleaner::config::Config;
+use crate::cleaner::{
+    config::{Config, SqlDialect},
+    db::QueryExecutor,
+};
 
+#[derive(Clone, Copy)]
 pub struct SqlValidator<'a> {
     config: &'a Config,
+    /// Column the retention DATE_SUB has to be compared against, any column when `None`
+    retention_column: Option<&'a str>,
+    /// Whether an `IN (...)` list of looked up values may stand in for the DATE_SUB
+    lookup_in_list: bool,
+    /// Minimum retention interval of the DATE_SUB, in days
+    retention_days: u64,
 }
 
 impl<'a> SqlValidator<'a> {
     pub fn new(config: &'a Config) -> Self {
-        Self { config }
+        Self {
+            config,
+            retention_column: None,
+            lookup_in_list: false,
+            retention_days: config.safe_mode.retention_days,
+        }
     }
 
-    pub fn validate_sql_query(&self, sql: &str) -> Result<(), anyhow::Error> {
-        let dialect = MySqlDialect {};
-        let ast = Parser::parse_sql(&dialect, sql)
-            .map_err(|e| anyhow::anyhow!("Failed to parse SQL: {}", e))?;
-
-        if ast.len() != 1 {
-            return Err(anyhow::anyhow!("Only single SQL statement is allowed"));
+    /// Requires the task's `min_retention_days` instead of `safe_mode.retention_days`, if set.
+    pub fn with_min_retention_days(mut self, min_retention_days: Option<u64>) -> Self {
+        if let Some(days) = min_retention_days {
+            self.retention_days = days;
         }
+        self
+    }
 
-        let stmt = &ast[0];
+    /// For a task with a `lookup`, accepts a non-empty `IN (...)` list instead of the DATE_SUB
+    /// condition if `safe_mode.allow_lookup_in_list` is set.
+    pub fn with_lookup(mut self, has_lookup: bool) -> Self {
+        self.lookup_in_list = has_lookup && self.config.safe_mode.allow_lookup_in_list;
+        self
+    }
+
+    /// Only accepts a DATE_SUB retention compared against `column`, e.g. `created_at <
+    /// DATE_SUB(..)`, so a retention on another column cannot stand in for it.
+    pub fn with_retention_column(mut self, column: Option<&'a str>) -> Self {
+        self.retention_column = column;
+        self
+    }
+
+    pub fn validate_sql_query(&self, sql: &str) -> Result<(), anyhow::Error> {
+        let stmt = single_statement(parse_sql(
+            sql,
+            self.config.database_config.dialect,
+            self.config.safe_mode.allow_optimizer_hints,
+        )?)?;
 
         // Check if it's a DELETE statement and extract the WHERE clause
-        let (selection, ..) = match stmt {
-            sqlparser::ast::Statement::Delete(delete) => (&delete.selection, &delete.from),
+        let delete = match &stmt {
+            sqlparser::ast::Statement::Delete(delete) => delete,
             _ => return Err(anyhow::anyhow!("Only DELETE statements are allowed")),
         };
+        // MySQL `DELETE ... ORDER BY id LIMIT n` deletes in index order, which keeps batches cheap
+        if self.config.safe_mode.require_order_by && delete.order_by.is_empty() {
+            return Err(anyhow::anyhow!(
+                "DELETE statement must have an ORDER BY clause (safe_mode.require_order_by)"
+            ));
+        }
+        // Without an ORDER BY every batch deletes an arbitrary N rows, not the oldest ones
+        if self.config.safe_mode.require_deterministic_limit
+            && delete.limit.is_some()
+            && delete.order_by.is_empty()
+        {
+            return Err(anyhow::anyhow!(
+                "DELETE statement with a LIMIT must have an ORDER BY clause, e.g. `ORDER BY id \
+                 LIMIT 1000` on a unique indexed column, so every batch deletes the oldest rows \
+                 (safe_mode.require_deterministic_limit)"
+            ));
+        }
+        let selection = &delete.selection;
         if selection.is_none() {
             return Err(anyhow::anyhow!(
                 "DELETE statement must have a WHERE clause and FROM clause"
@@ -39,6 +90,12 @@ impl<'a> SqlValidator<'a> {
         } else {
             let selection = selection.as_ref().unwrap();
             if !self.contains_date_sub(selection) {
+                if let Some(column) = self.retention_column {
+                    return Err(anyhow::anyhow!(
+                        "DELETE statement must compare retention_column `{}` against DATE_SUB",
+                        column
+                    ));
+                }
                 return Err(anyhow::anyhow!(
                     "DELETE statement must use DATE_SUB function"
                 ));
@@ -48,53 +105,74 @@ impl<'a> SqlValidator<'a> {
         Ok(())
     }
 
-    fn validate_interval(&self, interval: &ast::Interval) -> bool {
-        match (&*interval.value, &interval.leading_field) {
-            (
-                ast::Expr::Value(ast::Value::Number(value, false)), // false means not negative
-                Some(ast::DateTimeField::Month),
-            ) => {
-                if let Ok(months) = value.parse::<u64>() {
-                    months * 30 >= self.config.safe_mode.retention_days
-                } else {
-                    false
-                }
-            }
-            (
-                ast::Expr::Value(ast::Value::Number(value, false)),
-                Some(ast::DateTimeField::Year),
-            ) => {
-                if let Ok(years) = value.parse::<u64>() {
-                    years * 365 >= self.config.safe_mode.retention_days
-                } else {
-                    false
-                }
-            }
-            (ast::Expr::Value(ast::Value::Number(value, false)), Some(ast::DateTimeField::Day)) => {
-                if let Ok(days) = value.parse::<u64>() {
-                    days >= self.config.safe_mode.retention_days
-                } else {
-                    false
-                }
-            }
-            _ => false,
+    /// Accepts a `warmup_query` that only reads or fills a temporary table: a single SELECT or
+    /// CREATE TEMPORARY TABLE statement.
+    pub fn validate_warmup_query(&self, sql: &str) -> Result<()> {
+        let stmt = single_statement(parse_sql(
+            sql,
+            self.config.database_config.dialect,
+            self.config.safe_mode.allow_optimizer_hints,
+        )?)?;
+        match stmt {
+            ast::Statement::Query(_) => Ok(()),
+            ast::Statement::CreateTable(create_table) if create_table.temporary => Ok(()),
+            _ => Err(anyhow!(
+                "warmup_query must be a SELECT or CREATE TEMPORARY TABLE statement"
+            )),
         }
     }
 
+    /// Accepts a single DELETE with a LIMIT, as a `child_cleanup` step has to delete in batches
+    /// whether or not safe mode is enabled.
+    pub fn validate_bounded_delete(&self, sql: &str) -> Result<()> {
+        let stmt = single_statement(parse_sql(
+            sql,
+            self.config.database_config.dialect,
+            self.config.safe_mode.allow_optimizer_hints,
+        )?)?;
+        match stmt {
+            ast::Statement::Delete(delete) if delete.limit.is_some() => Ok(()),
+            ast::Statement::Delete(_) => Err(anyhow!("DELETE statement must have a LIMIT")),
+            _ => Err(anyhow!("Only DELETE statements are allowed")),
+        }
+    }
+
+    fn validate_interval(&self, interval: &ast::Interval) -> bool {
+        interval_days(interval).is_some_and(|days| days >= self.retention_days as f64)
+    }
+
     fn contains_date_sub(&self, expr: &ast::Expr) -> bool {
         match expr {
             ast::Expr::BinaryOp {
                 left, right, op, ..
             } => {
-                if op == &ast::BinaryOperator::Lt
-                    || op == &ast::BinaryOperator::LtEq
-                    || op == &ast::BinaryOperator::And
-                {
+                let comparison = op == &ast::BinaryOperator::Lt || op == &ast::BinaryOperator::LtEq;
+                if let (true, Some(column)) = (comparison, self.retention_column) {
+                    is_column(left, column) && self.contains_date_sub(right)
+                } else if comparison || op == &ast::BinaryOperator::And {
                     self.contains_date_sub(left) || self.contains_date_sub(right)
                 } else {
                     false
                 }
             }
+            ast::Expr::Nested(expr) => self.contains_date_sub(expr),
+            ast::Expr::InList {
+                negated: false,
+                list,
+                ..
+            } => self.lookup_in_list && !list.is_empty(),
+            // Only the upper bound limits how recent the deleted rows can be, so the retention
+            // interval has to be on the high side, e.g. `dt BETWEEN '2020-01-01' AND DATE_SUB(..)`
+            ast::Expr::Between {
+                negated: false,
+                expr,
+                high,
+                ..
+            } => {
+                self.retention_column
+                    .is_none_or(|column| is_column(expr, column))
+                    && self.contains_date_sub(high)
+            }
             ast::Expr::Function(ast::Function { name, args, .. }) => {
                 let function_name = name.to_string().to_uppercase();
                 if function_name == "DATE_SUB" {
@@ -177,6 +255,342 @@ impl<'a> SqlValidator<'a> {
     }
 }
 
+/// Whether `expr` is the column `column`, ignoring case, quotes and any table or schema
+/// qualifier on either side.
+fn is_column(expr: &ast::Expr, column: &str) -> bool {
+    let name = match expr {
+        ast::Expr::Identifier(ident) => ident,
+        ast::Expr::CompoundIdentifier(idents) => match idents.last() {
+            Some(ident) => ident,
+            None => return false,
+        },
+        _ => return false,
+    };
+    let column = column.rsplit('.').next().unwrap_or(column);
+    name.value
+        .eq_ignore_ascii_case(column.trim_matches(|c| c == '`' || c == '"'))
+}
+
+/// MySQL's compound interval units, with the leading and last fields of the equivalent ANSI
+/// `<leading> TO <last>` interval qualifier that the parser understands.
+const COMPOUND_INTERVAL_UNITS: &[(&str, &str, &str)] = &[
+    ("YEAR_MONTH", "YEAR", "MONTH"),
+    ("DAY_HOUR", "DAY", "HOUR"),
+    ("DAY_MINUTE", "DAY", "MINUTE"),
+    ("DAY_SECOND", "DAY", "SECOND"),
+    ("DAY_MICROSECOND", "DAY", "MICROSECOND"),
+    ("HOUR_MINUTE", "HOUR", "MINUTE"),
+    ("HOUR_SECOND", "HOUR", "SECOND"),
+    ("HOUR_MICROSECOND", "HOUR", "MICROSECOND"),
+    ("MINUTE_SECOND", "MINUTE", "SECOND"),
+    ("MINUTE_MICROSECOND", "MINUTE", "MICROSECOND"),
+];
+
+/// MySQL DELETE modifiers that only affect how the delete is carried out, not which rows it
+/// removes
+const DELETE_MODIFIERS: &[&str] = &["LOW_PRIORITY", "QUICK"];
+
+/// Parses SQL for validation. MySQL syntax the parser does not support is adjusted in the tokens
+/// first: compound interval units such as `YEAR_MONTH` are rewritten to `YEAR TO MONTH`, and the
+/// `LOW_PRIORITY` and `QUICK` DELETE modifiers are dropped. The modifiers and optimizer hint
+/// comments (`/*+ ... */`) are rejected unless `allow_optimizer_hints` is set. Trailing
+/// semicolons and comments are dropped, so they never count as an extra statement.
+fn parse_sql(
+    sql: &str,
+    dialect: SqlDialect,
+    allow_optimizer_hints: bool,
+) -> Result<Vec<ast::Statement>> {
+    let dialect = dialect.parser_dialect();
+    let mut tokens = Vec::new();
+    let mut after_delete = false;
+    for token in Tokenizer::new(dialect.as_ref(), sql)
+        .tokenize()
+        .map_err(|e| anyhow!("Failed to parse SQL: {}", e))?
+    {
+        let word = match &token {
+            Token::Word(word) if word.quote_style.is_none() => Some(word.value.as_str()),
+            Token::Whitespace(Whitespace::MultiLineComment(comment))
+                if comment.starts_with('+') =>
+            {
+                if !allow_optimizer_hints {
+                    return Err(anyhow!(
+                        "Optimizer hints require safe_mode.allow_optimizer_hints: /*{}*/",
+                        comment
+                    ));
+                }
+                None
+            }
+            _ => None,
+        };
+        if let Token::Whitespace(_) = token {
+            tokens.push(token);
+            continue;
+        }
+        if let Some(modifier) = word.filter(|word| {
+            after_delete
+                && DELETE_MODIFIERS
+                    .iter()
+                    .any(|modifier| word.eq_ignore_ascii_case(modifier))
+        }) {
+            if !allow_optimizer_hints {
+                return Err(anyhow!(
+                    "DELETE {} requires safe_mode.allow_optimizer_hints",
+                    modifier.to_uppercase()
+                ));
+            }
+            continue;
+        }
+        after_delete = word.is_some_and(|word| word.eq_ignore_ascii_case("DELETE"));
+        let compound_unit = word.and_then(|word| {
+            COMPOUND_INTERVAL_UNITS
+                .iter()
+                .find(|(unit, _, _)| word.eq_ignore_ascii_case(unit))
+        });
+        match compound_unit {
+            Some((_, leading, last)) => tokens.extend([
+                Token::make_keyword(leading),
+                Token::Whitespace(Whitespace::Space),
+                Token::make_keyword("TO"),
+                Token::Whitespace(Whitespace::Space),
+                Token::make_keyword(last),
+            ]),
+            None => tokens.push(token),
+        }
+    }
+    while matches!(tokens.last(), Some(Token::SemiColon | Token::Whitespace(_))) {
+        tokens.pop();
+    }
+    Parser::new(dialect.as_ref())
+        .with_tokens(tokens)
+        .parse_statements()
+        .map_err(|e| anyhow!("Failed to parse SQL: {}", e))
+}
+
+/// The only statement of a parsed query. Several statements are listed in the error, as a
+/// template can render a second statement unnoticed, e.g. from a parameter.
+fn single_statement(mut ast: Vec<ast::Statement>) -> Result<ast::Statement> {
+    match ast.len() {
+        0 => Err(anyhow!(
+            "Found no SQL statement, the query is empty or only comments"
+        )),
+        1 => Ok(ast.remove(0)),
+        count => Err(anyhow!(
+            "Found {} statements; only 1 allowed: {}",
+            count,
+            ast.iter()
+                .map(|stmt| format!("[{}]", stmt))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+/// Interval fields from the largest to the smallest, with their length in days. Months and years
+/// count as 30 and 365 days.
+const INTERVAL_FIELD_DAYS: &[(DateTimeField, f64)] = &[
+    (DateTimeField::Year, 365.0),
+    (DateTimeField::Month, 30.0),
+    (DateTimeField::Day, 1.0),
+    (DateTimeField::Hour, 1.0 / 24.0),
+    (DateTimeField::Minute, 1.0 / 1440.0),
+    (DateTimeField::Second, 1.0 / 86400.0),
+    (DateTimeField::Microsecond, 1.0 / 86_400_000_000.0),
+];
+
+/// The length of a non-negative interval in days, e.g. 30 for `INTERVAL 1 MONTH` and 547 for
+/// `INTERVAL '1-6' YEAR TO MONTH`. Like MySQL, a compound value with fewer parts than fields
+/// fills the smallest fields, so `'5' DAY TO HOUR` is 5 hours. `None` for any other interval.
+fn interval_days(interval: &ast::Interval) -> Option<f64> {
+    let value = match &*interval.value {
+        ast::Expr::Value(ast::Value::Number(value, false)) => value.as_str(),
+        ast::Expr::Value(ast::Value::SingleQuotedString(value)) => value.trim(),
+        _ => return None,
+    };
+    if value.starts_with('-') {
+        return None;
+    }
+    // MySQL accepts any punctuation between the parts of a compound value, e.g. '1 12:30'
+    let parts = value
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|part| !part.is_empty())
+        .map(|part| part.parse::<u64>().ok())
+        .collect::<Option<Vec<_>>>()?;
+
+    let position = |field: &DateTimeField| {
+        INTERVAL_FIELD_DAYS
+            .iter()
+            .position(|(interval_field, _)| interval_field == field)
+    };
+    let leading = position(interval.leading_field.as_ref()?)?;
+    let last = match &interval.last_field {
+        Some(field) => position(field)?,
+        None => leading,
+    };
+    if last < leading || parts.is_empty() || parts.len() > last - leading + 1 {
+        return None;
+    }
+    let fields = &INTERVAL_FIELD_DAYS[last + 1 - parts.len()..=last];
+    Some(
+        parts
+            .iter()
+            .zip(fields)
+            .map(|(part, (_, days))| *part as f64 * days)
+            .sum(),
+    )
+}
+
+/// The table a DELETE statement removes rows from and the columns its WHERE clause filters on.
+#[derive(Debug, PartialEq)]
+pub struct DeletePredicate {
+    pub schema: Option<String>,
+    pub table: String,
+    pub columns: Vec<String>,
+}
+
+pub fn delete_predicate(sql: &str, dialect: SqlDialect) -> Result<DeletePredicate> {
+    // Only the target and its columns are needed here, the query itself was validated already
+    let ast = parse_sql(sql, dialect, true)?;
+    let delete = match ast.as_slice() {
+        [ast::Statement::Delete(delete)] => delete,
+        _ => return Err(anyhow!("Only a single DELETE statement is allowed")),
+    };
+
+    let tables = match &delete.from {
+        FromTable::WithFromKeyword(tables) | FromTable::WithoutKeyword(tables) => tables,
+    };
+    let name = match tables.as_slice() {
+        [ast::TableWithJoins {
+            relation: ast::TableFactor::Table { name, .. },
+            joins,
+        }] if joins.is_empty() && delete.using.is_none() => name,
+        _ => {
+            return Err(anyhow!(
+                "Only single-table DELETE statements can be checked for indexed predicates"
+            ))
+        }
+    };
+    let (schema, table) = match name.0.as_slice() {
+        [table] => (None, table.value.clone()),
+        [schema, table] => (Some(schema.value.clone()), table.value.clone()),
+        _ => return Err(anyhow!("Unsupported table name: {}", name)),
+    };
+
+    let mut columns = Vec::new();
+    if let Some(selection) = &delete.selection {
+        collect_columns(selection, &mut columns);
+    }
+    Ok(DeletePredicate {
+        schema,
+        table,
+        columns,
+    })
+}
+
+/// Collects the columns of the target table an expression filters on. Subqueries are not
+/// descended into, since their columns belong to other scans.
+fn collect_columns(expr: &ast::Expr, columns: &mut Vec<String>) {
+    match expr {
+        ast::Expr::Identifier(ident) => columns.push(ident.value.clone()),
+        ast::Expr::CompoundIdentifier(parts) => {
+            if let Some(column) = parts.last() {
+                columns.push(column.value.clone());
+            }
+        }
+        ast::Expr::BinaryOp { left, right, .. } => {
+            collect_columns(left, columns);
+            collect_columns(right, columns);
+        }
+        ast::Expr::Nested(expr)
+        | ast::Expr::UnaryOp { expr, .. }
+        | ast::Expr::IsNull(expr)
+        | ast::Expr::IsNotNull(expr)
+        | ast::Expr::Between { expr, .. }
+        | ast::Expr::InList { expr, .. }
+        | ast::Expr::InSubquery { expr, .. }
+        | ast::Expr::Like { expr, .. } => collect_columns(expr, columns),
+        _ => {}
+    }
+}
+
+/// The literal LIMIT of a single DELETE statement, if it has one.
+fn delete_limit(sql: &str, dialect: SqlDialect) -> Option<u64> {
+    match parse_sql(sql, dialect, true).ok()?.as_slice() {
+        [ast::Statement::Delete(ast::Delete {
+            limit: Some(ast::Expr::Value(ast::Value::Number(limit, _))),
+            ..
+        })] => limit.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Lowers the literal LIMIT of a DELETE statement to `limit` when it is larger, so a batch
+/// deletes no more rows than a run has left. The rest of the query is kept as written. Returns
+/// `None` when the statement has no literal LIMIT to lower.
+pub fn lower_delete_limit(sql: &str, dialect: SqlDialect, limit: u64) -> Option<String> {
+    let current = delete_limit(sql, dialect)?;
+    if current <= limit {
+        return Some(sql.to_string());
+    }
+    let parser_dialect = dialect.parser_dialect();
+    let mut tokens = Tokenizer::new(parser_dialect.as_ref(), sql)
+        .tokenize()
+        .ok()?;
+    // The statement's LIMIT comes last, after any LIMIT of a subquery
+    let mut previous_word = None;
+    let mut position = None;
+    for (index, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Whitespace(_) => continue,
+            Token::Number(number, _)
+                if previous_word.is_some_and(|word: &str| word.eq_ignore_ascii_case("LIMIT"))
+                    && number.parse::<u64>().ok() == Some(current) =>
+            {
+                position = Some(index)
+            }
+            _ => {}
+        }
+        previous_word = match token {
+            Token::Word(word) if word.quote_style.is_none() => Some(word.value.as_str()),
+            _ => None,
+        };
+    }
+    tokens[position?] = Token::Number(limit.to_string(), false);
+    let lowered: String = tokens.iter().map(ToString::to_string).collect();
+    (delete_limit(&lowered, dialect) == Some(limit)).then_some(lowered)
+}
+
+/// Fails unless at least one column in the DELETE's WHERE clause leads an index on the target
+/// table, so a scheduled cleanup cannot fall back to a full table scan.
+pub async fn check_indexed_predicate<E: QueryExecutor>(
+    executor: &E,
+    config: &Config,
+    sql: &str,
+) -> Result<()> {
+    let database_config = &config.database_config;
+    let predicate = delete_predicate(sql, database_config.dialect)?;
+    let schema = predicate
+        .schema
+        .as_deref()
+        .unwrap_or(&database_config.database);
+    let indexed_columns = executor.indexed_columns(schema, &predicate.table).await?;
+    let is_indexed = predicate.columns.iter().any(|column| {
+        indexed_columns
+            .iter()
+            .any(|indexed| indexed.eq_ignore_ascii_case(column))
+    });
+    if is_indexed {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "None of the WHERE clause columns ({}) are indexed on {}.{} \
+             (safe_mode.require_indexed_predicate)",
+            predicate.columns.join(", "),
+            schema,
+            predicate.table
+        ))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -216,8 +630,9 @@ mod tests {
                 .unwrap();
 
             // Parse SQL and print AST
-            let dialect = MySqlDialect {};
-            let ast = Parser::parse_sql(&dialect, &sql).unwrap();
+            let config = Config::default();
+            let dialect = config.database_config.dialect.parser_dialect();
+            let ast = Parser::parse_sql(dialect.as_ref(), &sql).unwrap();
 
             println!("Rendered SQL:\n{}\n", sql);
             println!("SQL AST:\n{:#?}", ast);
@@ -229,11 +644,616 @@ mod tests {
                 _ => panic!("Expected DELETE statement"),
             }
 
-            let config = Config::default();
             let validator = SqlValidator::new(&config);
             let is_query_valid = validator.validate_sql_query(&sql);
             println!("is_query_valid: {:?}", is_query_valid);
             assert!(is_query_valid.is_ok(), "Query should be valid");
         }
     }
+
+    #[test]
+    fn test_sql_validate_between() {
+        let test_cases = vec![
+            (
+                "DELETE FROM events WHERE dt BETWEEN '2020-01-01' AND DATE_SUB('2024-03-20 00:00:00', INTERVAL 30 DAY) LIMIT 1000;",
+                true,
+            ),
+            (
+                "DELETE FROM events WHERE dt BETWEEN '2020-01-01' AND DATE_FORMAT(DATE_SUB('2024-03-20 00:00:00', INTERVAL 2 MONTH), '%Y-%m-%d') LIMIT 1000;",
+                true,
+            ),
+            (
+                "DELETE FROM events WHERE status = 'done' AND dt BETWEEN '2020-01-01' AND DATE_SUB('2024-03-20 00:00:00', INTERVAL 1 YEAR) LIMIT 1000;",
+                true,
+            ),
+            (
+                "DELETE FROM events WHERE dt <= DATE_SUB('2024-03-20 00:00:00', INTERVAL 30 DAY) LIMIT 1000;",
+                true,
+            ),
+            // retention below safe_mode.retention_days
+            (
+                "DELETE FROM events WHERE dt BETWEEN '2020-01-01' AND DATE_SUB('2024-03-20 00:00:00', INTERVAL 7 DAY) LIMIT 1000;",
+                false,
+            ),
+            // DATE_SUB on the low bound does not bound the most recent deleted row
+            (
+                "DELETE FROM events WHERE dt BETWEEN DATE_SUB('2024-03-20 00:00:00', INTERVAL 30 DAY) AND '2024-03-20' LIMIT 1000;",
+                false,
+            ),
+            (
+                "DELETE FROM events WHERE dt NOT BETWEEN '2020-01-01' AND DATE_SUB('2024-03-20 00:00:00', INTERVAL 30 DAY) LIMIT 1000;",
+                false,
+            ),
+        ];
+
+        let config = Config::default();
+        let validator = SqlValidator::new(&config);
+        for (sql, expected_valid) in test_cases {
+            let result = validator.validate_sql_query(sql);
+            assert_eq!(
+                result.is_ok(),
+                expected_valid,
+                "unexpected validation result for `{}`: {:?}",
+                sql,
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn test_sql_validate_and_semantics() {
+        // An AND is accepted when either side bounds the retention, as an extra condition can
+        // only delete fewer rows. An OR would widen the deletion, so it never counts.
+        let test_cases = vec![
+            (
+                "DELETE FROM events WHERE status = 'done' AND created_at < DATE_SUB('2024-03-20', INTERVAL 30 DAY)",
+                true,
+            ),
+            (
+                "DELETE FROM events WHERE created_at < DATE_SUB('2024-03-20', INTERVAL 30 DAY) AND status = 'done' AND region = 'eu'",
+                true,
+            ),
+            (
+                "DELETE FROM events WHERE (status = 'done' AND created_at < DATE_SUB('2024-03-20', INTERVAL 30 DAY)) AND id > 0",
+                true,
+            ),
+            (
+                "DELETE FROM events WHERE created_at < DATE_SUB('2024-03-20', INTERVAL 30 DAY) OR status = 'done'",
+                false,
+            ),
+            (
+                "DELETE FROM events WHERE status = 'done' AND region = 'eu'",
+                false,
+            ),
+        ];
+        let config = Config::default();
+        let validator = SqlValidator::new(&config);
+        for (sql, expected_valid) in test_cases {
+            assert_eq!(
+                validator.validate_sql_query(sql).is_ok(),
+                expected_valid,
+                "{}",
+                sql
+            );
+        }
+    }
+
+    #[test]
+    fn test_sql_validate_retention_column() {
+        let test_cases = vec![
+            (
+                "DELETE FROM events WHERE created_at < DATE_SUB('2024-03-20', INTERVAL 30 DAY)",
+                true,
+            ),
+            (
+                "DELETE FROM events WHERE status = 'done' AND `events`.`created_at` <= DATE_SUB('2024-03-20', INTERVAL 30 DAY)",
+                true,
+            ),
+            (
+                "DELETE FROM events WHERE created_at BETWEEN '2020-01-01' AND DATE_SUB('2024-03-20', INTERVAL 30 DAY)",
+                true,
+            ),
+            // A second DATE_SUB on another column, e.g. exclude_recently_updated_column, is fine
+            (
+                "DELETE FROM events WHERE (created_at < DATE_SUB('2024-03-20', INTERVAL 30 DAY)) AND updated_at < DATE_SUB('2024-03-20', INTERVAL 7 DAY)",
+                true,
+            ),
+            (
+                "DELETE FROM events WHERE updated_at < DATE_SUB('2024-03-20', INTERVAL 30 DAY)",
+                false,
+            ),
+            (
+                "DELETE FROM events WHERE updated_at BETWEEN '2020-01-01' AND DATE_SUB('2024-03-20', INTERVAL 30 DAY)",
+                false,
+            ),
+            (
+                "DELETE FROM events WHERE DATE_SUB('2024-03-20', INTERVAL 30 DAY) < created_at",
+                false,
+            ),
+        ];
+        let config = Config::default();
+        let validator = SqlValidator::new(&config).with_retention_column(Some("created_at"));
+        for (sql, expected_valid) in test_cases {
+            let result = validator.validate_sql_query(sql);
+            assert_eq!(result.is_ok(), expected_valid, "{}: {:?}", sql, result);
+        }
+        let error = validator
+            .validate_sql_query(
+                "DELETE FROM events WHERE updated_at < DATE_SUB('2024-03-20', INTERVAL 30 DAY)",
+            )
+            .unwrap_err();
+        assert!(error.to_string().contains("retention_column `created_at`"));
+    }
+
+    #[test]
+    fn test_sql_validate_min_retention_days() {
+        // (sql, task min_retention_days, expected valid) with a global retention of 30 days
+        let test_cases = vec![
+            (
+                "DELETE FROM events WHERE created_at < DATE_SUB('2024-03-20', INTERVAL 30 DAY)",
+                None,
+                true,
+            ),
+            (
+                "DELETE FROM events WHERE created_at < DATE_SUB('2024-03-20', INTERVAL 7 DAY)",
+                None,
+                false,
+            ),
+            // A looser task retention allows what the global one rejects
+            (
+                "DELETE FROM events WHERE created_at < DATE_SUB('2024-03-20', INTERVAL 7 DAY)",
+                Some(7),
+                true,
+            ),
+            // A stricter task retention rejects what the global one allows
+            (
+                "DELETE FROM audit_log WHERE created_at < DATE_SUB('2024-03-20', INTERVAL 90 DAY)",
+                Some(365),
+                false,
+            ),
+            (
+                "DELETE FROM audit_log WHERE created_at < DATE_SUB('2024-03-20', INTERVAL 1 YEAR)",
+                Some(365),
+                true,
+            ),
+        ];
+        let config = Config::default();
+        for (sql, min_retention_days, expected_valid) in test_cases {
+            let result = SqlValidator::new(&config)
+                .with_min_retention_days(min_retention_days)
+                .validate_sql_query(sql);
+            assert_eq!(
+                result.is_ok(),
+                expected_valid,
+                "{} {:?}: {:?}",
+                sql,
+                min_retention_days,
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn test_sql_validate_lookup_in_list() {
+        // (sql, valid with allow_lookup_in_list and a lookup)
+        let test_cases = vec![
+            (
+                "DELETE FROM events WHERE user_id IN (1, 2, 3) LIMIT 1000",
+                true,
+            ),
+            (
+                "DELETE FROM events WHERE type = 'click' AND user_id IN ('a', 'b') LIMIT 1000",
+                true,
+            ),
+            (
+                "DELETE FROM events WHERE user_id NOT IN (1, 2) LIMIT 1000",
+                false,
+            ),
+            (
+                "DELETE FROM events WHERE user_id IN (1) OR 1 = 1 LIMIT 1000",
+                false,
+            ),
+        ];
+        let mut config = Config::default();
+        config.safe_mode.allow_lookup_in_list = true;
+        for (sql, expected_valid) in test_cases {
+            let result = SqlValidator::new(&config)
+                .with_lookup(true)
+                .validate_sql_query(sql);
+            assert_eq!(result.is_ok(), expected_valid, "{}: {:?}", sql, result);
+            // Never without a lookup
+            assert!(SqlValidator::new(&config).validate_sql_query(sql).is_err());
+        }
+
+        // Nor without the opt-in
+        config.safe_mode.allow_lookup_in_list = false;
+        assert!(SqlValidator::new(&config)
+            .with_lookup(true)
+            .validate_sql_query("DELETE FROM events WHERE user_id IN (1, 2, 3) LIMIT 1000")
+            .is_err());
+    }
+
+    #[test]
+    fn test_sql_validate_compound_interval() {
+        // safe_mode.retention_days is 30 in the default config
+        let test_cases = vec![
+            ("INTERVAL '1-6' YEAR_MONTH", true),
+            ("INTERVAL '0-1' YEAR_MONTH", true),
+            ("INTERVAL '0-0' year_month", false),
+            ("INTERVAL '30 0' DAY_HOUR", true),
+            ("INTERVAL '29 23' DAY_HOUR", false),
+            // Too few parts fill the smallest fields, so this is 30 hours
+            ("INTERVAL '30' DAY_HOUR", false),
+            ("INTERVAL '30 12:00:00' DAY_SECOND", true),
+            ("INTERVAL '29 23:59:59' DAY_SECOND", false),
+            ("INTERVAL '720:00' HOUR_MINUTE", true),
+            ("INTERVAL '1-2-3' YEAR_MONTH", false),
+            ("INTERVAL 720 HOUR", true),
+            ("INTERVAL 719 HOUR", false),
+        ];
+
+        let config = Config::default();
+        let validator = SqlValidator::new(&config);
+        for (interval, expected_valid) in test_cases {
+            let sql = format!(
+                "DELETE FROM events WHERE created_at < DATE_SUB('2024-03-20 00:00:00', {}) LIMIT 1000",
+                interval
+            );
+            let result = validator.validate_sql_query(&sql);
+            assert_eq!(
+                result.is_ok(),
+                expected_valid,
+                "unexpected validation result for `{}`: {:?}",
+                interval,
+                result
+            );
+        }
+
+        // Compound units are only rewritten as keywords, not inside strings or identifiers
+        assert!(validator
+            .validate_sql_query(
+                "DELETE FROM `year_month` WHERE note = 'DAY_HOUR' AND created_at < DATE_SUB(NOW(), INTERVAL '1-0' YEAR_MONTH)"
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn test_sql_validate_optimizer_hints() {
+        let template = "DELETE /*+ INDEX_MERGE({{ table_name }} idx_created_at) */ QUICK FROM {{ table_name }} WHERE created_at < DATE_SUB('{{ data_interval_end }}', INTERVAL 30 DAY) LIMIT {{ batch_size }}";
+        let parameters = std::collections::HashMap::from([
+            ("table_name".to_string(), "events".to_string()),
+            ("batch_size".to_string(), "1000".to_string()),
+        ]);
+        let sql = TemplateEngine::new()
+            .render(template, &parameters, "2024-03-20 00:00:00")
+            .unwrap();
+        // The hint survives rendering
+        assert!(
+            sql.starts_with("DELETE /*+ INDEX_MERGE(events idx_created_at) */ QUICK FROM events")
+        );
+
+        let test_cases = vec![
+            (sql.as_str(), false, false),
+            (sql.as_str(), true, true),
+            (
+                "DELETE /*+ MAX_EXECUTION_TIME(1000) */ FROM events WHERE created_at < DATE_SUB(NOW(), INTERVAL 30 DAY)",
+                false,
+                false,
+            ),
+            (
+                "DELETE LOW_PRIORITY QUICK FROM events WHERE created_at < DATE_SUB(NOW(), INTERVAL 30 DAY)",
+                true,
+                true,
+            ),
+            (
+                "DELETE QUICK FROM events WHERE created_at < DATE_SUB(NOW(), INTERVAL 30 DAY)",
+                false,
+                false,
+            ),
+            // Plain comments are not hints
+            (
+                "DELETE /* nightly */ FROM events WHERE created_at < DATE_SUB(NOW(), INTERVAL 30 DAY)",
+                false,
+                true,
+            ),
+            // Hints do not loosen the other checks
+            (
+                "DELETE /*+ NO_INDEX_MERGE(events) */ QUICK FROM events WHERE created_at < DATE_SUB(NOW(), INTERVAL 7 DAY)",
+                true,
+                false,
+            ),
+        ];
+        for (sql, allow_optimizer_hints, expected_valid) in test_cases {
+            let mut config = Config::default();
+            config.safe_mode.allow_optimizer_hints = allow_optimizer_hints;
+            let result = SqlValidator::new(&config).validate_sql_query(sql);
+            assert_eq!(
+                result.is_ok(),
+                expected_valid,
+                "unexpected validation result for `{}`: {:?}",
+                sql,
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn test_sql_validate_order_by() {
+        let with_order_by = "DELETE FROM events WHERE created_at < DATE_SUB('2024-03-20 00:00:00', INTERVAL 30 DAY) ORDER BY id LIMIT 1000;";
+        let without_order_by = "DELETE FROM events WHERE created_at < DATE_SUB('2024-03-20 00:00:00', INTERVAL 30 DAY) LIMIT 1000;";
+        let test_cases = vec![
+            (false, with_order_by, true),
+            (false, without_order_by, true),
+            (true, with_order_by, true),
+            (true, without_order_by, false),
+        ];
+
+        for (require_order_by, sql, expected_valid) in test_cases {
+            let mut config = Config::default();
+            config.safe_mode.require_order_by = require_order_by;
+            let validator = SqlValidator::new(&config);
+            let result = validator.validate_sql_query(sql);
+            assert_eq!(
+                result.is_ok(),
+                expected_valid,
+                "require_order_by={} `{}`: {:?}",
+                require_order_by,
+                sql,
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn test_sql_validate_deterministic_limit() {
+        let retention = "WHERE created_at < DATE_SUB('2024-03-20 00:00:00', INTERVAL 30 DAY)";
+        let test_cases = vec![
+            (
+                false,
+                format!("DELETE FROM events {} LIMIT 1000", retention),
+                true,
+            ),
+            (
+                true,
+                format!("DELETE FROM events {} LIMIT 1000", retention),
+                false,
+            ),
+            (
+                true,
+                format!("DELETE FROM events {} ORDER BY id LIMIT 1000", retention),
+                true,
+            ),
+            (
+                true,
+                format!(
+                    "DELETE FROM events {} ORDER BY created_at, id LIMIT 1000",
+                    retention
+                ),
+                true,
+            ),
+            // Without a LIMIT every matching row is deleted, the order does not matter
+            (true, format!("DELETE FROM events {}", retention), true),
+        ];
+
+        for (require_deterministic_limit, sql, expected_valid) in test_cases {
+            let mut config = Config::default();
+            config.safe_mode.require_deterministic_limit = require_deterministic_limit;
+            let result = SqlValidator::new(&config).validate_sql_query(&sql);
+            assert_eq!(
+                result.is_ok(),
+                expected_valid,
+                "require_deterministic_limit={} `{}`: {:?}",
+                require_deterministic_limit,
+                sql,
+                result
+            );
+            if let Err(e) = result {
+                assert!(e.to_string().contains("ORDER BY id"), "{}", e);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sql_validate_statement_count() {
+        let delete = "DELETE FROM events WHERE created_at < DATE_SUB('2024-03-20 00:00:00', INTERVAL 30 DAY) LIMIT 1000";
+        // (sql, expected error)
+        let test_cases = vec![
+            (format!("{};", delete), None),
+            (format!("{}; ;\n", delete), None),
+            (format!("{}; -- batch of 1000\n", delete), None),
+            (format!("{} /* batch */ ;", delete), None),
+            (format!("-- purge old events\n{}", delete), None),
+            (format!("/* purge old events */ {};", delete), None),
+            (
+                "".to_string(),
+                Some("Found no SQL statement, the query is empty or only comments"),
+            ),
+            (
+                " ; -- nothing\n".to_string(),
+                Some("Found no SQL statement, the query is empty or only comments"),
+            ),
+            (
+                format!("{}; DELETE FROM events", delete),
+                Some(
+                    "Found 2 statements; only 1 allowed: [DELETE FROM events WHERE created_at < \
+                     DATE_SUB('2024-03-20 00:00:00', INTERVAL 30 DAY) LIMIT 1000], [DELETE FROM \
+                     events]",
+                ),
+            ),
+        ];
+
+        let config = Config::default();
+        for (sql, expected_error) in test_cases {
+            let result = SqlValidator::new(&config).validate_sql_query(&sql);
+            assert_eq!(
+                result.as_ref().err().map(|e| e.to_string()),
+                expected_error.map(str::to_string),
+                "`{}`",
+                sql
+            );
+        }
+    }
+
+    #[test]
+    fn test_validate_warmup_query() {
+        let test_cases =
+            vec![
+            ("SELECT COUNT(*) FROM events WHERE created_at < '2024-03-20'", true),
+            (
+                "CREATE TEMPORARY TABLE expired_ids AS SELECT id FROM events WHERE created_at < \
+                 '2024-03-20'",
+                true,
+            ),
+            ("CREATE TABLE expired_ids (id BIGINT)", false),
+            ("DELETE FROM events", false),
+            ("DROP TABLE events", false),
+            ("SELECT 1; DELETE FROM events", false),
+            ("-- prime the buffer pool\nSELECT COUNT(*) FROM events;", true),
+        ];
+        let config = Config::default();
+        for (sql, expected_valid) in test_cases {
+            let result = SqlValidator::new(&config).validate_warmup_query(sql);
+            assert_eq!(result.is_ok(), expected_valid, "`{}`: {:?}", sql, result);
+        }
+    }
+
+    #[test]
+    fn test_validate_bounded_delete() {
+        let test_cases =
+            vec![
+            ("DELETE FROM comments WHERE post_id < 100 LIMIT 1000", true),
+            (
+                "DELETE FROM comments WHERE post_id IN (SELECT id FROM posts) ORDER BY id LIMIT \
+                 1000;",
+                true,
+            ),
+            ("DELETE FROM comments WHERE post_id < 100", false),
+            ("SELECT id FROM comments LIMIT 1000", false),
+            ("DELETE FROM comments LIMIT 1; DELETE FROM posts LIMIT 1", false),
+        ];
+        let config = Config::default();
+        for (sql, expected_valid) in test_cases {
+            let result = SqlValidator::new(&config).validate_bounded_delete(sql);
+            assert_eq!(result.is_ok(), expected_valid, "`{}`: {:?}", sql, result);
+        }
+    }
+
+    #[test]
+    fn test_sql_validate_dialect() {
+        let delete_using = "DELETE FROM events USING runs WHERE events.run_id = runs.id AND events.created_at < DATE_SUB('2024-03-20 00:00:00', INTERVAL 30 DAY)";
+        let test_cases = vec![
+            (SqlDialect::Postgresql, delete_using, true),
+            (SqlDialect::Generic, delete_using, true),
+            // Backtick-quoted identifiers are MySQL-only
+            (
+                SqlDialect::Mysql,
+                "DELETE FROM `events` WHERE created_at < DATE_SUB('2024-03-20 00:00:00', INTERVAL 30 DAY)",
+                true,
+            ),
+            (
+                SqlDialect::Postgresql,
+                "DELETE FROM `events` WHERE created_at < DATE_SUB('2024-03-20 00:00:00', INTERVAL 30 DAY)",
+                false,
+            ),
+        ];
+
+        for (dialect, sql, expected_valid) in test_cases {
+            let mut config = Config::default();
+            config.database_config.dialect = dialect;
+            let validator = SqlValidator::new(&config);
+            let result = validator.validate_sql_query(sql);
+            assert_eq!(
+                result.is_ok(),
+                expected_valid,
+                "{:?} `{}`: {:?}",
+                dialect,
+                sql,
+                result
+            );
+        }
+    }
+
+    struct MockStatistics {
+        indexed_columns: Vec<&'static str>,
+    }
+
+    impl QueryExecutor for MockStatistics {
+        async fn execute_query(&self, _query: &str) -> Result<(u64, f64)> {
+            Ok((0, 0.0))
+        }
+
+        async fn indexed_columns(&self, schema: &str, table: &str) -> Result<Vec<String>> {
+            assert_eq!((schema, table), ("my_database", "events"));
+            Ok(self.indexed_columns.iter().map(|c| c.to_string()).collect())
+        }
+    }
+
+    #[test]
+    fn test_delete_predicate() {
+        let predicate = delete_predicate(
+            "DELETE FROM logs.events WHERE `created_at` < DATE_SUB(NOW(), INTERVAL 30 DAY) AND events.status = 'done' LIMIT 1000",
+            SqlDialect::Mysql,
+        )
+        .unwrap();
+        assert_eq!(
+            predicate,
+            DeletePredicate {
+                schema: Some("logs".to_string()),
+                table: "events".to_string(),
+                columns: vec!["created_at".to_string(), "status".to_string()],
+            }
+        );
+
+        let predicate = delete_predicate(
+            "DELETE FROM events WHERE id IN (SELECT id FROM (SELECT id FROM events WHERE created_at < NOW()) t)",
+            SqlDialect::Mysql,
+        )
+        .unwrap();
+        assert_eq!(predicate.schema, None);
+        assert_eq!(predicate.columns, vec!["id".to_string()]);
+    }
+
+    #[test]
+    fn test_lower_delete_limit() {
+        let sql = "DELETE FROM events WHERE id IN (SELECT id FROM (SELECT id FROM events LIMIT 1000) t) ORDER BY id limit 1000;";
+        assert_eq!(
+            lower_delete_limit(sql, SqlDialect::Mysql, 250).as_deref(),
+            Some("DELETE FROM events WHERE id IN (SELECT id FROM (SELECT id FROM events LIMIT 1000) t) ORDER BY id limit 250;")
+        );
+        // Already within the limit
+        assert_eq!(
+            lower_delete_limit(sql, SqlDialect::Mysql, 1000).as_deref(),
+            Some(sql)
+        );
+        // No literal LIMIT to lower
+        assert_eq!(
+            lower_delete_limit("DELETE FROM events WHERE id < 10", SqlDialect::Mysql, 5),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_indexed_predicate() {
+        let sql = "DELETE FROM events WHERE created_at < DATE_SUB(NOW(), INTERVAL 30 DAY) AND status = 'done' LIMIT 1000";
+        let test_cases = vec![
+            (vec!["id", "created_at"], true),
+            (vec!["id", "STATUS"], true),
+            (vec!["id"], false),
+            (vec![], false),
+        ];
+
+        for (indexed_columns, expected_ok) in test_cases {
+            let statistics = MockStatistics {
+                indexed_columns: indexed_columns.clone(),
+            };
+            let result = check_indexed_predicate(&statistics, &Config::default(), sql).await;
+            assert_eq!(
+                result.is_ok(),
+                expected_ok,
+                "indexed columns {:?}: {:?}",
+                indexed_columns,
+                result
+            );
+        }
+    }
 }
@@ -0,0 +1,128 @@
+use anyhow::{anyhow, Context, Result};
+use log::{debug, info};
+use std::process::Stdio;
+use tokio::{
+    net::TcpStream,
+    process::{Child, Command},
+    time::{sleep, Duration, Instant},
+};
+
+use crate::cleaner::config::SshTunnelConfig;
+
+const TUNNEL_READY_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// A local port forwarded to the database through a bastion host, using the system `ssh` client.
+/// The `ssh` process is stopped when the tunnel is dropped.
+#[derive(Debug)]
+pub struct SshTunnel {
+    child: Child,
+    local_port: u16,
+}
+
+impl SshTunnel {
+    pub async fn open(
+        config: &SshTunnelConfig,
+        target_host: &str,
+        target_port: u16,
+    ) -> Result<Self> {
+        let local_port = free_local_port()?;
+        info!(
+            "Opening SSH tunnel via {}@{}:{} to {}:{} on local port {}",
+            config.ssh_user, config.ssh_host, config.ssh_port, target_host, target_port, local_port
+        );
+        let mut child = Command::new("ssh")
+            .args(ssh_args(config, target_host, target_port, local_port))
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .context("Failed to start ssh, is an OpenSSH client installed?")?;
+
+        // Wait until the forwarded port accepts connections, or ssh gives up
+        let deadline = Instant::now() + TUNNEL_READY_TIMEOUT;
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return Err(anyhow!("SSH tunnel exited before it was ready: {}", status));
+            }
+            if TcpStream::connect(("127.0.0.1", local_port)).await.is_ok() {
+                break;
+            }
+            if Instant::now() >= deadline {
+                return Err(anyhow!(
+                    "SSH tunnel was not ready after {} seconds",
+                    TUNNEL_READY_TIMEOUT.as_secs()
+                ));
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+        debug!("SSH tunnel ready on local port {}", local_port);
+
+        Ok(Self { child, local_port })
+    }
+
+    pub fn local_port(&self) -> u16 {
+        self.local_port
+    }
+}
+
+impl Drop for SshTunnel {
+    fn drop(&mut self) {
+        debug!("Closing SSH tunnel on local port {}", self.local_port);
+        let _ = self.child.start_kill();
+    }
+}
+
+fn free_local_port() -> Result<u16> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")
+        .context("Failed to find a free local port for the SSH tunnel")?;
+    Ok(listener.local_addr()?.port())
+}
+
+fn ssh_args(
+    config: &SshTunnelConfig,
+    target_host: &str,
+    target_port: u16,
+    local_port: u16,
+) -> Vec<String> {
+    let mut args = vec![
+        "-N".to_string(),
+        // Fail instead of prompting or running without the forward
+        "-o".to_string(),
+        "BatchMode=yes".to_string(),
+        "-o".to_string(),
+        "ExitOnForwardFailure=yes".to_string(),
+        "-o".to_string(),
+        "ServerAliveInterval=30".to_string(),
+        "-p".to_string(),
+        config.ssh_port.to_string(),
+        "-L".to_string(),
+        format!("127.0.0.1:{}:{}:{}", local_port, target_host, target_port),
+    ];
+    if let Some(key_file) = &config.ssh_key_file {
+        args.extend(["-i".to_string(), key_file.clone()]);
+    }
+    args.push(format!("{}@{}", config.ssh_user, config.ssh_host));
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ssh_args() {
+        let mut config = SshTunnelConfig {
+            ssh_host: "bastion.example.com".to_string(),
+            ssh_port: 2222,
+            ssh_user: "kiyoshi".to_string(),
+            ssh_key_file: Some("/keys/id_ed25519".to_string()),
+        };
+        assert_eq!(
+            ssh_args(&config, "db.internal", 3306, 40000).join(" "),
+            "-N -o BatchMode=yes -o ExitOnForwardFailure=yes -o ServerAliveInterval=30 -p 2222 \
+             -L 127.0.0.1:40000:db.internal:3306 -i /keys/id_ed25519 kiyoshi@bastion.example.com"
+        );
+
+        config.ssh_key_file = None;
+        assert!(!ssh_args(&config, "db.internal", 3306, 40000).contains(&"-i".to_string()));
+    }
+}
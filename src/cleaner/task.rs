@@ -1,24 +1,48 @@
-use anyhow::Result;
-use log::{info, warn};
-use serde_json;
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use log::{debug, error, info, trace, warn};
 use slack_api_client::{CreateMessage, SlackClient};
-use std::sync::{Arc, Mutex};
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
 use tokio::time::{timeout, Duration};
 
 use crate::{
     cleaner::{
-        config::{CleanupTask, Config},
-        db::Database,
-        sql_validate::SqlValidator,
-        template::TemplateEngine,
+        callback::send_run_result,
+        circuit_breaker::BreakerTransition,
+        config::{
+            CleanupTask, Config, DisabledTask, DurationStyle, SafeModeEnforcement, SlackEvent,
+            TableReport, TruncateQueries,
+        },
+        db::{Database, QueryExecutor},
+        dead_letter::DeadLetter,
+        dry_run,
+        health::{SessionStats, SessionSummary},
+        lookup::fetch_lookup_lists,
+        metrics::metrics,
+        partition::drop_old_partitions,
+        run_history::{run_history, RunRecord},
+        run_id::{current_run_id, new_run_id, with_run_id},
+        run_report::save_run_report,
+        slack::{send_report, with_notification_budget},
+        sql_validate::{
+            check_indexed_predicate, delete_predicate, lower_delete_limit, SqlValidator,
+        },
+        template::{format_data_interval, quote_identifier, TemplateEngine},
     },
     scheduler::job::JobScheduleMetadata,
 };
 
 #[derive(Debug, Clone)]
-struct ProgressTracker {
+pub(crate) struct ProgressTracker {
     total_rows: u64,
     elapsed_time: f64,
+    batches: u32,
+    /// The rendered DELETE of every delete loop so far
+    statements: Vec<String>,
 }
 
 impl Default for ProgressTracker {
@@ -26,48 +50,135 @@ impl Default for ProgressTracker {
         Self {
             total_rows: 0,
             elapsed_time: 0.0,
+            batches: 0,
+            statements: Vec::new(),
         }
     }
 }
 
 fn humanize_time(seconds: f64) -> String {
+    humanize_duration(seconds, DurationStyle::Compact)
+}
+
+/// Writes a duration in the given style. Milliseconds are only shown for durations under a
+/// minute, and days for durations of 24 hours or more.
+fn humanize_duration(seconds: f64, style: DurationStyle) -> String {
+    if !seconds.is_finite() {
+        return "n/a".to_string();
+    }
+    let seconds = seconds.max(0.0);
+    let plural =
+        |count: u64, unit: &str| format!("{} {}{}", count, unit, if count == 1 { "" } else { "s" });
     if seconds < 1.0 {
-        return format!("{:.0}ms", seconds * 1000.0);
+        let millis = (seconds * 1000.0).round() as u64;
+        return match style {
+            DurationStyle::Compact => format!("{}ms", millis),
+            DurationStyle::Verbose => plural(millis, "millisecond"),
+            DurationStyle::Clock => format!("00:00:00.{:03}", millis),
+        };
     }
 
     let total_seconds = seconds as u64;
-    let hours = total_seconds / 3600;
+    let days = total_seconds / 86400;
+    let hours = (total_seconds % 86400) / 3600;
     let minutes = (total_seconds % 3600) / 60;
     let secs = total_seconds % 60;
     let millis = ((seconds % 1.0) * 1000.0) as u64;
-    let mut parts = Vec::new();
+    // Fractional seconds only matter for durations under a minute
+    let show_millis = millis > 0 && total_seconds < 60;
 
-    if hours > 0 {
-        parts.push(format!("{}h", hours));
-    }
-    if minutes > 0 {
-        parts.push(format!("{}m", minutes));
+    if style == DurationStyle::Clock {
+        let days = if days > 0 {
+            format!("{}d ", days)
+        } else {
+            String::new()
+        };
+        let millis = if show_millis {
+            format!(".{:03}", millis)
+        } else {
+            String::new()
+        };
+        return format!("{}{:02}:{:02}:{:02}{}", days, hours, minutes, secs, millis);
     }
+
+    let units: [(u64, &str, &str); 3] = [
+        (days, "d", "day"),
+        (hours, "h", "hour"),
+        (minutes, "m", "minute"),
+    ];
+    let mut parts: Vec<String> = units
+        .iter()
+        .filter(|(count, _, _)| *count > 0)
+        .map(|(count, short, long)| match style {
+            DurationStyle::Verbose => plural(*count, long),
+            _ => format!("{}{}", count, short),
+        })
+        .collect();
     if secs > 0 || parts.is_empty() {
-        if millis > 0 && parts.is_empty() {
-            parts.push(format!("{}.{:03}s", secs, millis));
-        } else {
-            parts.push(format!("{}s", secs));
-        }
+        parts.push(match (style, show_millis) {
+            (DurationStyle::Verbose, true) => format!("{}.{:03} seconds", secs, millis),
+            (DurationStyle::Verbose, false) => plural(secs, "second"),
+            (_, true) => format!("{}.{:03}s", secs, millis),
+            (_, false) => format!("{}s", secs),
+        });
     }
     parts.join(" ")
 }
 
+/// Rows deleted per second of query time, e.g. `1,234.5 rows/s`, or `n/a` without any query
+/// time.
+fn humanize_rate(rows: u64, seconds: f64) -> String {
+    let rate = rows as f64 / seconds;
+    if !seconds.is_finite() || seconds <= 0.0 || !rate.is_finite() {
+        return "n/a".to_string();
+    }
+    let tenths = (rate * 10.0).round() as u64;
+    format!("{}.{} rows/s", format_count(tenths / 10), tenths % 10)
+}
+
+/// A count with thousands separators, e.g. `1,234,567`.
+fn format_count(count: u64) -> String {
+    let digits = count.to_string();
+    let mut formatted = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        // A separator goes before every third digit from the right
+        if i > 0 && i % 3 == digits.len() % 3 {
+            formatted.push(',');
+        }
+        formatted.push(digit);
+    }
+    formatted
+}
+
 pub async fn process_cleanup_task(
     metadata: &JobScheduleMetadata,
     config: &Config,
     task: &CleanupTask,
+    session_stats: &SessionStats,
+) -> Result<(), anyhow::Error> {
+    with_run_id(
+        new_run_id(),
+        with_notification_budget(
+            config.max_notifications_per_run,
+            &task.name,
+            run_cleanup_task(metadata, config, task, session_stats),
+        ),
+    )
+    .await
+}
+
+async fn run_cleanup_task(
+    metadata: &JobScheduleMetadata,
+    config: &Config,
+    task: &CleanupTask,
+    session_stats: &SessionStats,
 ) -> Result<(), anyhow::Error> {
+    let started_at = Utc::now();
     let progress_tracker = Arc::new(Mutex::new(ProgressTracker::default()));
     let progress_tracker_clone = Arc::clone(&progress_tracker);
     let timeout_duration = Duration::from_secs_f64(task.task_timeout_seconds);
 
-    match timeout(
+    let result = match timeout(
         timeout_duration,
         execute_cleanup_task(metadata, config, task, progress_tracker_clone),
     )
@@ -90,28 +201,93 @@ pub async fn process_cleanup_task(
             if config.slack_config.enabled {
                 let slack_client = SlackClient::new(config.slack_config.bot_token.clone());
                 let timeout_report = create_timeout_report(&CleanupMetadata {
-                    config,
-                    task,
                     total_rows: progress.total_rows,
                     elapsed_time: progress.elapsed_time,
-                    schema_name: task.parameters.get("schema_name"),
-                    table_name: task.parameters.get("table_name"),
+                    ..CleanupMetadata::new(config, task)
                 });
 
-                let send_result = timeout_report
-                    .send_to_channel(&slack_client, config.slack_config.channel_id.clone())
-                    .await;
-
-                if let Err(e) = send_result {
-                    warn!("Failed to send timeout report to Slack: {}", e);
-                } else {
-                    info!("Timeout report sent to Slack");
-                }
+                send_slack_report(
+                    &slack_client,
+                    config,
+                    SlackEvent::Timeout,
+                    &timeout_report,
+                    "timeout report",
+                )
+                .await;
             }
 
             Err(anyhow::anyhow!("{}", error_message))
         }
+    };
+
+    let progress = progress_tracker.lock().unwrap().clone();
+    session_stats.record_run(progress.total_rows, result.is_ok());
+    let run = RunRecord::new(started_at, Utc::now(), progress.total_rows, &result);
+    send_run_result(config, &task.name, progress.batches, &run).await;
+    save_run_report(
+        config,
+        task,
+        metadata.data_interval_end,
+        &progress.statements,
+        progress.batches,
+        progress.elapsed_time,
+        &run,
+    );
+    if let Some(run_history) = run_history() {
+        run_history.record(&task.name, run);
+    }
+    result
+}
+
+/// Parses an `--as-of` RFC 3339 timestamp used as the run's `data_interval_end`. Timestamps after
+/// `now` are rejected unless `allow_future` is set.
+pub fn parse_as_of(value: &str, allow_future: bool, now: DateTime<Utc>) -> Result<DateTime<Utc>> {
+    let as_of = DateTime::parse_from_rfc3339(value)
+        .map_err(|e| anyhow::anyhow!("Invalid --as-of timestamp '{}': {}", value, e))?
+        .with_timezone(&Utc);
+    if as_of > now && !allow_future {
+        return Err(anyhow::anyhow!(
+            "--as-of timestamp {} is in the future, pass --allow-future to use it anyway",
+            as_of
+        ));
+    }
+    Ok(as_of)
+}
+
+/// The intervals of the backfill runs between `since` and `until`, oldest first. Each ends at a
+/// fire time of `cron_schedule` after `since` up to and including `until`, and starts at the
+/// previous one, the first at `since`.
+pub fn backfill_intervals(
+    cron_schedule: &str,
+    since: DateTime<Utc>,
+    until: DateTime<Utc>,
+) -> Result<Vec<JobScheduleMetadata>> {
+    if since >= until {
+        return Err(anyhow::anyhow!(
+            "--since {} must be before --until {}",
+            since,
+            until
+        ));
+    }
+    let schedule = cron::Schedule::from_str(cron_schedule)
+        .with_context(|| format!("Invalid cron schedule '{}'", cron_schedule))?;
+    let fire_times: Vec<_> = schedule
+        .after(&since)
+        .take_while(|fire_time| *fire_time <= until)
+        .collect();
+    if fire_times.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Schedule '{}' does not fire between {} and {}",
+            cron_schedule,
+            since,
+            until
+        ));
     }
+    let starts = std::iter::once(since).chain(fire_times.iter().copied());
+    Ok(starts
+        .zip(fire_times.iter().copied())
+        .map(|(start, end)| JobScheduleMetadata::new(start, end))
+        .collect())
 }
 
 async fn execute_cleanup_task(
@@ -121,8 +297,10 @@ async fn execute_cleanup_task(
     progress_tracker: Arc<Mutex<ProgressTracker>>,
 ) -> Result<(), anyhow::Error> {
     // Initialize components
-    let db = match Database::new(&config.database_config).await {
-        Ok(db) => db,
+    let db = match Database::new_for_task(&config.database_config, &task.name).await {
+        Ok(db) => db
+            .with_max_connections(task.max_connections)
+            .with_query_timeout(task.query_timeout(config)),
         Err(e) => {
             return Err(anyhow::anyhow!(
                 "Failed to initialize database connection: {}",
@@ -130,14 +308,22 @@ async fn execute_cleanup_task(
             ))
         }
     };
-    let template_engine = TemplateEngine::new();
-
     // Calculate intervals
-    let data_interval_end = metadata
-        .data_interval_end
-        .format("%Y-%m-%d %H:%M:%S")
-        .to_string();
-    info!("data_interval_end: {}", data_interval_end);
+    let data_interval_start = format_data_interval(
+        metadata.data_interval_start,
+        task.data_interval_format(config),
+    );
+    let data_interval_end = format_data_interval(
+        metadata.data_interval_end,
+        task.data_interval_format(config),
+    );
+    info!(
+        "data_interval_start: {}, data_interval_end: {}",
+        data_interval_start, data_interval_end
+    );
+    let template_engine =
+        TemplateEngine::with_identifier_quote(config.database_config.dialect.identifier_quote())
+            .with_data_interval_start(data_interval_start);
 
     let slack_client = if config.slack_config.enabled {
         Some(SlackClient::new(config.slack_config.bot_token.clone()))
@@ -152,42 +338,190 @@ async fn execute_cleanup_task(
 
     info!("Processing cleanup task: {}", task.name);
 
+    if task.partition_drop.is_some() {
+        return run_partition_drop(&db, config, task, metadata, &slack_client).await;
+    }
+
     // Render SQL template
-    let mut template_parameters = task.parameters.clone();
-    template_parameters.insert("batch_size".to_string(), task.batch_size.to_string());
-    let sql = template_engine.render(
-        &task.template_query,
-        &template_parameters,
-        &data_interval_end,
-    )?;
+    let mut template_parameters = build_template_parameters(config, task);
+    let mut lookup_lists = Vec::new();
+    let mut tables = Vec::new();
+    let mut truncate_queries = None;
+    let mut warmup_query = None;
+    let mut child_queries = Vec::new();
+    let rendered = async {
+        if task.has_tables() {
+            tables = task_tables(
+                &db,
+                task,
+                &template_engine,
+                &template_parameters,
+                &data_interval_end,
+            )
+            .await?;
+            // Validated with the first table, every table gets its own DELETE loop
+            template_parameters.insert(
+                "table_name".to_string(),
+                tables.first().cloned().unwrap_or_default(),
+            );
+        }
+        if let Some(lookup) = &task.lookup {
+            lookup_lists = fetch_lookup_lists(
+                lookup,
+                task,
+                &template_engine,
+                &template_parameters,
+                &data_interval_end,
+            )
+            .await?;
+            // Validated and reported with the first list, every list gets its own DELETE loop
+            template_parameters.insert(
+                "lookup_ids".to_string(),
+                lookup_lists.first().cloned().unwrap_or_default(),
+            );
+        }
+        let rendered = render_task_templates(
+            &template_engine,
+            task,
+            &template_parameters,
+            &data_interval_end,
+        )?;
+        warmup_query = task
+            .warmup_query
+            .as_deref()
+            .map(|query| {
+                template_engine
+                    .render(query, &template_parameters, &data_interval_end)
+                    .context("failed to render warmup_query")
+            })
+            .transpose()?;
+        truncate_queries = render_truncate_queries(
+            &template_engine,
+            task,
+            &template_parameters,
+            &data_interval_end,
+        )?;
+        let rendered = match task.batch_size_percent {
+            Some(percent) => {
+                // Render again with the batch size computed from the table the query deletes from
+                let batch_size = percent_batch_size(&db, config, &rendered.0, percent)
+                    .await
+                    .context("failed to compute batch_size_percent")?;
+                info!(
+                    "Using batch_size {} ({}% of the table) for task: {}",
+                    batch_size, percent, task.name
+                );
+                template_parameters.insert("batch_size".to_string(), batch_size.to_string());
+                render_task_templates(
+                    &template_engine,
+                    task,
+                    &template_parameters,
+                    &data_interval_end,
+                )?
+            }
+            None => rendered,
+        };
+        child_queries = render_child_cleanup(
+            config,
+            &template_engine,
+            task,
+            &template_parameters,
+            &data_interval_end,
+        )?;
+        Ok::<_, anyhow::Error>(rendered)
+    }
+    .await;
+    let (sql, hooks) = match rendered {
+        Ok(rendered) => rendered,
+        Err(e) => {
+            let error_report = create_error_report(
+                &CleanupMetadata::new(config, task),
+                &format!(
+                    "Failed to prepare the query for task: {}, error: {:#}",
+                    task.name, e
+                ),
+            );
+            if let Some(slack_client) = &slack_client {
+                send_slack_report(
+                    slack_client,
+                    config,
+                    SlackEvent::Error,
+                    &error_report,
+                    "error report",
+                )
+                .await;
+            }
+            return Err(anyhow::anyhow!(
+                "Failed to prepare the query for task: {}, error: {:#}",
+                task.name,
+                e
+            ));
+        }
+    };
+    trace!(
+        "Rendered template of task {} with parameters {:?}:\n{}",
+        task.name,
+        config.redact_secrets(&format!("{:?}", template_parameters)),
+        config.redact_secrets(&sql)
+    );
+
+    if task.lookup.is_some() && lookup_lists.is_empty() {
+        info!(
+            "Lookup of task {} returned no values, nothing to clean up",
+            task.name
+        );
+        return Ok(());
+    }
+    if task.has_tables() && tables.is_empty() {
+        info!(
+            "tables_query of task {} returned no tables, nothing to clean up",
+            task.name
+        );
+        return Ok(());
+    }
 
     // Validate SQL query
+    let mut validation_warning = false;
     if config.safe_mode.enabled {
-        let validator = SqlValidator::new(config);
-        let validate_result = validator.validate_sql_query(&sql);
-        if let Err(e) = validate_result {
+        let validator = SqlValidator::new(config)
+            .with_lookup(task.lookup.is_some())
+            .with_min_retention_days(task.min_retention_days);
+        let mut validate_result = validate_task_query(&db, config, task, &sql, &hooks).await;
+        if validate_result.is_ok() {
+            validate_result = child_queries.iter().try_for_each(|(name, query)| {
+                validator
+                    .validate_sql_query(query)
+                    .map_err(|e| anyhow::anyhow!("child_cleanup '{}' rejected: {}", name, e))
+            });
+        }
+        if let (true, true, Some(warmup_query)) = (
+            validate_result.is_ok(),
+            task.validate_warmup_query,
+            &warmup_query,
+        ) {
+            validate_result = validator
+                .validate_warmup_query(warmup_query)
+                .map_err(|e| anyhow::anyhow!("warmup_query rejected: {}", e));
+        }
+        if let (Err(e), SafeModeEnforcement::Warn) = (&validate_result, config.safe_mode.mode) {
+            warn_validation_failure(config, task, &slack_client, e).await;
+            validation_warning = true;
+        } else if let Err(e) = validate_result {
             let error_report = create_error_report(
-                &CleanupMetadata {
-                    config,
-                    task,
-                    total_rows: 0,
-                    elapsed_time: 0.0,
-                    schema_name: task.parameters.get("schema_name"),
-                    table_name: task.parameters.get("table_name"),
-                },
+                &CleanupMetadata::new(config, task),
                 &format!(
                 "SQL validation failed for task: {}, error: {}. If unexpected, please consider switching safe_mode.enabled to false otherwise the Kiyoshi might be lacking support in ensuring that the query is safe to run",
                 task.name, e
             ));
             if let Some(slack_client) = &slack_client {
-                let send_result = error_report
-                    .send_to_channel(slack_client, config.slack_config.channel_id.clone())
-                    .await;
-                if let Err(e) = send_result {
-                    warn!("Failed to send error report to Slack: {}", e);
-                } else {
-                    info!("Error report sent to Slack");
-                }
+                send_slack_report(
+                    slack_client,
+                    config,
+                    SlackEvent::Error,
+                    &error_report,
+                    "error report",
+                )
+                .await;
             }
             return Err(anyhow::anyhow!(
                 "SQL validation failed for task: {}, error: {}",
@@ -197,233 +531,2705 @@ async fn execute_cleanup_task(
         }
     }
 
-    info!("Executing cleanup query for task: {}", task.name);
-
-    // Execute with retries
-    let mut attempt = 0;
-    let mut success = false;
-    let mut total_rows: u64 = 0;
-    let mut total_time_elapsed: f64 = 0.0;
-
-    'outer: while attempt < task.retry_attempts {
-        loop {
-            info!("Executing sql query: \n{}", sql);
-            match db.execute_query(&sql).await {
-                Ok((affected_rows, elapsed_in_secs)) => {
-                    if affected_rows == 0 {
-                        info!(
-                            "No more rows to clean up. Total rows cleaned: {} for task: {} in {}",
-                            total_rows,
-                            task.name,
-                            humanize_time(elapsed_in_secs)
-                        );
-                        success = true;
-                        let report = create_cleanup_report(CleanupMetadata {
-                            config,
-                            task,
-                            total_rows,
-                            elapsed_time: total_time_elapsed,
-                            schema_name: task
-                                .parameters
-                                .get("schema_name")
-                                .or(Some(&config.database_config.database)),
-                            table_name: task.parameters.get("table_name"),
-                        });
-                        if let Some(slack_client) = &slack_client {
-                            let send_result = report
-                                .send_to_channel(
-                                    slack_client,
-                                    config.slack_config.channel_id.clone(),
-                                )
-                                .await;
-                            if let Err(e) = send_result {
-                                warn!("Failed to send cleanup report to Slack: {}", e);
-                            } else {
-                                info!("Cleanup report sent to Slack");
-                            }
-                        }
-                        break 'outer;
-                    }
-                    total_time_elapsed += elapsed_in_secs;
-                    total_rows += affected_rows;
+    if task.require_first_run_ack && !config.acked_tasks.contains(&task.name) {
+        return run_unacknowledged_task(&db, config, task, &sql, &slack_client).await;
+    }
 
-                    {
-                        let mut tracker = progress_tracker.lock().unwrap();
-                        tracker.total_rows = total_rows;
-                        tracker.elapsed_time = total_time_elapsed;
-                    }
+    if validation_warning {
+        warn!(
+            "[safe_mode=warn] Executing cleanup query that failed validation for task: {}",
+            task.name
+        );
+    } else {
+        info!("Executing cleanup query for task: {}", task.name);
+    }
 
-                    info!(
-                        "Successfully cleaned up {} rows (total: {}) for task: {} in {}",
-                        affected_rows,
-                        total_rows,
-                        task.name,
-                        humanize_time(elapsed_in_secs)
-                    );
-                    tokio::time::sleep(Duration::from_secs_f64(task.query_interval_seconds)).await;
+    let db_clock = if config.report_db_time {
+        read_db_clock(&db, config, task).await
+    } else {
+        None
+    };
+    let warmup_time = match &warmup_query {
+        Some(warmup_query) => match run_warmup_query(&db, task, warmup_query).await {
+            Ok(elapsed_time) => Some(elapsed_time),
+            Err(e) => {
+                let error_report =
+                    create_error_report(&CleanupMetadata::new(config, task), &format!("{:#}", e));
+                if let Some(slack_client) = &slack_client {
+                    send_slack_report(
+                        slack_client,
+                        config,
+                        SlackEvent::Error,
+                        &error_report,
+                        "error report",
+                    )
+                    .await;
                 }
-                Err(e) => {
-                    attempt += 1;
-                    warn!(
-                        "Attempt {}/{} failed for task {}: {}",
-                        attempt, task.retry_attempts, task.name, e
-                    );
-                    if attempt < task.retry_attempts {
-                        tokio::time::sleep(Duration::from_secs(task.retry_delay_seconds.into()))
-                            .await;
-                    }
-                    if attempt == task.retry_attempts {
-                        let error_report = create_error_report(
-                            &CleanupMetadata {
-                                config,
-                                task,
-                                total_rows,
-                                elapsed_time: total_time_elapsed,
-                                schema_name: task
-                                    .parameters
-                                    .get("schema_name")
-                                    .or(Some(&config.database_config.database)),
-                                table_name: task.parameters.get("table_name"),
-                            },
-                            &format!("All attempts failed for task: {}, error: {}", task.name, e),
-                        );
-                        if let Some(slack_client) = &slack_client {
-                            let send_result = error_report
-                                .send_to_channel(
-                                    slack_client,
-                                    config.slack_config.channel_id.clone(),
-                                )
-                                .await;
-                            if let Err(e) = send_result {
-                                warn!("Failed to send error report to Slack: {}", e);
-                            } else {
-                                info!("Error report sent to Slack");
-                            }
-                        }
-                    }
-                    break; // Break inner loop to retry with attempt counter
+                return Err(e);
+            }
+        },
+        None => None,
+    };
+    let trial_rows = if task.trial_rollback_first {
+        match run_trial(&db, task, &sql).await {
+            Ok(trial_rows) => Some(trial_rows),
+            Err(e) => {
+                let error_report =
+                    create_error_report(&CleanupMetadata::new(config, task), &format!("{:#}", e));
+                if let Some(slack_client) = &slack_client {
+                    send_slack_report(
+                        slack_client,
+                        config,
+                        SlackEvent::Error,
+                        &error_report,
+                        "error report",
+                    )
+                    .await;
                 }
+                return Err(e);
+            }
+        }
+    } else {
+        None
+    };
+    if let (Some(trial_rows), false) = (trial_rows, task.trial_then_commit) {
+        if let Some(slack_client) = &slack_client {
+            let mut trial_metadata = CleanupMetadata::new(config, task);
+            trial_metadata.sql = Some(&sql);
+            trial_metadata.parameters = Some(&template_parameters);
+            send_slack_report(
+                slack_client,
+                config,
+                SlackEvent::Success,
+                &create_trial_report(&trial_metadata, trial_rows),
+                "trial report",
+            )
+            .await;
+        }
+        info!(
+            "Trial of task {} done, set trial_then_commit to delete for real",
+            task.name
+        );
+        return Ok(());
+    }
+    // The TRUNCATE run instead of the DELETE loop, if any
+    let mut truncated_with = None;
+    let mut child_steps = Vec::new();
+    let summary = if task.lookup.is_some() {
+        let render = |list: &str| {
+            let mut parameters = template_parameters.clone();
+            parameters.insert("lookup_ids".to_string(), list.to_string());
+            render_task_templates(&template_engine, task, &parameters, &data_interval_end)
+        };
+        run_lookup_delete_loops(
+            &db,
+            config,
+            task,
+            &lookup_lists,
+            render,
+            &progress_tracker,
+            &slack_client,
+        )
+        .await
+    } else if !tables.is_empty() {
+        let render = |table: &str| {
+            let mut parameters = template_parameters.clone();
+            parameters.insert("table_name".to_string(), table.to_string());
+            render_task_templates(&template_engine, task, &parameters, &data_interval_end)
+        };
+        let table_runs = run_table_delete_loops(
+            &db,
+            config,
+            task,
+            &tables,
+            render,
+            &progress_tracker,
+            &slack_client,
+        )
+        .await;
+        return report_table_runs(
+            &db,
+            config,
+            task,
+            &template_parameters,
+            table_runs,
+            db_clock,
+            &slack_client,
+        )
+        .await;
+    } else {
+        let truncated = match &truncate_queries {
+            Some(_) if validation_warning => {
+                warn!(
+                    "Not truncating the table of task {} as its query failed validation",
+                    task.name
+                );
+                None
+            }
+            Some(queries) => truncate_if_fully_expired(&db, task, queries, &progress_tracker).await,
+            None => None,
+        };
+        match truncated {
+            Some(summary) => {
+                truncated_with = truncate_queries.as_ref().map(|queries| &queries.truncate);
+                summary
+            }
+            None => {
+                let (steps, summary) = run_with_child_cleanup(
+                    &db,
+                    config,
+                    task,
+                    &child_queries,
+                    &sql,
+                    &hooks,
+                    &progress_tracker,
+                )
+                .await;
+                child_steps = steps;
+                summary
             }
         }
+    };
+    if summary.elapsed_time > 0.0 {
+        info!(
+            "Average deletion rate for task {}: {} ({} rows in {})",
+            task.name,
+            humanize_rate(summary.total_rows, summary.elapsed_time),
+            format_count(summary.total_rows),
+            humanize_time(summary.elapsed_time)
+        );
+        let tags = task.tags.join(",");
+        metrics().set_gauge(
+            "kiyoshi_deletion_rate_rows_per_second",
+            &[
+                ("task", &task.name),
+                ("tags", &tags),
+                ("owner", task.owner.as_deref().unwrap_or_default()),
+            ],
+            summary.total_rows as f64 / summary.elapsed_time,
+        );
     }
+    let mut cleanup_metadata = CleanupMetadata::new(config, task);
+    cleanup_metadata.db_clock = db_clock;
+    cleanup_metadata.server_time = summary.server_time;
+    cleanup_metadata.total_rows = summary.total_rows;
+    cleanup_metadata.elapsed_time = summary.elapsed_time;
+    cleanup_metadata.sql = Some(truncated_with.unwrap_or(&sql));
+    cleanup_metadata.parameters = Some(&template_parameters);
+    cleanup_metadata.truncated = truncated_with.is_some();
+    cleanup_metadata.warmup_time = warmup_time;
+    cleanup_metadata.trial_rows = trial_rows;
+    cleanup_metadata.child_steps = &child_steps;
 
-    if !success {
-        warn!("All attempts failed for task: {}", task.name);
-        return Err(anyhow::anyhow!(
-            "All attempts failed for task: {}",
-            task.name
-        ));
+    match summary.outcome {
+        DeleteLoopOutcome::Completed | DeleteLoopOutcome::Capped => {
+            cleanup_metadata.capped = matches!(summary.outcome, DeleteLoopOutcome::Capped);
+            // A truncated table has no space left to reclaim
+            if truncated_with.is_none() {
+                cleanup_metadata.optimize_result =
+                    optimize_after_cleanup(&db, config, task, &sql, summary.total_rows, Utc::now())
+                        .await;
+            }
+            let report = create_cleanup_report(cleanup_metadata);
+            if let Some(slack_client) = &slack_client {
+                send_slack_report(
+                    slack_client,
+                    config,
+                    SlackEvent::Success,
+                    &report,
+                    "cleanup report",
+                )
+                .await;
+            }
+        }
+        DeleteLoopOutcome::Failed(e) => {
+            let error_report = create_error_report(
+                &cleanup_metadata,
+                &format!("All attempts failed for task: {}, error: {}", task.name, e),
+            );
+            if let Some(slack_client) = &slack_client {
+                send_slack_report(
+                    slack_client,
+                    config,
+                    SlackEvent::Error,
+                    &error_report,
+                    "error report",
+                )
+                .await;
+            }
+            warn!("All attempts failed for task: {}", task.name);
+            return Err(anyhow::anyhow!(
+                "All attempts failed for task: {}",
+                task.name
+            ));
+        }
     }
 
     info!("Cleanup process completed");
     Ok(())
 }
 
-struct CleanupMetadata<'a> {
-    config: &'a Config,
-    task: &'a CleanupTask,
+#[derive(Debug)]
+enum DeleteLoopOutcome {
+    /// A batch affected no rows, so there is nothing left to clean up
+    Completed,
+    /// `max_rows_per_run` was reached and more rows may remain for the next run
+    Capped,
+    /// All retry attempts failed
+    Failed(anyhow::Error),
+}
+
+#[derive(Debug)]
+struct DeleteLoopSummary {
     total_rows: u64,
     elapsed_time: f64,
-    schema_name: Option<&'a String>,
-    table_name: Option<&'a String>,
+    /// Execution time of the batches on the server, with `measure_server_time` and if available
+    server_time: Option<f64>,
+    /// Batches run, including empty and failed ones, as counted against `max_batches_per_run`
+    batches: u32,
+    outcome: DeleteLoopOutcome,
 }
 
-fn create_cleanup_report(metadata: CleanupMetadata) -> CreateMessage {
-    let schema_table = match (metadata.schema_name, metadata.table_name) {
-        (Some(schema), Some(table)) => format!("{}.{}", schema, table),
-        (None, Some(table)) => table.clone(),
-        (Some(schema), None) => schema.clone(),
-        (None, None) => "Unknown Target".to_string(),
-    };
+impl DeleteLoopSummary {
+    fn new(outcome: DeleteLoopOutcome) -> Self {
+        Self {
+            total_rows: 0,
+            elapsed_time: 0.0,
+            server_time: None,
+            batches: 0,
+            outcome,
+        }
+    }
 
-    CreateMessage::Blocks(serde_json::json!([
-        {
-            "type": "section",
-            "text": {
-                "type": "mrkdwn",
-                "text": "🧹 *Cleanup Task Completed*"
-            }
-        },
-        {
-            "type": "section",
-            "text": {
-                "type": "mrkdwn",
-                "text": format!("*Host:* `{}`\n*Task:* `{}`\n*Target:* `{}`", metadata.config.database_config.host, metadata.task.name, schema_table)
-            }
-        },
-        {
-            "type": "section",
-            "fields": [
-                {
-                    "type": "mrkdwn",
-                    "text": format!("*Total Rows Cleaned:*\n{}", metadata.total_rows)
-                },
-                {
-                    "type": "mrkdwn",
-                    "text": format!("*Total Time Elapsed:*\n{}", humanize_time(metadata.elapsed_time))
-                }
-            ]
-        },
-        {
-            "type": "context",
-            "elements": [
-                {
-                    "type": "mrkdwn",
-                    "text": format!("🕒 Completed: {} | 🫧 Kiyoshi Cleanup Service",
-                        chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC")
-                    )
-                }
-            ]
+    /// Adds the rows, time and batches of a later loop of the same run, whose outcome becomes the
+    /// run's.
+    fn add(&mut self, summary: DeleteLoopSummary) {
+        self.total_rows += summary.total_rows;
+        self.elapsed_time += summary.elapsed_time;
+        if let Some(server_time) = summary.server_time {
+            self.server_time = Some(self.server_time.unwrap_or(0.0) + server_time);
         }
-    ]))
+        self.batches += summary.batches;
+        self.outcome = summary.outcome;
+    }
 }
 
-fn create_timeout_report(metadata: &CleanupMetadata) -> CreateMessage {
-    let schema_table = match (metadata.schema_name, metadata.table_name) {
-        (Some(schema), Some(table)) => format!("{}.{}", schema, table),
-        (None, Some(table)) => table.clone(),
-        (Some(schema), None) => schema.clone(),
-        (None, None) => "Unknown Target".to_string(),
-    };
+/// What is left of a task's `max_rows_per_run` and `max_batches_per_run` for a DELETE loop, when
+/// several loops of one run share them.
+#[derive(Clone, Copy, Debug)]
+struct LoopBudget {
+    max_rows: Option<u64>,
+    max_batches: Option<u32>,
+}
 
-    let mut blocks = vec![
-        serde_json::json!({
-            "type": "section",
-            "text": {
-                "type": "mrkdwn",
-                "text": "⏰ *Cleanup Task Timed Out*"
-            }
-        }),
-        serde_json::json!({
-            "type": "section",
-            "text": {
-                "type": "mrkdwn",
-                "text": format!("*Host:* `{}`\n*Task:* `{}`\n*Target:* `{}`", metadata.config.database_config.host, metadata.task.name, schema_table)
-            }
-        }),
-        serde_json::json!({
-            "type": "section",
-            "text": {
-                "type": "mrkdwn",
-                "text": format!("Task timed out after {} seconds\n", metadata.task.task_timeout_seconds)
-            }
-        }),
-    ];
+impl LoopBudget {
+    fn of(task: &CleanupTask) -> Self {
+        Self {
+            max_rows: task.max_rows_per_run,
+            max_batches: task.max_batches_per_run,
+        }
+    }
 
-    if metadata.elapsed_time > 0.0 {
-        blocks.push(serde_json::json!({
-            "type": "section",
-            "fields": [
-                {
-                    "type": "mrkdwn",
-                    "text": format!("*Rows Cleaned:*\n{}", metadata.total_rows)
-                },
-                {
-                    "type": "mrkdwn",
-                    "text": format!("*Time Elapsed:*\n{}", humanize_time(metadata.elapsed_time))
-                }
-            ]
-        }));
+    /// The budget left after a loop that ran `summary`.
+    fn after(self, summary: &DeleteLoopSummary) -> Self {
+        Self {
+            max_rows: self
+                .max_rows
+                .map(|max_rows| max_rows.saturating_sub(summary.total_rows)),
+            max_batches: self
+                .max_batches
+                .map(|max_batches| max_batches.saturating_sub(summary.batches)),
+        }
+    }
+}
+
+/// Validates a rendered DELETE and its batch hooks with safe mode: the query, the hooks unless
+/// `allow_unsafe_hooks` is set and, with `require_indexed_predicate`, the index its WHERE clause
+/// uses.
+async fn validate_task_query<E: QueryExecutor>(
+    executor: &E,
+    config: &Config,
+    task: &CleanupTask,
+    sql: &str,
+    hooks: &BatchHooks,
+) -> Result<()> {
+    let validator = SqlValidator::new(config)
+        .with_lookup(task.lookup.is_some())
+        .with_min_retention_days(task.min_retention_days);
+    validator
+        .with_retention_column(task.retention_column.as_deref())
+        .validate_sql_query(sql)?;
+    if !task.allow_unsafe_hooks {
+        hooks.queries().try_for_each(|hook| {
+            validator.validate_sql_query(hook).map_err(|e| {
+                anyhow::anyhow!(
+                    "batch hook rejected: {} (set allow_unsafe_hooks to run hooks unvalidated)",
+                    e
+                )
+            })
+        })?;
+    }
+    if config.safe_mode.require_indexed_predicate {
+        check_indexed_predicate(executor, config, sql).await?;
+    }
+    Ok(())
+}
+
+/// Logs a query that failed validation with `safe_mode.mode: warn` and will run anyway, and
+/// reports it to Slack with `safe_mode.notify_warnings`.
+async fn warn_validation_failure(
+    config: &Config,
+    task: &CleanupTask,
+    slack_client: &Option<SlackClient>,
+    error: &anyhow::Error,
+) {
+    warn!(
+        "[safe_mode=warn] SQL validation failed for task: {}, executing anyway: {:#}",
+        task.name, error
+    );
+    if let (Some(slack_client), true) = (slack_client, config.safe_mode.notify_warnings) {
+        send_slack_report(
+            slack_client,
+            config,
+            SlackEvent::Error,
+            &create_validation_warning_report(
+                &CleanupMetadata::new(config, task),
+                &format!("{:#}", error),
+            ),
+            "validation warning",
+        )
+        .await;
+    }
+}
+
+/// Validates the query of a table or lookup list after the first, which was validated with the
+/// task's other queries before the run. `label` names it in a warning. Fails if safe mode rejects
+/// it, unless `safe_mode.mode: warn` reports it and lets it run.
+async fn validate_later_query<E: QueryExecutor>(
+    executor: &E,
+    config: &Config,
+    task: &CleanupTask,
+    slack_client: &Option<SlackClient>,
+    label: &str,
+    rendered: (String, BatchHooks),
+) -> Result<(String, BatchHooks)> {
+    if !config.safe_mode.enabled {
+        return Ok(rendered);
+    }
+    let (sql, hooks) = &rendered;
+    match validate_task_query(executor, config, task, sql, hooks).await {
+        Err(e) if config.safe_mode.mode == SafeModeEnforcement::Warn => {
+            warn_validation_failure(config, task, slack_client, &e.context(label.to_string()))
+                .await;
+            Ok(rendered)
+        }
+        Err(e) => Err(e),
+        Ok(()) => Ok(rendered),
+    }
+}
+
+/// Runs a DELETE loop for each `lookup_ids` list in turn, stopping at the first loop that does
+/// not complete. `render` renders the task's templates for a list. Lists after the first are
+/// validated on their own, as only the first one was. The lists share the task's
+/// `max_rows_per_run` and `max_batches_per_run`.
+async fn run_lookup_delete_loops<E: QueryExecutor>(
+    executor: &E,
+    config: &Config,
+    task: &CleanupTask,
+    lookup_lists: &[String],
+    render: impl Fn(&str) -> Result<(String, BatchHooks)>,
+    progress_tracker: &Mutex<ProgressTracker>,
+    slack_client: &Option<SlackClient>,
+) -> DeleteLoopSummary {
+    let mut total = DeleteLoopSummary::new(DeleteLoopOutcome::Completed);
+    let mut budget = LoopBudget::of(task);
+    for (index, list) in lookup_lists.iter().enumerate() {
+        info!(
+            "Deleting by lookup list {}/{} for task: {}",
+            index + 1,
+            lookup_lists.len(),
+            task.name
+        );
+        let rendered = match render(list) {
+            Ok(rendered) if index > 0 => {
+                let label = format!("lookup list {} rejected", index + 1);
+                validate_later_query(executor, config, task, slack_client, &label, rendered).await
+            }
+            rendered => rendered,
+        };
+        let summary = match rendered {
+            Ok((sql, hooks)) => {
+                run_delete_loop_within(
+                    executor,
+                    config,
+                    task,
+                    &sql,
+                    &hooks,
+                    progress_tracker,
+                    budget,
+                )
+                .await
+            }
+            Err(e) => DeleteLoopSummary::new(DeleteLoopOutcome::Failed(
+                e.context(format!("lookup list {} rejected", index + 1)),
+            )),
+        };
+        budget = budget.after(&summary);
+        total.add(summary);
+        if !matches!(total.outcome, DeleteLoopOutcome::Completed) {
+            break;
+        }
+    }
+    total
+}
+
+/// The tables a task with `tables` or `tables_query` runs against, in order. Rows of
+/// `tables_query` that are NULL or repeat an earlier table are skipped.
+async fn task_tables(
+    db: &Database,
+    task: &CleanupTask,
+    template_engine: &TemplateEngine,
+    template_parameters: &HashMap<String, String>,
+    data_interval_end: &str,
+) -> Result<Vec<String>> {
+    let Some(tables_query) = &task.tables_query else {
+        return Ok(task.tables.clone());
+    };
+    let query = template_engine
+        .render(tables_query, template_parameters, data_interval_end)
+        .context("failed to render tables_query")?;
+    let (_, rows) = db
+        .fetch_text_rows(&query)
+        .await
+        .context("tables_query failed")?;
+    let mut seen = HashSet::new();
+    let tables: Vec<String> = rows
+        .into_iter()
+        .filter_map(|row| row.into_iter().next().flatten())
+        .filter(|table| seen.insert(table.clone()))
+        .collect();
+    info!(
+        "tables_query of task {} returned {} tables",
+        task.name,
+        tables.len()
+    );
+    Ok(tables)
+}
+
+/// The DELETE loop of one of a task's tables.
+#[derive(Debug)]
+struct TableRun {
+    table: String,
+    /// The rendered DELETE, `None` if the table's templates could not be rendered or validated
+    sql: Option<String>,
+    summary: DeleteLoopSummary,
+}
+
+/// Runs a DELETE loop for each table in turn, rendered by `render` with the table as
+/// `table_name`. A failed table does not stop the others. Tables after the first are validated on
+/// their own, as only the first one was. The tables share the task's `max_rows_per_run` and
+/// `max_batches_per_run`, so once they are used up the remaining tables are left for a later run.
+async fn run_table_delete_loops<E: QueryExecutor>(
+    executor: &E,
+    config: &Config,
+    task: &CleanupTask,
+    tables: &[String],
+    render: impl Fn(&str) -> Result<(String, BatchHooks)>,
+    progress_tracker: &Mutex<ProgressTracker>,
+    slack_client: &Option<SlackClient>,
+) -> Vec<TableRun> {
+    let mut table_runs = Vec::new();
+    let mut budget = LoopBudget::of(task);
+    for (index, table) in tables.iter().enumerate() {
+        info!(
+            "Cleaning up table {}/{} {} for task: {}",
+            index + 1,
+            tables.len(),
+            table,
+            task.name
+        );
+        let rendered = match render(table) {
+            Ok(rendered) if index > 0 => {
+                let label = format!("table {} rejected", table);
+                validate_later_query(executor, config, task, slack_client, &label, rendered).await
+            }
+            rendered => rendered,
+        };
+        let table_run = match rendered {
+            Ok((sql, hooks)) => TableRun {
+                table: table.clone(),
+                summary: run_delete_loop_within(
+                    executor,
+                    config,
+                    task,
+                    &sql,
+                    &hooks,
+                    progress_tracker,
+                    budget,
+                )
+                .await,
+                sql: Some(sql),
+            },
+            Err(e) => TableRun {
+                table: table.clone(),
+                sql: None,
+                summary: DeleteLoopSummary::new(DeleteLoopOutcome::Failed(
+                    e.context("table rejected"),
+                )),
+            },
+        };
+        info!(
+            "Table {} of task {}: {} rows deleted in {}",
+            table,
+            task.name,
+            format_count(table_run.summary.total_rows),
+            humanize_time(table_run.summary.elapsed_time)
+        );
+        budget = budget.after(&table_run.summary);
+        table_runs.push(table_run);
+    }
+    table_runs
+}
+
+/// Optimizes the tables of a task with `tables` and sends one report per table or a combined
+/// one, as set by `table_report`. Fails if any table failed.
+async fn report_table_runs<E: QueryExecutor>(
+    executor: &E,
+    config: &Config,
+    task: &CleanupTask,
+    template_parameters: &HashMap<String, String>,
+    table_runs: Vec<TableRun>,
+    db_clock: Option<DbClock>,
+    slack_client: &Option<SlackClient>,
+) -> Result<(), anyhow::Error> {
+    let mut optimize_results = Vec::new();
+    for table_run in &table_runs {
+        let optimize_result = match (&table_run.sql, &table_run.summary.outcome) {
+            (Some(sql), DeleteLoopOutcome::Completed | DeleteLoopOutcome::Capped) => {
+                optimize_after_cleanup(
+                    executor,
+                    config,
+                    task,
+                    sql,
+                    table_run.summary.total_rows,
+                    Utc::now(),
+                )
+                .await
+            }
+            _ => None,
+        };
+        optimize_results.push(optimize_result);
+    }
+    let failed_tables: Vec<&str> = table_runs
+        .iter()
+        .filter(|table_run| matches!(table_run.summary.outcome, DeleteLoopOutcome::Failed(_)))
+        .map(|table_run| table_run.table.as_str())
+        .collect();
+
+    let mut reports = Vec::new();
+    match task.table_report {
+        TableReport::PerTable => {
+            for (table_run, optimize_result) in table_runs.iter().zip(optimize_results) {
+                let mut parameters = template_parameters.clone();
+                parameters.insert("table_name".to_string(), table_run.table.clone());
+                let mut metadata = CleanupMetadata::new(config, task);
+                metadata.table_name = Some(&table_run.table);
+                metadata.db_clock = db_clock;
+                metadata.server_time = table_run.summary.server_time;
+                metadata.total_rows = table_run.summary.total_rows;
+                metadata.elapsed_time = table_run.summary.elapsed_time;
+                metadata.sql = table_run.sql.as_deref();
+                metadata.parameters = Some(&parameters);
+                reports.push(match &table_run.summary.outcome {
+                    DeleteLoopOutcome::Failed(e) => (
+                        SlackEvent::Error,
+                        create_error_report(
+                            &metadata,
+                            &format!(
+                                "All attempts failed for task: {} on table {}, error: {:#}",
+                                task.name, table_run.table, e
+                            ),
+                        ),
+                    ),
+                    outcome => {
+                        metadata.capped = matches!(outcome, DeleteLoopOutcome::Capped);
+                        metadata.optimize_result = optimize_result;
+                        (SlackEvent::Success, create_cleanup_report(metadata))
+                    }
+                });
+            }
+        }
+        TableReport::Combined => {
+            let tables = table_runs
+                .iter()
+                .map(|table_run| table_run.table.as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+            let mut metadata = CleanupMetadata::new(config, task);
+            metadata.table_name = Some(&tables);
+            metadata.db_clock = db_clock;
+            for table_run in &table_runs {
+                metadata.total_rows += table_run.summary.total_rows;
+                metadata.elapsed_time += table_run.summary.elapsed_time;
+                if let Some(server_time) = table_run.summary.server_time {
+                    metadata.server_time = Some(metadata.server_time.unwrap_or(0.0) + server_time);
+                }
+            }
+            reports.push(if failed_tables.is_empty() {
+                metadata.capped = table_runs.iter().any(|table_run| {
+                    matches!(table_run.summary.outcome, DeleteLoopOutcome::Capped)
+                });
+                let optimize_results: Vec<String> = table_runs
+                    .iter()
+                    .zip(optimize_results)
+                    .filter_map(|(table_run, result)| {
+                        result.map(|result| format!("{}: {}", table_run.table, result))
+                    })
+                    .collect();
+                metadata.optimize_result =
+                    (!optimize_results.is_empty()).then(|| optimize_results.join(", "));
+                (SlackEvent::Success, create_cleanup_report(metadata))
+            } else {
+                let errors: Vec<String> = table_runs
+                    .iter()
+                    .filter_map(|table_run| match &table_run.summary.outcome {
+                        DeleteLoopOutcome::Failed(e) => {
+                            Some(format!("{}: {:#}", table_run.table, e))
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                (
+                    SlackEvent::Error,
+                    create_error_report(
+                        &metadata,
+                        &format!(
+                            "All attempts failed for task: {} on {} of {} tables, errors: {}",
+                            task.name,
+                            failed_tables.len(),
+                            table_runs.len(),
+                            errors.join("; ")
+                        ),
+                    ),
+                )
+            });
+        }
+    }
+
+    if let Some(slack_client) = slack_client {
+        for (event, report) in &reports {
+            send_slack_report(slack_client, config, *event, report, "cleanup report").await;
+        }
+    }
+
+    if !failed_tables.is_empty() {
+        warn!(
+            "All attempts failed for task: {} on tables: {}",
+            task.name,
+            failed_tables.join(", ")
+        );
+        return Err(anyhow::anyhow!(
+            "All attempts failed for task: {} on tables: {}",
+            task.name,
+            failed_tables.join(", ")
+        ));
+    }
+    info!("Cleanup process completed");
+    Ok(())
+}
+
+/// Rendered `pre_batch_query`, `post_batch_query` and `completion_check_query` of a task.
+#[derive(Debug, Default)]
+struct BatchHooks {
+    pre_batch: Option<String>,
+    post_batch: Option<String>,
+    completion_check: Option<CompletionCheck>,
+}
+
+impl BatchHooks {
+    /// The hooks that modify data, validated like the DELETE.
+    fn queries(&self) -> impl Iterator<Item = &String> {
+        [&self.pre_batch, &self.post_batch].into_iter().flatten()
+    }
+}
+
+/// A rendered `completion_check_query`, run after batches smaller than `batch_size`.
+#[derive(Debug)]
+struct CompletionCheck {
+    query: String,
+    batch_size: u64,
+}
+
+/// The rows still to delete according to the task's `completion_check_query`, if it has one and
+/// the batch deleted fewer than `batch_size` rows. A failing check is logged and ignored, so
+/// completion falls back to empty batches.
+async fn remaining_rows<E: QueryExecutor>(
+    executor: &E,
+    task: &CleanupTask,
+    hooks: &BatchHooks,
+    affected_rows: u64,
+) -> Option<u64> {
+    let check = hooks.completion_check.as_ref()?;
+    if affected_rows >= check.batch_size {
+        return None;
+    }
+    match executor.fetch_count(&check.query).await {
+        Ok(remaining) => {
+            info!(
+                "completion_check_query reports {} rows remaining for task: {}",
+                remaining, task.name
+            );
+            Some(remaining)
+        }
+        Err(e) => {
+            warn!(
+                "completion_check_query failed for task: {}, deciding completion by empty \
+                 batches: {}",
+                task.name, e
+            );
+            None
+        }
+    }
+}
+
+/// Runs one DELETE batch between the task's batch hooks. A failing hook fails the batch like a
+/// failed DELETE; only the DELETE's own elapsed time is returned, along with its time on the
+/// server if `measure_server_time` is set and the server reports it.
+async fn execute_batch<E: QueryExecutor>(
+    executor: &E,
+    task: &CleanupTask,
+    sql: &str,
+    hooks: &BatchHooks,
+    measure_server_time: bool,
+) -> Result<(u64, f64, Option<f64>)> {
+    if let Some(pre_batch) = &hooks.pre_batch {
+        run_batch_hook(executor, task, "pre_batch_query", pre_batch).await?;
+    }
+    let result = if measure_server_time {
+        executor.execute_query_with_server_time(sql).await?
+    } else {
+        let (affected_rows, elapsed_in_secs) = executor.execute_query(sql).await?;
+        (affected_rows, elapsed_in_secs, None)
+    };
+    if let Some(post_batch) = &hooks.post_batch {
+        run_batch_hook(executor, task, "post_batch_query", post_batch).await?;
+    }
+    Ok(result)
+}
+
+/// Runs the task's `warmup_query`, once per run before its first DELETE batch, returning how long
+/// it took.
+async fn run_warmup_query<E: QueryExecutor>(
+    executor: &E,
+    task: &CleanupTask,
+    query: &str,
+) -> Result<f64> {
+    info!("Running warmup_query for task: {}\n{}", task.name, query);
+    let (affected_rows, elapsed_in_secs) = executor.execute_query(query).await.map_err(|e| {
+        anyhow::anyhow!("warmup_query failed for task: {}, error: {}", task.name, e)
+    })?;
+    info!(
+        "Ran warmup_query for task: {} ({} rows affected) in {}",
+        task.name,
+        affected_rows,
+        humanize_time(elapsed_in_secs)
+    );
+    Ok(elapsed_in_secs)
+}
+
+/// Runs the task's first DELETE batch in a transaction that is rolled back, with
+/// `trial_rollback_first`, returning how many rows it would have deleted.
+async fn run_trial<E: QueryExecutor>(executor: &E, task: &CleanupTask, sql: &str) -> Result<u64> {
+    info!(
+        "Running trial of task {} in a rolled back transaction",
+        task.name
+    );
+    let (affected_rows, elapsed_in_secs) = executor
+        .trial_execute_query(sql)
+        .await
+        .map_err(|e| anyhow::anyhow!("Trial run failed for task: {}, error: {}", task.name, e))?;
+    info!(
+        "Trial of task {} would delete {} rows in its first batch ({}), rolled back",
+        task.name,
+        format_count(affected_rows),
+        humanize_time(elapsed_in_secs)
+    );
+    Ok(affected_rows)
+}
+
+/// Rows deleted by one `child_cleanup` step of a run.
+#[derive(Debug, PartialEq)]
+struct ChildStepRun {
+    name: String,
+    total_rows: u64,
+}
+
+/// Renders the task's `child_cleanup` steps in order as `(name, query)`. Each has to be a DELETE
+/// with a LIMIT, with or without safe mode.
+fn render_child_cleanup(
+    config: &Config,
+    template_engine: &TemplateEngine,
+    task: &CleanupTask,
+    template_parameters: &HashMap<String, String>,
+    data_interval_end: &str,
+) -> Result<Vec<(String, String)>> {
+    task.child_cleanup
+        .iter()
+        .map(|child| {
+            let sql = template_engine
+                .render(
+                    &child.template_query,
+                    template_parameters,
+                    data_interval_end,
+                )
+                .with_context(|| format!("failed to render child_cleanup '{}'", child.name))?;
+            check_rendered_query(&sql)
+                .and_then(|()| SqlValidator::new(config).validate_bounded_delete(&sql))
+                .with_context(|| format!("child_cleanup '{}' rejected", child.name))?;
+            Ok((child.name.clone(), sql))
+        })
+        .collect()
+}
+
+/// Runs the task's `child_cleanup` steps in order, each until it deletes no more rows, then the
+/// task's own DELETE loop. The steps and the parent share the task's `max_rows_per_run` and
+/// `max_batches_per_run`, and the summary of the run counts the rows of all of them. A step that
+/// does not complete ends the run there, as the child rows it left would fail the parent DELETE on
+/// their foreign keys. Also returns the rows deleted by each step that ran.
+async fn run_with_child_cleanup<E: QueryExecutor>(
+    executor: &E,
+    config: &Config,
+    task: &CleanupTask,
+    child_queries: &[(String, String)],
+    sql: &str,
+    hooks: &BatchHooks,
+    progress_tracker: &Mutex<ProgressTracker>,
+) -> (Vec<ChildStepRun>, DeleteLoopSummary) {
+    let mut child_steps = Vec::new();
+    let mut total = DeleteLoopSummary::new(DeleteLoopOutcome::Completed);
+    let mut budget = LoopBudget::of(task);
+    for (index, (name, query)) in child_queries.iter().enumerate() {
+        info!(
+            "Running child_cleanup step {}/{} '{}' for task: {}",
+            index + 1,
+            child_queries.len(),
+            name,
+            task.name
+        );
+        let summary = run_delete_loop_within(
+            executor,
+            config,
+            task,
+            query,
+            &BatchHooks::default(),
+            progress_tracker,
+            budget,
+        )
+        .await;
+        info!(
+            "child_cleanup step '{}' of task {}: {} rows deleted in {}",
+            name,
+            task.name,
+            format_count(summary.total_rows),
+            humanize_time(summary.elapsed_time)
+        );
+        child_steps.push(ChildStepRun {
+            name: name.clone(),
+            total_rows: summary.total_rows,
+        });
+        budget = budget.after(&summary);
+        let outcome = match summary.outcome {
+            DeleteLoopOutcome::Completed => DeleteLoopOutcome::Completed,
+            DeleteLoopOutcome::Capped => {
+                info!(
+                    "Not running the DELETE of task {} until child_cleanup step '{}' completes",
+                    task.name, name
+                );
+                DeleteLoopOutcome::Capped
+            }
+            DeleteLoopOutcome::Failed(e) => DeleteLoopOutcome::Failed(anyhow::anyhow!(
+                "child_cleanup step '{}' failed: {:#}",
+                name,
+                e
+            )),
+        };
+        let completed = matches!(outcome, DeleteLoopOutcome::Completed);
+        total.add(DeleteLoopSummary { outcome, ..summary });
+        if !completed {
+            return (child_steps, total);
+        }
+    }
+    let summary =
+        run_delete_loop_within(executor, config, task, sql, hooks, progress_tracker, budget).await;
+    total.add(summary);
+    (child_steps, total)
+}
+
+async fn run_batch_hook<E: QueryExecutor>(
+    executor: &E,
+    task: &CleanupTask,
+    name: &str,
+    query: &str,
+) -> Result<()> {
+    let (affected_rows, elapsed_in_secs) = executor
+        .execute_query(query)
+        .await
+        .map_err(|e| anyhow::anyhow!("{} failed for task: {}, error: {}", name, task.name, e))?;
+    info!(
+        "Ran {} for task: {} ({} rows affected) in {}",
+        name,
+        task.name,
+        affected_rows,
+        humanize_time(elapsed_in_secs)
+    );
+    Ok(())
+}
+
+/// Logs the executor's pool connections at debug level and exports them as the
+/// `kiyoshi_db_pool_connections` gauge.
+fn record_pool_status<E: QueryExecutor>(executor: &E, task: &CleanupTask) {
+    let Some(status) = executor.pool_status() else {
+        return;
+    };
+    debug!(
+        "Connection pool of task {}: {} open, {} in use, {} idle",
+        task.name,
+        status.size,
+        status.in_use(),
+        status.idle
+    );
+    for (state, connections) in [("in_use", status.in_use()), ("idle", status.idle)] {
+        metrics().set_gauge(
+            "kiyoshi_db_pool_connections",
+            &[("task", &task.name), ("state", state)],
+            connections as f64,
+        );
+    }
+}
+
+/// [`run_delete_loop_within`] with the whole of the task's row and batch caps.
+#[cfg(test)]
+async fn run_delete_loop<E: QueryExecutor>(
+    executor: &E,
+    config: &Config,
+    task: &CleanupTask,
+    sql: &str,
+    hooks: &BatchHooks,
+    progress_tracker: &Mutex<ProgressTracker>,
+) -> DeleteLoopSummary {
+    let budget = LoopBudget::of(task);
+    run_delete_loop_within(executor, config, task, sql, hooks, progress_tracker, budget).await
+}
+
+/// Runs the cleanup query in batches with retries until a batch affects no rows, the row cap is
+/// reached or all retry attempts fail, with only `budget` left of the task's row and batch caps.
+async fn run_delete_loop_within<E: QueryExecutor>(
+    executor: &E,
+    config: &Config,
+    task: &CleanupTask,
+    sql: &str,
+    hooks: &BatchHooks,
+    progress_tracker: &Mutex<ProgressTracker>,
+    budget: LoopBudget,
+) -> DeleteLoopSummary {
+    // Failed attempts of the current batch, reset by a successful batch, and of the whole run
+    let mut batch_failures: u32 = 0;
+    let mut total_failures: u32 = 0;
+    let mut total_rows: u64 = 0;
+    let mut total_time_elapsed: f64 = 0.0;
+    let mut total_server_time: Option<f64> = None;
+    let mut consecutive_empty_batches: u32 = 0;
+    let mut batches: u32 = 0;
+    progress_tracker
+        .lock()
+        .unwrap()
+        .statements
+        .push(sql.to_string());
+    let dialect = config.database_config.dialect;
+    if budget.max_rows.is_some() && lower_delete_limit(sql, dialect, u64::MAX).is_none() {
+        warn!(
+            "The last batch of task {} may exceed max_rows_per_run, as its query has no literal LIMIT to lower",
+            task.name
+        );
+    }
+
+    let outcome = loop {
+        if budget
+            .max_batches
+            .is_some_and(|max_batches| batches >= max_batches)
+        {
+            let max_batches_per_run = task.max_batches_per_run.unwrap_or_default();
+            warn!(
+                "Reached max_batches_per_run of {} for task: {}, aborting the run",
+                max_batches_per_run, task.name
+            );
+            break DeleteLoopOutcome::Failed(anyhow::anyhow!(
+                "Aborted after reaching max_batches_per_run of {}",
+                max_batches_per_run
+            ));
+        }
+        if budget
+            .max_rows
+            .is_some_and(|max_rows| total_rows >= max_rows)
+        {
+            info!(
+                "Reached max_rows_per_run of {} for task: {}, more rows may remain for the next run",
+                task.max_rows_per_run.unwrap_or_default(),
+                task.name
+            );
+            break DeleteLoopOutcome::Capped;
+        }
+        // The last batch before max_rows_per_run only deletes the rows the cap has left
+        let batch_sql = budget
+            .max_rows
+            .and_then(|max_rows| lower_delete_limit(sql, dialect, max_rows - total_rows))
+            .unwrap_or_else(|| sql.to_string());
+        batches += 1;
+        progress_tracker.lock().unwrap().batches += 1;
+        info!("Executing sql query: \n{}", batch_sql);
+        let batch_result = execute_batch(
+            executor,
+            task,
+            &batch_sql,
+            hooks,
+            config.measure_server_time,
+        )
+        .await;
+        record_pool_status(executor, task);
+        match batch_result {
+            Ok((affected_rows, elapsed_in_secs, server_time)) => {
+                batch_failures = 0;
+                let remaining = remaining_rows(executor, task, hooks, affected_rows).await;
+                if affected_rows == 0 {
+                    consecutive_empty_batches += 1;
+                    let max_empty_batches_reached = task
+                        .max_empty_batches
+                        .is_some_and(|max| consecutive_empty_batches >= max);
+                    // The completion check is authoritative over stop_on_zero_rows
+                    let complete = match remaining {
+                        Some(remaining) => remaining == 0 || max_empty_batches_reached,
+                        None => task.stop_on_zero_rows || max_empty_batches_reached,
+                    };
+                    if complete {
+                        info!(
+                            "No more rows to clean up. Total rows cleaned: {} for task: {} in {}",
+                            format_count(total_rows),
+                            task.name,
+                            humanize_time(elapsed_in_secs)
+                        );
+                        break DeleteLoopOutcome::Completed;
+                    }
+                    if remaining.is_some() {
+                        info!(
+                            "Empty batch {} for task: {}, continuing as rows remain",
+                            consecutive_empty_batches, task.name
+                        );
+                    } else {
+                        info!(
+                            "Empty batch {} for task: {}, continuing as stop_on_zero_rows is false",
+                            consecutive_empty_batches, task.name
+                        );
+                    }
+                    tokio::time::sleep(task.query_interval(elapsed_in_secs)).await;
+                    continue;
+                }
+                consecutive_empty_batches = 0;
+                total_time_elapsed += elapsed_in_secs;
+                total_rows += affected_rows;
+                if let Some(server_time) = server_time {
+                    total_server_time = Some(total_server_time.unwrap_or(0.0) + server_time);
+                }
+
+                {
+                    let mut tracker = progress_tracker.lock().unwrap();
+                    tracker.total_rows += affected_rows;
+                    tracker.elapsed_time += elapsed_in_secs;
+                }
+
+                info!(
+                    "Successfully cleaned up {} rows (total: {}) for task: {} in {} ({}){}",
+                    format_count(affected_rows),
+                    format_count(total_rows),
+                    task.name,
+                    humanize_time(elapsed_in_secs),
+                    humanize_rate(affected_rows, elapsed_in_secs),
+                    server_time
+                        .map(|server_time| format!(
+                            ", {} on the server",
+                            humanize_time(server_time)
+                        ))
+                        .unwrap_or_default()
+                );
+                report_slow_query(executor, config, task, &batch_sql, elapsed_in_secs).await;
+
+                if budget
+                    .max_rows
+                    .is_some_and(|max_rows| total_rows >= max_rows)
+                {
+                    info!(
+                        "Reached max_rows_per_run of {} for task: {}, more rows may remain for the next run",
+                        task.max_rows_per_run.unwrap_or_default(),
+                        task.name
+                    );
+                    break DeleteLoopOutcome::Capped;
+                }
+                if remaining == Some(0) {
+                    info!(
+                        "No more rows to clean up. Total rows cleaned: {} for task: {}",
+                        format_count(total_rows),
+                        task.name
+                    );
+                    break DeleteLoopOutcome::Completed;
+                }
+                tokio::time::sleep(task.query_interval(elapsed_in_secs)).await;
+            }
+            Err(e) => {
+                batch_failures += 1;
+                total_failures += 1;
+                warn!(
+                    "Attempt {}/{} failed for task {} ({} failed attempts this run): {}",
+                    batch_failures,
+                    task.batch_retry_attempts(),
+                    task.name,
+                    total_failures,
+                    e
+                );
+                if batch_failures >= task.batch_retry_attempts() {
+                    break DeleteLoopOutcome::Failed(e);
+                }
+                if let Some(max_total_failures) = task.max_total_failures {
+                    if total_failures >= max_total_failures {
+                        warn!(
+                            "Reached max_total_failures of {} for task: {}",
+                            max_total_failures, task.name
+                        );
+                        break DeleteLoopOutcome::Failed(e);
+                    }
+                }
+                tokio::time::sleep(Duration::from_secs(task.retry_delay_seconds.into())).await;
+            }
+        }
+    };
+
+    DeleteLoopSummary {
+        total_rows,
+        elapsed_time: total_time_elapsed,
+        server_time: total_server_time,
+        batches,
+        outcome,
+    }
+}
+
+/// With `allow_truncate_when_fully_expired`, truncates the task's table instead of running the
+/// DELETE loop when the DELETE would spare none of its rows. The spared rows are counted again
+/// right before the TRUNCATE, which only runs if both counts are exactly zero. Returns `None`, to
+/// run the DELETE loop instead, when rows are spared, the table is empty or holds more rows than
+/// `max_rows_per_run`, or any step fails.
+async fn truncate_if_fully_expired<E: QueryExecutor>(
+    executor: &E,
+    task: &CleanupTask,
+    queries: &TruncateQueries,
+    progress_tracker: &Mutex<ProgressTracker>,
+) -> Option<DeleteLoopSummary> {
+    let checked = async {
+        let spared_rows = executor.fetch_count(&queries.spared_rows).await?;
+        if spared_rows > 0 {
+            return Ok(None);
+        }
+        let total_rows = executor.fetch_count(&queries.total_rows).await?;
+        if total_rows == 0 {
+            return Ok(None);
+        }
+        // Verified once more so rows written since the first count are never truncated
+        let spared_rows = executor.fetch_count(&queries.spared_rows).await?;
+        Ok::<_, anyhow::Error>((spared_rows == 0).then_some(total_rows))
+    }
+    .await;
+    let total_rows = match checked {
+        Ok(Some(total_rows)) => total_rows,
+        Ok(None) => {
+            info!(
+                "Table of task {} is not fully expired, running the DELETE loop",
+                task.name
+            );
+            return None;
+        }
+        Err(e) => {
+            warn!(
+                "Failed to check whether the table of task {} is fully expired, running the \
+                 DELETE loop: {:#}",
+                task.name, e
+            );
+            return None;
+        }
+    };
+    // The TRUNCATE would bypass max_rows_per_run, which the DELETE loop honours
+    if let Some(max_rows_per_run) = task
+        .max_rows_per_run
+        .filter(|max_rows_per_run| total_rows > *max_rows_per_run)
+    {
+        info!(
+            "Not truncating the table of task {} as its {} rows exceed max_rows_per_run of {}, \
+             running the DELETE loop",
+            task.name,
+            format_count(total_rows),
+            format_count(max_rows_per_run)
+        );
+        return None;
+    }
+
+    info!(
+        "All {} rows of the table of task {} are expired, truncating it:\n{}",
+        format_count(total_rows),
+        task.name,
+        queries.truncate
+    );
+    let elapsed_time = match executor.execute_query(&queries.truncate).await {
+        Ok((_, elapsed_time)) => elapsed_time,
+        Err(e) => {
+            warn!(
+                "Failed to truncate the table of task {}, running the DELETE loop: {:#}",
+                task.name, e
+            );
+            return None;
+        }
+    };
+    {
+        let mut tracker = progress_tracker.lock().unwrap();
+        tracker.statements.push(queries.truncate.clone());
+        tracker.batches += 1;
+        tracker.total_rows += total_rows;
+        tracker.elapsed_time += elapsed_time;
+    }
+    Some(DeleteLoopSummary {
+        total_rows,
+        elapsed_time,
+        server_time: None,
+        batches: 0,
+        outcome: DeleteLoopOutcome::Completed,
+    })
+}
+
+/// Renders the task's `allow_truncate_when_fully_expired` queries, if it has any.
+fn render_truncate_queries(
+    template_engine: &TemplateEngine,
+    task: &CleanupTask,
+    template_parameters: &HashMap<String, String>,
+    data_interval_end: &str,
+) -> Result<Option<TruncateQueries>> {
+    let Some(templates) = task.truncate_templates()? else {
+        return Ok(None);
+    };
+    let render = |template: &str| {
+        template_engine
+            .render(template, template_parameters, data_interval_end)
+            .context("failed to render the allow_truncate_when_fully_expired queries")
+    };
+    Ok(Some(TruncateQueries {
+        spared_rows: render(&templates.spared_rows)?,
+        total_rows: render(&templates.total_rows)?,
+        truncate: render(&templates.truncate)?,
+    }))
+}
+
+/// Runs `OPTIMIZE TABLE` on the DELETE's target when the task's `optimize_after_cleanup` threshold
+/// and window allow it, returning the elapsed time (or the error) for the report.
+async fn optimize_after_cleanup<E: QueryExecutor>(
+    executor: &E,
+    config: &Config,
+    task: &CleanupTask,
+    sql: &str,
+    total_rows: u64,
+    now: DateTime<Utc>,
+) -> Option<String> {
+    let optimize = task.optimize_after_cleanup.as_ref()?;
+    if !optimize.should_run(total_rows, now) {
+        info!(
+            "Skipping OPTIMIZE TABLE for task: {} ({} rows deleted, min_rows {}, window {:?})",
+            task.name, total_rows, optimize.min_rows, optimize.allowed_window
+        );
+        return None;
+    }
+
+    let result = async {
+        let dialect = config.database_config.dialect;
+        let predicate = delete_predicate(sql, dialect)?;
+        let quote = dialect.identifier_quote();
+        let mut target = quote_identifier(&predicate.table, quote)?;
+        if let Some(schema) = &predicate.schema {
+            target = format!("{}.{}", quote_identifier(schema, quote)?, target);
+        }
+        info!("Running OPTIMIZE TABLE {} for task: {}", target, task.name);
+        let (_, elapsed_in_secs) = executor
+            .execute_query(&format!("OPTIMIZE TABLE {}", target))
+            .await?;
+        Ok::<_, anyhow::Error>(elapsed_in_secs)
+    }
+    .await;
+
+    Some(match result {
+        Ok(elapsed_in_secs) => {
+            info!(
+                "OPTIMIZE TABLE finished for task: {} in {}",
+                task.name,
+                humanize_time(elapsed_in_secs)
+            );
+            humanize_time(elapsed_in_secs)
+        }
+        Err(e) => {
+            warn!("OPTIMIZE TABLE failed for task: {}: {}", task.name, e);
+            format!("failed: {}", e)
+        }
+    })
+}
+
+/// Renders a task's `template_query` and batch hooks.
+fn render_task_templates(
+    template_engine: &TemplateEngine,
+    task: &CleanupTask,
+    template_parameters: &HashMap<String, String>,
+    data_interval_end: &str,
+) -> Result<(String, BatchHooks)> {
+    let render = |name: &str, template: &str| {
+        template_engine
+            .render(template, template_parameters, data_interval_end)
+            .with_context(|| format!("failed to render {}", name))
+    };
+    let sql = render("template_query", &task.template_query)?;
+    check_rendered_query(&sql)?;
+    let hooks = BatchHooks {
+        pre_batch: task
+            .pre_batch_query
+            .as_deref()
+            .map(|hook| render("pre_batch_query", hook))
+            .transpose()?,
+        post_batch: task
+            .post_batch_query
+            .as_deref()
+            .map(|hook| render("post_batch_query", hook))
+            .transpose()?,
+        completion_check: task
+            .completion_check_query
+            .as_deref()
+            .map(|query| {
+                Ok::<_, anyhow::Error>(CompletionCheck {
+                    query: render("completion_check_query", query)?,
+                    batch_size: template_parameters
+                        .get("batch_size")
+                        .and_then(|batch_size| batch_size.parse().ok())
+                        .unwrap_or(task.batch_size as u64),
+                })
+            })
+            .transpose()?,
+    };
+    Ok((sql, hooks))
+}
+
+/// Rejects a query that rendered to nothing, e.g. from a missing parameter, before it reaches
+/// the SQL parser with a less helpful error.
+fn check_rendered_query(sql: &str) -> Result<()> {
+    if sql.trim().is_empty() {
+        return Err(anyhow::anyhow!(
+            "rendered query is empty, check template_query and parameters"
+        ));
+    }
+    Ok(())
+}
+
+/// The batch size for `batch_size_percent`, from the estimated row count of the table `sql`
+/// deletes from.
+async fn percent_batch_size<E: QueryExecutor>(
+    executor: &E,
+    config: &Config,
+    sql: &str,
+    percent: f64,
+) -> Result<u32> {
+    let database_config = &config.database_config;
+    let predicate = delete_predicate(sql, database_config.dialect)?;
+    let schema = predicate
+        .schema
+        .as_deref()
+        .unwrap_or(&database_config.database);
+    let table_rows = executor.estimated_rows(schema, &predicate.table).await?;
+    Ok(batch_size_from_percent(percent, table_rows))
+}
+
+/// `percent` of `table_rows`, rounded down but at least 1 so the run can make progress.
+fn batch_size_from_percent(percent: f64, table_rows: u64) -> u32 {
+    let batch_size = (table_rows as f64 * percent / 100.0).floor();
+    batch_size.clamp(1.0, u32::MAX as f64) as u32
+}
+
+/// Renders a task's query as of `data_interval_end` and, with safe mode enabled, validates it,
+/// without touching the database.
+pub(crate) fn render_task_query(
+    config: &Config,
+    task: &CleanupTask,
+    data_interval_end: DateTime<Utc>,
+) -> Result<String> {
+    let template_engine =
+        TemplateEngine::with_identifier_quote(config.database_config.dialect.identifier_quote())
+            .with_data_interval_start(format_data_interval(
+                task.data_interval_start(data_interval_end),
+                task.data_interval_format(config),
+            ));
+    let mut template_parameters = build_template_parameters(config, task);
+    if let Some(table) = task.tables.first() {
+        template_parameters.insert("table_name".to_string(), table.clone());
+    }
+    let sql = template_engine.render(
+        &task.template_query,
+        &template_parameters,
+        &format_data_interval(data_interval_end, task.data_interval_format(config)),
+    )?;
+    check_rendered_query(&sql)?;
+    if config.safe_mode.enabled {
+        SqlValidator::new(config)
+            .with_min_retention_days(task.min_retention_days)
+            .with_retention_column(task.retention_column.as_deref())
+            .validate_sql_query(&sql)?;
+    }
+    Ok(sql)
+}
+
+/// The `schema.table` a task cleans up, from its `schema_name` and `table_name` parameters.
+pub(crate) fn task_target(config: &Config, task: &CleanupTask) -> String {
+    schema_table(&CleanupMetadata::new(config, task))
+}
+
+/// Builds the template context from the task parameters plus the built-in `batch_size` and
+/// `retention_days` variables.
+///
+/// With safe mode enabled `retention_days` always comes from the task's `min_retention_days` or
+/// `safe_mode.retention_days`, so the rendered interval and the validator cannot diverge.
+/// Otherwise a user parameter of the same name takes precedence.
+fn build_template_parameters(config: &Config, task: &CleanupTask) -> HashMap<String, String> {
+    let mut template_parameters = task.parameters.clone();
+    template_parameters.insert("batch_size".to_string(), task.batch_size.to_string());
+
+    let retention_days = task.retention_days(config).to_string();
+    match template_parameters.get("retention_days") {
+        Some(user_value) if config.safe_mode.enabled && *user_value != retention_days => {
+            warn!(
+                "Task {} parameter retention_days={} is overridden by the minimum retention of {} \
+                 days",
+                task.name, user_value, retention_days
+            );
+            template_parameters.insert("retention_days".to_string(), retention_days);
+        }
+        Some(_) => {}
+        None => {
+            template_parameters.insert("retention_days".to_string(), retention_days);
+        }
+    }
+    template_parameters
+}
+
+/// Logs a batch that exceeded `slow_query_threshold_seconds`, optionally with its query plan.
+async fn report_slow_query<E: QueryExecutor>(
+    executor: &E,
+    config: &Config,
+    task: &CleanupTask,
+    sql: &str,
+    elapsed_in_secs: f64,
+) {
+    let Some(threshold) = config.slow_query_threshold_seconds else {
+        return;
+    };
+    if elapsed_in_secs <= threshold {
+        return;
+    }
+
+    warn!(
+        "Slow query for task {}: took {} (threshold {}):\n{}",
+        task.name,
+        humanize_time(elapsed_in_secs),
+        humanize_time(threshold),
+        sql
+    );
+    let tags = task.tags.join(",");
+    metrics().increment(
+        "kiyoshi_slow_queries_total",
+        &[
+            ("task", &task.name),
+            ("tags", &tags),
+            ("owner", task.owner.as_deref().unwrap_or_default()),
+        ],
+        1.0,
+    );
+
+    if config.explain_slow_queries {
+        match executor.explain(sql).await {
+            Ok(plan) => warn!("Query plan for slow query of task {}:\n{}", task.name, plan),
+            Err(e) => warn!("Failed to EXPLAIN slow query for task {}: {}", task.name, e),
+        }
+    }
+}
+
+/// Counts the rows a not-yet-acknowledged task would delete and reports the estimate instead of
+/// deleting anything.
+async fn run_unacknowledged_task(
+    db: &Database,
+    config: &Config,
+    task: &CleanupTask,
+    sql: &str,
+    slack_client: &Option<SlackClient>,
+) -> Result<(), anyhow::Error> {
+    let count_sql = dry_run::count_query(sql, config.database_config.dialect)?;
+    info!(
+        "Task {} requires acknowledgement, running dry-run count instead of deleting: \n{}",
+        task.name, count_sql
+    );
+    let estimated_rows: i64 = db.fetch_scalar(&count_sql).await?;
+    info!(
+        "Dry-run for task {} would delete an estimated {} rows. Add it to config.acked_tasks to enable deletion",
+        task.name,
+        format_count(estimated_rows.max(0) as u64)
+    );
+    let sample = if task.sample_rows > 0 {
+        // A failed sample is only logged, the count is what gates acknowledgement
+        match sample_rows(db, config, task, sql).await {
+            Ok(sample) => {
+                info!(
+                    "Sample of the rows task {} would delete:\n{}",
+                    task.name, sample
+                );
+                Some(sample)
+            }
+            Err(e) => {
+                warn!("Failed to sample rows for task {}: {:#}", task.name, e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    if let Some(slack_client) = slack_client {
+        let report = create_ack_required_report(
+            &CleanupMetadata::new(config, task),
+            estimated_rows,
+            sample.as_deref(),
+        );
+        send_slack_report(
+            slack_client,
+            config,
+            SlackEvent::Success,
+            &report,
+            "acknowledgement report",
+        )
+        .await;
+    }
+    Ok(())
+}
+
+/// Selects up to `sample_rows` rows the DELETE would remove, formatted as a table with the task's
+/// `sensitive_columns` masked.
+async fn sample_rows(
+    db: &Database,
+    config: &Config,
+    task: &CleanupTask,
+    sql: &str,
+) -> Result<String> {
+    let sample_sql = dry_run::sample_query(sql, config.database_config.dialect, task.sample_rows)?;
+    let (columns, rows) = db.fetch_text_rows(&sample_sql).await?;
+    if rows.is_empty() {
+        return Ok("(no rows)".to_string());
+    }
+    Ok(dry_run::format_sample(
+        &columns,
+        &rows,
+        &task.sensitive_columns,
+    ))
+}
+
+/// Drops the task's partitions that are older than its retention and reports them to Slack.
+async fn run_partition_drop(
+    db: &Database,
+    config: &Config,
+    task: &CleanupTask,
+    metadata: &JobScheduleMetadata,
+    slack_client: &Option<SlackClient>,
+) -> Result<()> {
+    let start = std::time::Instant::now();
+    let result =
+        drop_old_partitions(db, config, task, metadata.data_interval_end.naive_utc()).await;
+    let cleanup_metadata = CleanupMetadata {
+        elapsed_time: start.elapsed().as_secs_f64(),
+        ..CleanupMetadata::new(config, task)
+    };
+    match &result {
+        Ok(dropped) => {
+            info!(
+                "Dropped {} partitions for task {}: {:?}",
+                dropped.len(),
+                task.name,
+                dropped
+            );
+            if let Some(slack_client) = slack_client {
+                send_slack_report(
+                    slack_client,
+                    config,
+                    SlackEvent::Success,
+                    &create_partition_drop_report(&cleanup_metadata, dropped),
+                    "partition drop report",
+                )
+                .await;
+            }
+        }
+        Err(e) => {
+            if let Some(slack_client) = slack_client {
+                send_slack_report(
+                    slack_client,
+                    config,
+                    SlackEvent::Error,
+                    &create_error_report(
+                        &cleanup_metadata,
+                        &format!(
+                            "Failed to drop partitions for task: {}, error: {:#}",
+                            task.name, e
+                        ),
+                    ),
+                    "error report",
+                )
+                .await;
+            }
+        }
+    }
+    result
+        .map(|_| ())
+        .with_context(|| format!("Failed to drop partitions for task: {}", task.name))
+}
+
+/// Sends `report`, named `label` in the logs, to the channel of `event`. A report that cannot be
+/// delivered is logged at error level for error and timeout reports, and as a warning otherwise.
+async fn send_slack_report(
+    slack_client: &SlackClient,
+    config: &Config,
+    event: SlackEvent,
+    report: &CreateMessage,
+    label: &str,
+) {
+    let send_result = send_report(
+        slack_client,
+        &config.slack_config,
+        config.slack_config.channel_for(event),
+        report,
+    )
+    .await;
+    match send_result {
+        Err(e) if event == SlackEvent::Success => {
+            warn!("Failed to send {} to Slack: {:#}", label, e)
+        }
+        Err(e) => error!("Failed to send {} to Slack: {:#}", label, e),
+        Ok(()) => info!("Sent {} to Slack", label),
+    }
+}
+
+/// Sends a Slack report listing tasks disabled by lenient config validation.
+pub async fn notify_disabled_tasks(config: &Config, disabled_tasks: &[DisabledTask]) {
+    if !config.slack_config.enabled || disabled_tasks.is_empty() {
+        return;
+    }
+    let slack_client = SlackClient::new(config.slack_config.bot_token.clone());
+    let report = create_disabled_tasks_report(config, disabled_tasks);
+    send_slack_report(
+        &slack_client,
+        config,
+        SlackEvent::Error,
+        &report,
+        "disabled tasks report",
+    )
+    .await;
+}
+
+/// Logs the session summary on shutdown and sends it to Slack, to the error channel when any task
+/// is still failing.
+pub async fn notify_shutdown(config: &Config, summary: &SessionSummary) {
+    info!(
+        "Session summary: ran for {}, {} task run(s) ({} failed), {} rows deleted",
+        humanize_time(summary.uptime_seconds),
+        summary.runs,
+        summary.failed_runs,
+        format_count(summary.rows_deleted)
+    );
+    if summary.failing_tasks.is_empty() {
+        info!("Exit summary: no tasks failing");
+    } else {
+        warn!(
+            "Exit summary: {} task(s) failing since their last success: {}",
+            summary.failing_tasks.len(),
+            summary.failing_tasks.join(", ")
+        );
+    }
+    if !config.slack_config.enabled || !config.slack_config.notify_on_shutdown {
+        return;
+    }
+    let slack_client = SlackClient::new(config.slack_config.bot_token.clone());
+    let event = if summary.failing_tasks.is_empty() {
+        SlackEvent::Success
+    } else {
+        SlackEvent::Error
+    };
+    send_slack_report(
+        &slack_client,
+        config,
+        event,
+        &create_shutdown_report(config, summary),
+        "shutdown report",
+    )
+    .await;
+}
+
+/// Notifies Slack when a task's circuit breaker opens (error channel) or recovers (success
+/// channel).
+pub async fn notify_circuit_breaker(
+    config: &Config,
+    task: &CleanupTask,
+    transition: BreakerTransition,
+) {
+    match transition {
+        BreakerTransition::Opened { cooldown } => warn!(
+            "Circuit breaker opened for task: {}, skipping runs for {}",
+            task.name,
+            humanize_time(cooldown.num_milliseconds() as f64 / 1000.0)
+        ),
+        BreakerTransition::Recovered => {
+            info!("Circuit breaker closed for task: {}", task.name)
+        }
+    }
+    if !config.slack_config.enabled {
+        return;
+    }
+    let slack_client = SlackClient::new(config.slack_config.bot_token.clone());
+    let event = match transition {
+        BreakerTransition::Opened { .. } => SlackEvent::Error,
+        BreakerTransition::Recovered => SlackEvent::Success,
+    };
+    let report = create_circuit_breaker_report(&CleanupMetadata::new(config, task), transition);
+    send_slack_report(
+        &slack_client,
+        config,
+        event,
+        &report,
+        "circuit breaker report",
+    )
+    .await;
+}
+
+/// Logs and reports, once, that a task was disabled after repeated failed runs.
+pub async fn notify_dead_letter(config: &Config, task: &CleanupTask, dead_letter: &DeadLetter) {
+    error!(
+        "Task {} disabled after {} consecutive failed runs, re-enable it through the admin \
+         server: {}",
+        task.name, dead_letter.consecutive_failures, dead_letter.last_error
+    );
+    if !config.slack_config.enabled {
+        return;
+    }
+    let slack_client = SlackClient::new(config.slack_config.bot_token.clone());
+    let report = create_dead_letter_report(&CleanupMetadata::new(config, task), dead_letter);
+    send_slack_report(
+        &slack_client,
+        config,
+        SlackEvent::Error,
+        &report,
+        "dead letter report",
+    )
+    .await;
+}
+
+/// Logs and reports a panic caught while running a task. The task stays scheduled, so it runs
+/// again at its next fire time.
+pub async fn notify_task_panic(config: &Config, task: &CleanupTask, message: &str) {
+    error!(
+        "Task {} panicked, it stays scheduled for its next run: {}",
+        task.name, message
+    );
+    if !config.slack_config.enabled {
+        return;
+    }
+    let slack_client = SlackClient::new(config.slack_config.bot_token.clone());
+    let report = create_error_report(
+        &CleanupMetadata::new(config, task),
+        &format!(
+            "Task {} panicked: {}. Other tasks are not affected and this task runs again at its next scheduled time",
+            task.name, message
+        ),
+    );
+    send_slack_report(
+        &slack_client,
+        config,
+        SlackEvent::Error,
+        &report,
+        "panic report",
+    )
+    .await;
+}
+
+struct CleanupMetadata<'a> {
+    config: &'a Config,
+    task: &'a CleanupTask,
+    total_rows: u64,
+    elapsed_time: f64,
+    schema_name: Option<&'a String>,
+    table_name: Option<&'a String>,
+    capped: bool,
+    /// The table was truncated instead of running the DELETE loop
+    truncated: bool,
+    /// Duration of the `OPTIMIZE TABLE` run after the cleanup, or why it failed
+    optimize_result: Option<String>,
+    /// Rendered query and template parameters, shown in the success report
+    sql: Option<&'a str>,
+    parameters: Option<&'a HashMap<String, String>>,
+    /// Kiyoshi's and the database's clocks at the start of the run, with `report_db_time`
+    db_clock: Option<DbClock>,
+    /// Execution time of the batches on the server, with `measure_server_time`
+    server_time: Option<f64>,
+    /// Duration of the `warmup_query`, not included in `elapsed_time`
+    warmup_time: Option<f64>,
+    /// Rows the rolled back first batch affected, with `trial_rollback_first`
+    trial_rows: Option<u64>,
+    /// Rows deleted by each `child_cleanup` step that ran, also counted in `total_rows`
+    child_steps: &'a [ChildStepRun],
+}
+
+impl<'a> CleanupMetadata<'a> {
+    fn new(config: &'a Config, task: &'a CleanupTask) -> Self {
+        Self {
+            config,
+            task,
+            total_rows: 0,
+            elapsed_time: 0.0,
+            schema_name: task
+                .parameters
+                .get("schema_name")
+                .or(Some(&config.database_config.database)),
+            table_name: task.parameters.get("table_name"),
+            capped: false,
+            truncated: false,
+            optimize_result: None,
+            sql: None,
+            parameters: None,
+            db_clock: None,
+            server_time: None,
+            warmup_time: None,
+            trial_rows: None,
+            child_steps: &[],
+        }
+    }
+}
+
+/// Kiyoshi's UTC clock and the database's `NOW()`, read together once per run.
+#[derive(Debug, Clone, Copy)]
+struct DbClock {
+    app_time: DateTime<Utc>,
+    db_time: NaiveDateTime,
+}
+
+impl DbClock {
+    /// How far the database's clock is ahead of Kiyoshi's, negative when it is behind.
+    fn skew_seconds(&self) -> f64 {
+        (self.db_time - self.app_time.naive_utc()).num_milliseconds() as f64 / 1000.0
+    }
+}
+
+/// Reads the database's `NOW()`, warning when it differs from Kiyoshi's clock by more than
+/// `max_clock_skew_seconds`. A failed read is logged and only leaves the clocks out of the report.
+async fn read_db_clock(db: &Database, config: &Config, task: &CleanupTask) -> Option<DbClock> {
+    let app_time = Utc::now();
+    let db_time = match db.fetch_scalar::<NaiveDateTime>("SELECT NOW()").await {
+        Ok(db_time) => db_time,
+        Err(e) => {
+            warn!(
+                "Failed to read database time for task {}: {:#}",
+                task.name, e
+            );
+            return None;
+        }
+    };
+    let clock = DbClock { app_time, db_time };
+    if clock.skew_seconds().abs() > config.max_clock_skew_seconds {
+        warn!(
+            "Database NOW() {} differs from Kiyoshi's UTC time {} by {:.0}s for task {}, check the \
+             database time zone and NTP as DATE_SUB retention follows the database clock",
+            clock.db_time,
+            clock.app_time.format("%Y-%m-%d %H:%M:%S"),
+            clock.skew_seconds(),
+            task.name
+        );
+    }
+    Some(clock)
+}
+
+/// The id of the current run for the context block of reports, empty outside a run.
+fn run_id_details() -> String {
+    current_run_id()
+        .map(|run_id| format!(" | 🔗 Run `{}`", run_id))
+        .unwrap_or_default()
+}
+
+/// Both clocks for the completion report, flagged when they differ by more than
+/// `max_clock_skew_seconds`.
+fn db_clock_details(clock: &DbClock, max_clock_skew_seconds: f64) -> String {
+    let mut details = format!(
+        "*Kiyoshi Time (UTC):*\n{}\n*Database NOW():*\n{}",
+        clock.app_time.format("%Y-%m-%d %H:%M:%S"),
+        clock.db_time.format("%Y-%m-%d %H:%M:%S")
+    );
+    let skew_seconds = clock.skew_seconds();
+    if skew_seconds.abs() > max_clock_skew_seconds {
+        details.push_str(&format!(
+            "\n⚠️ Clocks differ by {}",
+            humanize_time(skew_seconds.abs())
+        ));
+    }
+    details
+}
+
+/// The Host/Task/Target lines shared by the task reports, plus the task's tags if it has any.
+fn task_details(metadata: &CleanupMetadata, schema_table: &str) -> String {
+    let mut details = format!(
+        "*Host:* `{}`\n*Task:* `{}`\n*Target:* `{}`",
+        metadata.config.database_config.host, metadata.task.name, schema_table
+    );
+    if let Some(owner) = &metadata.task.owner {
+        details.push_str(&format!("\n*Owner:* `{}`", owner));
+    }
+    if !metadata.task.tags.is_empty() {
+        let tags = metadata
+            .task
+            .tags
+            .iter()
+            .map(|tag| format!("`{}`", tag))
+            .collect::<Vec<_>>()
+            .join(", ");
+        details.push_str(&format!("\n*Tags:* {}", tags));
+    }
+    details
+}
+
+/// The rows deleted by each `child_cleanup` step, in the order they ran.
+fn child_cleanup_details(child_steps: &[ChildStepRun]) -> Option<String> {
+    if child_steps.is_empty() {
+        return None;
+    }
+    let steps = child_steps
+        .iter()
+        .map(|step| format!("`{}`: {} rows", step.name, format_count(step.total_rows)))
+        .collect::<Vec<_>>()
+        .join("\n");
+    Some(format!("*Child Cleanup:*\n{}", steps))
+}
+
+const REPORT_SQL_MAX_CHARS: usize = 1000;
+
+/// The rendered query and its parameters, with secrets redacted and long queries truncated.
+fn query_details(metadata: &CleanupMetadata) -> Option<String> {
+    let config = metadata.config;
+    let mut sql = config.redact_secrets(metadata.sql?.trim());
+    if sql.chars().count() > REPORT_SQL_MAX_CHARS {
+        sql = sql.chars().take(REPORT_SQL_MAX_CHARS).collect::<String>() + "…";
+    }
+    let mut details = format!("*Query:*\n```{}```", sql);
+    if let Some(parameters) = metadata.parameters {
+        let mut parameters = parameters
+            .iter()
+            .map(|(key, value)| format!("`{}={}`", key, config.redact_secrets(value)))
+            .collect::<Vec<_>>();
+        parameters.sort();
+        details.push_str(&format!("\n*Parameters:* {}", parameters.join(", ")));
+    }
+    Some(details)
+}
+
+fn schema_table(metadata: &CleanupMetadata) -> String {
+    match (metadata.schema_name, metadata.table_name) {
+        (Some(schema), Some(table)) => format!("{}.{}", schema, table),
+        (None, Some(table)) => table.clone(),
+        (Some(schema), None) => schema.clone(),
+        (None, None) => "Unknown Target".to_string(),
+    }
+}
+
+/// Renders a user-supplied report template, returning `None` (and falling back to the built-in
+/// blocks) when no template is configured or rendering fails.
+fn render_report_template(
+    template: Option<&String>,
+    metadata: &CleanupMetadata,
+    error: &str,
+) -> Option<CreateMessage> {
+    let template = template?;
+    let context = HashMap::from([
+        (
+            "host".to_string(),
+            metadata.config.database_config.host.clone(),
+        ),
+        ("task".to_string(), metadata.task.name.clone()),
+        ("tags".to_string(), metadata.task.tags.join(", ")),
+        (
+            "owner".to_string(),
+            metadata.task.owner.clone().unwrap_or_default(),
+        ),
+        ("target".to_string(), schema_table(metadata)),
+        ("rows".to_string(), metadata.total_rows.to_string()),
+        (
+            "elapsed".to_string(),
+            humanize_duration(
+                metadata.elapsed_time,
+                metadata.config.slack_config.duration_style,
+            ),
+        ),
+        ("error".to_string(), error.to_string()),
+        ("run_id".to_string(), current_run_id().unwrap_or_default()),
+    ]);
+    match TemplateEngine::new().render_json_array(template, &context) {
+        Ok(blocks) => Some(CreateMessage::Blocks(blocks)),
+        Err(e) => {
+            warn!(
+                "Failed to render Slack report template for task {}, using default report: {}",
+                metadata.task.name, e
+            );
+            None
+        }
+    }
+}
+
+fn create_cleanup_report(metadata: CleanupMetadata) -> CreateMessage {
+    let templates = &metadata.config.slack_config.templates;
+    if let Some(message) = render_report_template(templates.success.as_ref(), &metadata, "") {
+        return message;
+    }
+    let schema_table = schema_table(&metadata);
+    let title = if metadata.capped {
+        format!(
+            "⏸️ *Cleanup Task Capped* (reached max_rows_per_run of {}, more rows remain for the next run)",
+            metadata.task.max_rows_per_run.unwrap_or_default()
+        )
+    } else if metadata.truncated {
+        "✂️ *Cleanup Task Completed by TRUNCATE* (no row was newer than the retention)".to_string()
+    } else {
+        "🧹 *Cleanup Task Completed*".to_string()
+    };
+
+    let mut blocks = vec![
+        serde_json::json!({
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": title
+            }
+        }),
+        serde_json::json!({
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": task_details(&metadata, &schema_table)
+            }
+        }),
+        serde_json::json!({
+            "type": "section",
+            "fields": [
+                {
+                    "type": "mrkdwn",
+                    "text": format!("*Total Rows Cleaned:*\n{}", format_count(metadata.total_rows))
+                },
+                {
+                    "type": "mrkdwn",
+                    "text": format!("*Total Time Elapsed:*\n{}", humanize_duration(metadata.elapsed_time, metadata.config.slack_config.duration_style))
+                },
+                {
+                    "type": "mrkdwn",
+                    "text": format!("*Avg Rate:*\n{}", humanize_rate(metadata.total_rows, metadata.elapsed_time))
+                }
+            ]
+        }),
+    ];
+    if let Some(server_time) = metadata.server_time {
+        // Wall time minus server time is spent on the network and waiting for connections
+        blocks.push(serde_json::json!({
+            "type": "section",
+            "fields": [
+                {
+                    "type": "mrkdwn",
+                    "text": format!("*Server Time:*\n{}", humanize_duration(server_time, metadata.config.slack_config.duration_style))
+                },
+                {
+                    "type": "mrkdwn",
+                    "text": format!(
+                        "*Network and Wait Time:*\n{}",
+                        humanize_duration(
+                            (metadata.elapsed_time - server_time).max(0.0),
+                            metadata.config.slack_config.duration_style
+                        )
+                    )
+                }
+            ]
+        }));
+    }
+    if let Some(warmup_time) = metadata.warmup_time {
+        blocks.push(serde_json::json!({
+            "type": "section",
+            "fields": [
+                {
+                    "type": "mrkdwn",
+                    "text": format!(
+                        "*Warmup Query:*\n{}",
+                        humanize_duration(warmup_time, metadata.config.slack_config.duration_style)
+                    )
+                }
+            ]
+        }));
+    }
+    if let Some(trial_rows) = metadata.trial_rows {
+        blocks.push(serde_json::json!({
+            "type": "section",
+            "fields": [
+                {
+                    "type": "mrkdwn",
+                    "text": format!("*Trial First Batch (rolled back):*\n{}", format_count(trial_rows))
+                }
+            ]
+        }));
+    }
+    if let Some(child_details) = child_cleanup_details(metadata.child_steps) {
+        blocks.push(serde_json::json!({
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": child_details
+            }
+        }));
+    }
+    if let Some(optimize_result) = &metadata.optimize_result {
+        blocks.push(serde_json::json!({
+            "type": "section",
+            "fields": [
+                {
+                    "type": "mrkdwn",
+                    "text": format!("*OPTIMIZE TABLE:*\n{}", optimize_result)
+                }
+            ]
+        }));
+    }
+    if let Some(db_clock) = &metadata.db_clock {
+        blocks.push(serde_json::json!({
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": db_clock_details(db_clock, metadata.config.max_clock_skew_seconds)
+            }
+        }));
+    }
+    if let Some(query_details) = query_details(&metadata) {
+        blocks.push(serde_json::json!({
+            "type": "context",
+            "elements": [
+                {
+                    "type": "mrkdwn",
+                    "text": query_details
+                }
+            ]
+        }));
+    }
+    blocks.push(serde_json::json!({
+        "type": "context",
+        "elements": [
+            {
+                "type": "mrkdwn",
+                "text": format!("🕒 Completed: {}{} | 🫧 Kiyoshi Cleanup Service",
+                    chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC"),
+                    run_id_details()
+                )
+            }
+        ]
+    }));
+
+    CreateMessage::Blocks(serde_json::json!(blocks))
+}
+
+const REPORT_SAMPLE_MAX_CHARS: usize = 2500;
+
+fn create_ack_required_report(
+    metadata: &CleanupMetadata,
+    estimated_rows: i64,
+    sample: Option<&str>,
+) -> CreateMessage {
+    let schema_table = schema_table(metadata);
+
+    let mut blocks = vec![
+        serde_json::json!({
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": "🔎 *Cleanup Task Awaiting Acknowledgement*"
+            }
+        }),
+        serde_json::json!({
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": task_details(metadata, &schema_table)
+            }
+        }),
+        serde_json::json!({
+            "type": "section",
+            "fields": [
+                {
+                    "type": "mrkdwn",
+                    "text": format!(
+                        "*Estimated Rows To Clean:*\n{}",
+                        format_count(estimated_rows.max(0) as u64)
+                    )
+                }
+            ]
+        }),
+    ];
+    if let Some(sample) = sample {
+        let mut sample = sample.to_string();
+        if sample.chars().count() > REPORT_SAMPLE_MAX_CHARS {
+            sample = sample
+                .chars()
+                .take(REPORT_SAMPLE_MAX_CHARS)
+                .collect::<String>()
+                + "…";
+        }
+        blocks.push(serde_json::json!({
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": format!("*Sample Rows:*\n```{}```", sample)
+            }
+        }));
+    }
+    blocks.push(serde_json::json!({
+        "type": "section",
+        "text": {
+            "type": "mrkdwn",
+            "text": format!("Nothing was deleted. Add `{}` to `config.acked_tasks` to enable deletion.", metadata.task.name)
+        }
+    }));
+    blocks.push(serde_json::json!({
+        "type": "context",
+        "elements": [
+            {
+                "type": "mrkdwn",
+                "text": format!("🕒 Dry-run: {}{} | 🫧 Kiyoshi Cleanup Service",
+                    chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC"),
+                    run_id_details()
+                )
+            }
+        ]
+    }));
+
+    CreateMessage::Blocks(serde_json::json!(blocks))
+}
+
+fn create_trial_report(metadata: &CleanupMetadata, trial_rows: u64) -> CreateMessage {
+    let schema_table = schema_table(metadata);
+
+    let mut blocks = vec![
+        serde_json::json!({
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": "🧪 *Cleanup Task Trial Run*"
+            }
+        }),
+        serde_json::json!({
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": task_details(metadata, &schema_table)
+            }
+        }),
+        serde_json::json!({
+            "type": "section",
+            "fields": [
+                {
+                    "type": "mrkdwn",
+                    "text": format!("*Rows The First Batch Would Clean:*\n{}", format_count(trial_rows))
+                }
+            ]
+        }),
+    ];
+    if let Some(query_details) = query_details(metadata) {
+        blocks.push(serde_json::json!({
+            "type": "context",
+            "elements": [
+                {
+                    "type": "mrkdwn",
+                    "text": query_details
+                }
+            ]
+        }));
+    }
+    blocks.push(serde_json::json!({
+        "type": "section",
+        "text": {
+            "type": "mrkdwn",
+            "text": "Nothing was deleted, the DELETE was rolled back. Set `trial_then_commit` to go on with the cleanup after the trial."
+        }
+    }));
+    blocks.push(serde_json::json!({
+        "type": "context",
+        "elements": [
+            {
+                "type": "mrkdwn",
+                "text": format!("🕒 Trial: {}{} | 🫧 Kiyoshi Cleanup Service",
+                    chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC"),
+                    run_id_details()
+                )
+            }
+        ]
+    }));
+
+    CreateMessage::Blocks(serde_json::json!(blocks))
+}
+
+fn create_validation_warning_report(metadata: &CleanupMetadata, error: &str) -> CreateMessage {
+    let schema_table = schema_table(metadata);
+
+    CreateMessage::Blocks(serde_json::json!([
+        {
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": "⚠️ *SQL Validation Failed, Executing Anyway*"
+            }
+        },
+        {
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": task_details(metadata, &schema_table)
+            }
+        },
+        {
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": format!("*Validation Error:*\n```\n{}\n```", error)
+            }
+        },
+        {
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": "The query was executed because `safe_mode.mode` is `warn`. It will be rejected once the mode is switched to `enforce`."
+            }
+        },
+        {
+            "type": "context",
+            "elements": [
+                {
+                    "type": "mrkdwn",
+                    "text": format!("⚠️ Warned: {}{} | 🫧 Kiyoshi Cleanup Service",
+                        chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC"),
+                        run_id_details()
+                    )
+                }
+            ]
+        }
+    ]))
+}
+
+fn create_partition_drop_report(metadata: &CleanupMetadata, dropped: &[String]) -> CreateMessage {
+    let schema_table = schema_table(metadata);
+    let partitions = if dropped.is_empty() {
+        "None, no partition is entirely older than the retention".to_string()
+    } else {
+        dropped
+            .iter()
+            .map(|name| format!("`{}`", name))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    CreateMessage::Blocks(serde_json::json!([
+        {
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": "✅ *Partition Drop Completed*"
+            }
+        },
+        {
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": task_details(metadata, &schema_table)
+            }
+        },
+        {
+            "type": "section",
+            "fields": [
+                {
+                    "type": "mrkdwn",
+                    "text": format!("*Dropped Partitions:*\n{}", partitions)
+                },
+                {
+                    "type": "mrkdwn",
+                    "text": format!("*Duration:*\n{}", humanize_duration(metadata.elapsed_time, metadata.config.slack_config.duration_style))
+                }
+            ]
+        },
+        {
+            "type": "context",
+            "elements": [
+                {
+                    "type": "mrkdwn",
+                    "text": format!("🕒 Completed: {}{} | 🫧 Kiyoshi Cleanup Service",
+                        chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC"),
+                        run_id_details()
+                    )
+                }
+            ]
+        }
+    ]))
+}
+
+fn create_disabled_tasks_report(config: &Config, disabled_tasks: &[DisabledTask]) -> CreateMessage {
+    let details = disabled_tasks
+        .iter()
+        .map(|task| format!("• `{}`: {}", task.name, task.reason))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    CreateMessage::Blocks(serde_json::json!([
+        {
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": "⚠️ *Cleanup Tasks Disabled By Validation*"
+            }
+        },
+        {
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": format!("*Host:* `{}`\n*Disabled Tasks:*\n{}", config.database_config.host, details)
+            }
+        },
+        {
+            "type": "context",
+            "elements": [
+                {
+                    "type": "mrkdwn",
+                    "text": format!("🚨 Startup: {}{} | 🫧 Kiyoshi Cleanup Service",
+                        chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC"),
+                        run_id_details()
+                    )
+                }
+            ]
+        }
+    ]))
+}
+
+fn create_shutdown_report(config: &Config, summary: &SessionSummary) -> CreateMessage {
+    let failing_tasks = if summary.failing_tasks.is_empty() {
+        "none".to_string()
+    } else {
+        summary
+            .failing_tasks
+            .iter()
+            .map(|task| format!("`{}`", task))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    CreateMessage::Blocks(serde_json::json!([
+        {
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": "🛑 *Kiyoshi Shutting Down*"
+            }
+        },
+        {
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": format!("*Host:* `{}`\n*Failing Tasks:* {}", config.database_config.host, failing_tasks)
+            }
+        },
+        {
+            "type": "section",
+            "fields": [
+                {
+                    "type": "mrkdwn",
+                    "text": format!(
+                        "*Uptime:*\n{}",
+                        humanize_duration(summary.uptime_seconds, config.slack_config.duration_style)
+                    )
+                },
+                {
+                    "type": "mrkdwn",
+                    "text": format!("*Task Runs:*\n{} ({} failed)", summary.runs, summary.failed_runs)
+                },
+                {
+                    "type": "mrkdwn",
+                    "text": format!("*Total Rows Cleaned:*\n{}", format_count(summary.rows_deleted))
+                }
+            ]
+        },
+        {
+            "type": "context",
+            "elements": [
+                {
+                    "type": "mrkdwn",
+                    "text": format!("🕒 Stopped: {}{} | 🫧 Kiyoshi Cleanup Service",
+                        chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC"),
+                        run_id_details()
+                    )
+                }
+            ]
+        }
+    ]))
+}
+
+fn create_circuit_breaker_report(
+    metadata: &CleanupMetadata,
+    transition: BreakerTransition,
+) -> CreateMessage {
+    let schema_table = schema_table(metadata);
+    let title = match transition {
+        BreakerTransition::Opened { cooldown } => format!(
+            "🔌 *Cleanup Task Paused* (circuit breaker opened after repeated failures, next trial run in {})",
+            humanize_duration(
+                cooldown.num_milliseconds() as f64 / 1000.0,
+                metadata.config.slack_config.duration_style
+            )
+        ),
+        BreakerTransition::Recovered => {
+            "🔋 *Cleanup Task Recovered* (circuit breaker closed)".to_string()
+        }
+    };
+
+    CreateMessage::Blocks(serde_json::json!([
+        {
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": title
+            }
+        },
+        {
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": task_details(metadata, &schema_table)
+            }
+        },
+        {
+            "type": "context",
+            "elements": [
+                {
+                    "type": "mrkdwn",
+                    "text": format!("🕒 {}{} | 🫧 Kiyoshi Cleanup Service",
+                        chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC"),
+                        run_id_details()
+                    )
+                }
+            ]
+        }
+    ]))
+}
+
+fn create_dead_letter_report(
+    metadata: &CleanupMetadata,
+    dead_letter: &DeadLetter,
+) -> CreateMessage {
+    let schema_table = schema_table(metadata);
+    CreateMessage::Blocks(serde_json::json!([
+        {
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": format!(
+                    "🪦 *Cleanup Task Disabled* (failed {} runs in a row, no longer scheduled until re-enabled)",
+                    dead_letter.consecutive_failures
+                )
+            }
+        },
+        {
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": task_details(metadata, &schema_table)
+            }
+        },
+        {
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": format!("*Last Error:*\n```\n{}\n```", dead_letter.last_error)
+            }
+        },
+        {
+            "type": "context",
+            "elements": [
+                {
+                    "type": "mrkdwn",
+                    "text": format!("🕒 {}{} | 🫧 Kiyoshi Cleanup Service",
+                        chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC"),
+                        run_id_details()
+                    )
+                }
+            ]
+        }
+    ]))
+}
+
+fn create_timeout_report(metadata: &CleanupMetadata) -> CreateMessage {
+    let templates = &metadata.config.slack_config.templates;
+    let error = format!(
+        "Task timed out after {} seconds",
+        metadata.task.task_timeout_seconds
+    );
+    if let Some(message) = render_report_template(templates.timeout.as_ref(), metadata, &error) {
+        return message;
+    }
+    let schema_table = schema_table(metadata);
+
+    let mut blocks = vec![
+        serde_json::json!({
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": "⏰ *Cleanup Task Timed Out*"
+            }
+        }),
+        serde_json::json!({
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": task_details(metadata, &schema_table)
+            }
+        }),
+        serde_json::json!({
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": format!("Task timed out after {} seconds\n", metadata.task.task_timeout_seconds)
+            }
+        }),
+    ];
+
+    if metadata.elapsed_time > 0.0 {
+        blocks.push(serde_json::json!({
+            "type": "section",
+            "fields": [
+                {
+                    "type": "mrkdwn",
+                    "text": format!("*Rows Cleaned:*\n{}", format_count(metadata.total_rows))
+                },
+                {
+                    "type": "mrkdwn",
+                    "text": format!("*Time Elapsed:*\n{}", humanize_duration(metadata.elapsed_time, metadata.config.slack_config.duration_style))
+                }
+            ]
+        }));
     }
 
     blocks.extend(vec![
@@ -442,20 +3248,1437 @@ fn create_timeout_report(metadata: &CleanupMetadata) -> CreateMessage {
             "elements": [
                 {
                     "type": "mrkdwn",
-                    "text": format!("🚨 Timed Out: {} | 🫧 Kiyoshi Cleanup Service",
-                        chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC")
+                    "text": format!("🚨 Timed Out: {}{} | 🫧 Kiyoshi Cleanup Service",
+                        chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC"),
+                        run_id_details()
+                    )
+                }
+            ]
+        }),
+    ]);
+
+    CreateMessage::Blocks(serde_json::json!(blocks))
+}
+
+fn create_error_report(metadata: &CleanupMetadata, error: &str) -> CreateMessage {
+    let templates = &metadata.config.slack_config.templates;
+    if let Some(message) = render_report_template(templates.error.as_ref(), metadata, error) {
+        return message;
+    }
+    let schema_table = schema_table(metadata);
+    let mut blocks = vec![
+        serde_json::json!({
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": "❌ *Cleanup Task Failed*"
+            }
+        }),
+        serde_json::json!({
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": task_details(metadata, &schema_table)
+            }
+        }),
+    ];
+    // Child rows already deleted before the failure
+    if let Some(child_details) = child_cleanup_details(metadata.child_steps) {
+        blocks.push(serde_json::json!({
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": child_details
+            }
+        }));
+    }
+    blocks.extend([
+        serde_json::json!({
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": format!("*Error Details:*\n```\n{}\n```", error)
+            }
+        }),
+        serde_json::json!({
+            "type": "divider"
+        }),
+        serde_json::json!({
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": "⚠️ *Action Required:* Please check the logs and investigate the issue."
+            }
+        }),
+        serde_json::json!({
+            "type": "context",
+            "elements": [
+                {
+                    "type": "mrkdwn",
+                    "text": format!("🚨 Failed: {}{} | 🫧 Kiyoshi Cleanup Service",
+                        chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC"),
+                        run_id_details()
                     )
                 }
             ]
         }),
     ]);
-
     CreateMessage::Blocks(serde_json::json!(blocks))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cleaner::{
+        config::OptimizeAfterCleanup, db::PoolStatus, template::DEFAULT_DATA_INTERVAL_FORMAT,
+    };
+    use std::collections::VecDeque;
+
+    /// Returns queued affected-row counts (or errors) per DELETE query, then 0 once the queue is
+    /// empty. Other queries, such as batch hooks, always affect 0 rows.
+    struct MockExecutor {
+        results: Mutex<VecDeque<Result<u64, String>>>,
+        queries: Mutex<Vec<String>>,
+        /// Returned by count queries in order, then 0
+        remaining: Mutex<VecDeque<u64>>,
+    }
+
+    impl MockExecutor {
+        fn new(results: Vec<Result<u64, String>>) -> Self {
+            Self {
+                results: Mutex::new(results.into()),
+                queries: Mutex::new(Vec::new()),
+                remaining: Mutex::new(VecDeque::new()),
+            }
+        }
+
+        fn with_remaining(self, remaining: Vec<u64>) -> Self {
+            *self.remaining.lock().unwrap() = remaining.into();
+            self
+        }
+
+        fn query_count(&self) -> usize {
+            self.queries.lock().unwrap().len()
+        }
+    }
+
+    impl QueryExecutor for MockExecutor {
+        async fn execute_query(&self, query: &str) -> Result<(u64, f64)> {
+            self.queries.lock().unwrap().push(query.to_string());
+            if !query.starts_with("DELETE") {
+                return Ok((0, 0.01));
+            }
+            match self.results.lock().unwrap().pop_front() {
+                Some(Ok(rows)) => Ok((rows, 0.01)),
+                Some(Err(e)) => Err(anyhow::anyhow!(e)),
+                None => Ok((0, 0.01)),
+            }
+        }
+
+        async fn execute_query_with_server_time(
+            &self,
+            query: &str,
+        ) -> Result<(u64, f64, Option<f64>)> {
+            let (affected_rows, elapsed_in_secs) = self.execute_query(query).await?;
+            Ok((affected_rows, elapsed_in_secs, Some(0.004)))
+        }
+
+        async fn trial_execute_query(&self, query: &str) -> Result<(u64, f64)> {
+            self.execute_query(&format!("TRIAL {}", query)).await?;
+            match self.results.lock().unwrap().pop_front() {
+                Some(Ok(rows)) => Ok((rows, 0.01)),
+                Some(Err(e)) => Err(anyhow::anyhow!(e)),
+                None => Ok((0, 0.01)),
+            }
+        }
+
+        async fn fetch_count(&self, query: &str) -> Result<u64> {
+            self.queries.lock().unwrap().push(query.to_string());
+            Ok(self.remaining.lock().unwrap().pop_front().unwrap_or(0))
+        }
+
+        fn pool_status(&self) -> Option<PoolStatus> {
+            Some(PoolStatus { size: 5, idle: 2 })
+        }
+    }
+
+    fn test_task(extra_yaml: &str) -> CleanupTask {
+        let yaml = format!(
+            r#"
+name: test_task
+description: test task
+cron_schedule: "0 0 * * * *"
+enabled: true
+template_query: "DELETE FROM t WHERE created_at < DATE_SUB(NOW(), INTERVAL 30 DAY) LIMIT 1000"
+parameters: {{}}
+batch_size: 1000
+retry_attempts: 3
+retry_delay_seconds: 0
+{}"#,
+            extra_yaml
+        );
+        serde_yaml::from_str(&yaml).unwrap()
+    }
+
+    #[test]
+    fn test_render_task_templates() {
+        let template_engine = TemplateEngine::new();
+        let mut task = test_task(
+            "pre_batch_query: \"SELECT '{{ data_interval_end }}'\"\npost_batch_query: \"DELETE FROM {{ table_name | ident }}\"",
+        );
+        let mut parameters = HashMap::from([("table_name".to_string(), "events".to_string())]);
+
+        let (sql, hooks) =
+            render_task_templates(&template_engine, &task, &parameters, "2024-03-20 00:00:00")
+                .unwrap();
+        assert_eq!(sql, task.template_query);
+        assert_eq!(
+            hooks.pre_batch.as_deref(),
+            Some("SELECT '2024-03-20 00:00:00'")
+        );
+        assert_eq!(hooks.post_batch.as_deref(), Some("DELETE FROM `events`"));
+
+        // Errors only detectable at render time name the failing template
+        parameters.insert("table_name".to_string(), "events; DROP TABLE t".to_string());
+        let error =
+            render_task_templates(&template_engine, &task, &parameters, "2024-03-20 00:00:00")
+                .unwrap_err();
+        assert!(format!("{:#}", error).starts_with("failed to render post_batch_query: "));
+
+        task.template_query = "DELETE FROM {{ table_name | ident }}".to_string();
+        let error =
+            render_task_templates(&template_engine, &task, &parameters, "2024-03-20 00:00:00")
+                .unwrap_err();
+        assert!(format!("{:#}", error).starts_with("failed to render template_query: "));
+
+        // A query rendering to nothing fails before it is parsed
+        parameters.insert("table_name".to_string(), "events".to_string());
+        for template_query in ["{{ query }}", "  {% if false %}DELETE FROM t{% endif %}\n"] {
+            task.template_query = template_query.to_string();
+            let error =
+                render_task_templates(&template_engine, &task, &parameters, "2024-03-20 00:00:00")
+                    .unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                "rendered query is empty, check template_query and parameters",
+                "{}",
+                template_query
+            );
+            let error = render_task_query(&Config::default(), &task, Utc::now()).unwrap_err();
+            assert!(
+                !error.to_string().contains("parse"),
+                "{}: {:#}",
+                template_query,
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn test_build_template_parameters_retention_days() {
+        let mut config = Config::default();
+        let mut task = test_task("");
+        let parameters = build_template_parameters(&config, &task);
+        assert_eq!(parameters["retention_days"], "30");
+        assert_eq!(parameters["batch_size"], "1000");
+
+        task.parameters
+            .insert("retention_days".to_string(), "7".to_string());
+        let parameters = build_template_parameters(&config, &task);
+        assert_eq!(parameters["retention_days"], "30");
+
+        task.min_retention_days = Some(365);
+        let parameters = build_template_parameters(&config, &task);
+        assert_eq!(parameters["retention_days"], "365");
+
+        config.safe_mode.enabled = false;
+        let parameters = build_template_parameters(&config, &task);
+        assert_eq!(parameters["retention_days"], "7");
+    }
+
+    #[test]
+    fn test_batch_size_from_percent() {
+        let test_cases = vec![
+            (1.0, 1_000_000, 10_000),
+            (0.5, 1_000_000, 5_000),
+            (100.0, 42, 42),
+            (1.0, 150, 1),
+            (1.0, 0, 1),
+            (10.0, 100_000_000_000, u32::MAX),
+        ];
+        for (percent, table_rows, expected) in test_cases {
+            assert_eq!(
+                batch_size_from_percent(percent, table_rows),
+                expected,
+                "{}% of {}",
+                percent,
+                table_rows
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delete_loop_completes_on_zero_rows() {
+        let task = test_task("");
+        let executor = MockExecutor::new(vec![Ok(1000), Ok(500)]);
+        let tracker = Mutex::new(ProgressTracker::default());
+        let config = Config::default();
+
+        let summary = run_delete_loop(
+            &executor,
+            &config,
+            &task,
+            "DELETE",
+            &BatchHooks::default(),
+            &tracker,
+        )
+        .await;
+        assert!(matches!(summary.outcome, DeleteLoopOutcome::Completed));
+        assert_eq!(summary.total_rows, 1500);
+        assert_eq!(executor.query_count(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_delete_loop_continues_past_empty_batch() {
+        let task = test_task("stop_on_zero_rows: false\nmax_empty_batches: 2");
+        let executor = MockExecutor::new(vec![Ok(0), Ok(500)]);
+        let tracker = Mutex::new(ProgressTracker::default());
+        let config = Config::default();
+
+        let summary = run_delete_loop(
+            &executor,
+            &config,
+            &task,
+            "DELETE",
+            &BatchHooks::default(),
+            &tracker,
+        )
+        .await;
+        assert!(matches!(summary.outcome, DeleteLoopOutcome::Completed));
+        assert_eq!(summary.total_rows, 500);
+        // Empty, 500 rows, then two consecutive empty batches
+        assert_eq!(executor.query_count(), 4);
+
+        // By default the first empty batch ends the run
+        let task = test_task("");
+        let executor = MockExecutor::new(vec![Ok(0), Ok(500)]);
+        let summary = run_delete_loop(
+            &executor,
+            &config,
+            &task,
+            "DELETE",
+            &BatchHooks::default(),
+            &tracker,
+        )
+        .await;
+        assert_eq!(summary.total_rows, 0);
+        assert_eq!(executor.query_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_delete_loop_completion_check() {
+        let task = test_task("");
+        let hooks = BatchHooks {
+            completion_check: Some(CompletionCheck {
+                query: "SELECT COUNT(*) FROM t".to_string(),
+                batch_size: 1000,
+            }),
+            ..BatchHooks::default()
+        };
+        // A full batch is not checked. The partial and empty batches are, and the check keeps
+        // the loop going past the empty batch until it reports no rows remaining
+        let executor = MockExecutor::new(vec![Ok(1000), Ok(400), Ok(0), Ok(300)])
+            .with_remaining(vec![300, 300, 0]);
+        let tracker = Mutex::new(ProgressTracker::default());
+
+        let summary = run_delete_loop(
+            &executor,
+            &Config::default(),
+            &task,
+            "DELETE",
+            &hooks,
+            &tracker,
+        )
+        .await;
+        assert!(matches!(summary.outcome, DeleteLoopOutcome::Completed));
+        assert_eq!(summary.total_rows, 1700);
+        assert_eq!(
+            *executor.queries.lock().unwrap(),
+            vec![
+                "DELETE",
+                "DELETE",
+                "SELECT COUNT(*) FROM t",
+                "DELETE",
+                "SELECT COUNT(*) FROM t",
+                "DELETE",
+                "SELECT COUNT(*) FROM t",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lookup_delete_loops() {
+        let task = test_task(
+            r#"lookup:
+  database_config: {host: users-db, username: kiyoshi, password: secret, database: users}
+  query: "SELECT id FROM deleted_users""#,
+        );
+        let executor = MockExecutor::new(vec![Ok(2), Ok(0), Ok(1), Ok(0)]);
+        let tracker = Mutex::new(ProgressTracker::default());
+        let mut config = Config::default();
+        config.safe_mode.allow_lookup_in_list = true;
+        let lookup_lists = crate::cleaner::lookup::chunk_id_lists(
+            &["1".to_string(), "2".to_string(), "u-3".to_string()],
+            2,
+            1024,
+        );
+        let render = |list: &str| {
+            let parameters = HashMap::from([
+                ("lookup_ids".to_string(), list.to_string()),
+                ("batch_size".to_string(), "1000".to_string()),
+            ]);
+            let sql = TemplateEngine::new().render(
+                "DELETE FROM events WHERE user_id IN ({{ lookup_ids }}) LIMIT {{ batch_size }}",
+                &parameters,
+                "2024-03-20 00:00:00",
+            )?;
+            Ok((sql, BatchHooks::default()))
+        };
+
+        let summary = run_lookup_delete_loops(
+            &executor,
+            &config,
+            &task,
+            &lookup_lists,
+            render,
+            &tracker,
+            &None,
+        )
+        .await;
+        assert!(matches!(summary.outcome, DeleteLoopOutcome::Completed));
+        assert_eq!(summary.total_rows, 3);
+        assert_eq!(
+            *executor.queries.lock().unwrap(),
+            vec![
+                "DELETE FROM events WHERE user_id IN (1, 2) LIMIT 1000",
+                "DELETE FROM events WHERE user_id IN (1, 2) LIMIT 1000",
+                "DELETE FROM events WHERE user_id IN ('u-3') LIMIT 1000",
+                "DELETE FROM events WHERE user_id IN ('u-3') LIMIT 1000",
+            ]
+        );
+
+        // Later lists are validated like the first, here including the index check
+        config.safe_mode.require_indexed_predicate = true;
+        let executor = MockExecutor::new(vec![Ok(2), Ok(0)]);
+        let summary = run_lookup_delete_loops(
+            &executor,
+            &config,
+            &task,
+            &lookup_lists,
+            render,
+            &tracker,
+            &None,
+        )
+        .await;
+        assert_eq!(summary.total_rows, 2);
+        assert_eq!(executor.query_count(), 2);
+        match summary.outcome {
+            DeleteLoopOutcome::Failed(e) => assert_eq!(
+                format!("{:#}", e),
+                "lookup list 2 rejected: Index lookup is not supported by this executor"
+            ),
+            outcome => panic!("unexpected outcome {:?}", outcome),
+        }
+
+        // and run anyway with safe_mode.mode: warn
+        config.safe_mode.mode = SafeModeEnforcement::Warn;
+        let executor = MockExecutor::new(vec![Ok(2), Ok(0), Ok(1), Ok(0)]);
+        let summary = run_lookup_delete_loops(
+            &executor,
+            &config,
+            &task,
+            &lookup_lists,
+            render,
+            &tracker,
+            &None,
+        )
+        .await;
+        assert!(matches!(summary.outcome, DeleteLoopOutcome::Completed));
+        assert_eq!(summary.total_rows, 3);
+
+        // The lists share max_rows_per_run, the second only gets the row the first one left
+        let task = test_task(
+            r#"max_rows_per_run: 3
+lookup:
+  database_config: {host: users-db, username: kiyoshi, password: secret, database: users}
+  query: "SELECT id FROM deleted_users""#,
+        );
+        let executor = MockExecutor::new(vec![Ok(2), Ok(0), Ok(1)]);
+        let summary = run_lookup_delete_loops(
+            &executor,
+            &config,
+            &task,
+            &lookup_lists,
+            render,
+            &tracker,
+            &None,
+        )
+        .await;
+        assert!(matches!(summary.outcome, DeleteLoopOutcome::Capped));
+        assert_eq!(summary.total_rows, 3);
+        assert_eq!(
+            *executor.queries.lock().unwrap(),
+            vec![
+                "DELETE FROM events WHERE user_id IN (1, 2) LIMIT 3",
+                "DELETE FROM events WHERE user_id IN (1, 2) LIMIT 1",
+                "DELETE FROM events WHERE user_id IN ('u-3') LIMIT 1",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_table_delete_loops() {
+        let task = test_task("");
+        // The second table fails all 3 attempts, the others complete
+        let executor = MockExecutor::new(vec![
+            Ok(2),
+            Ok(0),
+            Err("lock wait timeout".to_string()),
+            Err("lock wait timeout".to_string()),
+            Err("lock wait timeout".to_string()),
+            Ok(1),
+            Ok(0),
+        ]);
+        let tracker = Mutex::new(ProgressTracker::default());
+        let tables = vec![
+            "events_1".to_string(),
+            "events_2".to_string(),
+            "events_3".to_string(),
+        ];
+        let render = |table: &str| {
+            let parameters = HashMap::from([("table_name".to_string(), table.to_string())]);
+            let sql = TemplateEngine::new().render(
+                "DELETE FROM {{ table_name | ident }} WHERE created_at < DATE_SUB('{{ data_interval_end }}', INTERVAL 30 DAY) LIMIT 1000",
+                &parameters,
+                "2024-03-20 00:00:00",
+            )?;
+            Ok((sql, BatchHooks::default()))
+        };
+
+        let table_runs = run_table_delete_loops(
+            &executor,
+            &Config::default(),
+            &task,
+            &tables,
+            render,
+            &tracker,
+            &None,
+        )
+        .await;
+        assert_eq!(table_runs.len(), 3);
+        let runs: Vec<_> = table_runs
+            .iter()
+            .map(|table_run| {
+                (
+                    table_run.table.as_str(),
+                    table_run.summary.total_rows,
+                    matches!(table_run.summary.outcome, DeleteLoopOutcome::Completed),
+                )
+            })
+            .collect();
+        assert_eq!(
+            runs,
+            vec![
+                ("events_1", 2, true),
+                ("events_2", 0, false),
+                ("events_3", 1, true)
+            ]
+        );
+        let queried_tables: Vec<_> = executor
+            .queries
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|query| query.split('`').nth(1).unwrap().to_string())
+            .collect();
+        assert_eq!(
+            queried_tables,
+            vec![
+                "events_1", "events_1", "events_2", "events_2", "events_2", "events_3", "events_3"
+            ]
+        );
+        // The progress of every table adds up
+        {
+            let tracker = tracker.lock().unwrap();
+            assert_eq!((tracker.total_rows, tracker.batches), (3, 7));
+        }
+
+        // The tables share max_rows_per_run, events_3 is left for a later run
+        let task = test_task("max_rows_per_run: 3");
+        let executor = MockExecutor::new(vec![Ok(2), Ok(0), Ok(1)]);
+        let table_runs = run_table_delete_loops(
+            &executor,
+            &Config::default(),
+            &task,
+            &tables,
+            render,
+            &tracker,
+            &None,
+        )
+        .await;
+        let runs: Vec<_> = table_runs
+            .iter()
+            .map(|table_run| {
+                (
+                    table_run.table.as_str(),
+                    table_run.summary.total_rows,
+                    format!("{:?}", table_run.summary.outcome),
+                )
+            })
+            .collect();
+        assert_eq!(
+            runs,
+            vec![
+                ("events_1", 2, "Completed".to_string()),
+                ("events_2", 1, "Capped".to_string()),
+                ("events_3", 0, "Capped".to_string())
+            ]
+        );
+        assert_eq!(executor.query_count(), 3);
+        assert!(executor.queries.lock().unwrap()[2].ends_with("LIMIT 1"));
+    }
+
+    #[tokio::test]
+    async fn test_table_delete_loops_validate_every_table() {
+        let task = test_task("");
+        let tables = vec![
+            "events_1".to_string(),
+            "events_2".to_string(),
+            "events_3".to_string(),
+        ];
+        // events_2 has an unsafe hook and events_3 no retention
+        let render = |table: &str| {
+            let retention = match table {
+                "events_3" => "",
+                _ => "WHERE created_at < DATE_SUB(NOW(), INTERVAL 30 DAY) ",
+            };
+            let hooks = BatchHooks {
+                post_batch: (table == "events_2")
+                    .then(|| "UPDATE `events_2_stats` SET n = 0".to_string()),
+                ..BatchHooks::default()
+            };
+            Ok((
+                format!("DELETE FROM `{}` {}LIMIT 1000", table, retention),
+                hooks,
+            ))
+        };
+        let outcomes = |table_runs: &[TableRun]| -> Vec<(u64, String)> {
+            table_runs
+                .iter()
+                .map(|table_run| {
+                    let outcome = match &table_run.summary.outcome {
+                        DeleteLoopOutcome::Failed(e) => format!("{:#}", e),
+                        outcome => format!("{:?}", outcome),
+                    };
+                    (table_run.summary.total_rows, outcome)
+                })
+                .collect()
+        };
+
+        let mut config = Config::default();
+        let executor = MockExecutor::new(vec![Ok(2), Ok(0)]);
+        let tracker = Mutex::new(ProgressTracker::default());
+        let table_runs =
+            run_table_delete_loops(&executor, &config, &task, &tables, render, &tracker, &None)
+                .await;
+        assert_eq!(
+            outcomes(&table_runs),
+            vec![
+                (2, "Completed".to_string()),
+                (
+                    0,
+                    "table rejected: batch hook rejected: Only DELETE statements are allowed \
+                     (set allow_unsafe_hooks to run hooks unvalidated)"
+                        .to_string()
+                ),
+                (
+                    0,
+                    "table rejected: DELETE statement must have a WHERE clause and FROM clause"
+                        .to_string()
+                ),
+            ]
+        );
+        assert_eq!(executor.query_count(), 2);
+
+        // With safe_mode.mode: warn every table runs anyway
+        config.safe_mode.mode = SafeModeEnforcement::Warn;
+        let executor = MockExecutor::new(vec![Ok(2), Ok(0), Ok(1), Ok(0), Ok(3), Ok(0)]);
+        let table_runs =
+            run_table_delete_loops(&executor, &config, &task, &tables, render, &tracker, &None)
+                .await;
+        assert_eq!(
+            outcomes(&table_runs),
+            vec![
+                (2, "Completed".to_string()),
+                (1, "Completed".to_string()),
+                (3, "Completed".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_truncate_if_fully_expired() {
+        let task = test_task("");
+        let queries = TruncateQueries {
+            spared_rows: "SELECT COUNT(*) FROM t WHERE NOT COALESCE((expired), FALSE)".to_string(),
+            total_rows: "SELECT COUNT(*) FROM t".to_string(),
+            truncate: "TRUNCATE TABLE t".to_string(),
+        };
+        // (spared, total and spared again counts, expected truncated rows, expected queries)
+        let test_cases = vec![
+            (vec![0, 5, 0], Some(5), 4),
+            // Rows are spared
+            (vec![3], None, 1),
+            // Nothing to truncate
+            (vec![0, 0], None, 2),
+            // A row newer than the retention appeared after the first count
+            (vec![0, 5, 1], None, 3),
+        ];
+        for (counts, expected, expected_queries) in test_cases {
+            let executor = MockExecutor::new(vec![]).with_remaining(counts.clone());
+            let progress_tracker = Mutex::new(ProgressTracker::default());
+            let summary =
+                truncate_if_fully_expired(&executor, &task, &queries, &progress_tracker).await;
+            assert_eq!(
+                summary.as_ref().map(|summary| summary.total_rows),
+                expected,
+                "{:?}",
+                counts
+            );
+            assert_eq!(executor.query_count(), expected_queries, "{:?}", counts);
+            let truncated = executor.queries.lock().unwrap().contains(&queries.truncate);
+            assert_eq!(truncated, expected.is_some(), "{:?}", counts);
+            let tracker = progress_tracker.lock().unwrap();
+            assert_eq!(tracker.total_rows, expected.unwrap_or(0), "{:?}", counts);
+        }
+
+        // A table with more rows than max_rows_per_run is left to the DELETE loop
+        for (max_rows_per_run, expected) in [(4, None), (5, Some(5))] {
+            let task = test_task(&format!("max_rows_per_run: {}", max_rows_per_run));
+            let executor = MockExecutor::new(vec![]).with_remaining(vec![0, 5, 0]);
+            let progress_tracker = Mutex::new(ProgressTracker::default());
+            let summary =
+                truncate_if_fully_expired(&executor, &task, &queries, &progress_tracker).await;
+            assert_eq!(summary.map(|summary| summary.total_rows), expected);
+            let truncated = executor.queries.lock().unwrap().contains(&queries.truncate);
+            assert_eq!(truncated, expected.is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_optimize_after_cleanup_threshold() {
+        let task = test_task("optimize_after_cleanup:\n  min_rows: 1000");
+        let config = Config::default();
+        let sql = "DELETE FROM app.events WHERE created_at < NOW() LIMIT 1000";
+        let now = Utc::now();
+
+        let executor = MockExecutor::new(vec![]);
+        assert_eq!(
+            optimize_after_cleanup(&executor, &config, &task, sql, 999, now).await,
+            None
+        );
+        assert_eq!(executor.query_count(), 0);
+
+        let result = optimize_after_cleanup(&executor, &config, &task, sql, 1000, now).await;
+        assert!(result.is_some_and(|result| !result.starts_with("failed")));
+        assert_eq!(
+            *executor.queries.lock().unwrap(),
+            vec!["OPTIMIZE TABLE `app`.`events`"]
+        );
+
+        let task = test_task("");
+        assert_eq!(
+            optimize_after_cleanup(&executor, &config, &task, sql, 1000, now).await,
+            None
+        );
+    }
+
+    #[test]
+    fn test_optimize_allowed_window() {
+        let at = |hour, minute| {
+            DateTime::parse_from_rfc3339(&format!("2024-03-20T{:02}:{:02}:00Z", hour, minute))
+                .unwrap()
+                .with_timezone(&Utc)
+        };
+        let mut optimize = OptimizeAfterCleanup {
+            min_rows: 0,
+            allowed_window: Some("01:00-05:00".to_string()),
+        };
+        assert!(optimize.should_run(1, at(1, 0)));
+        assert!(optimize.should_run(1, at(4, 59)));
+        assert!(!optimize.should_run(1, at(5, 0)));
+        assert!(!optimize.should_run(1, at(12, 0)));
+
+        // Windows can wrap past midnight
+        optimize.allowed_window = Some("22:00-02:00".to_string());
+        assert!(optimize.should_run(1, at(23, 0)));
+        assert!(optimize.should_run(1, at(1, 0)));
+        assert!(!optimize.should_run(1, at(3, 0)));
+    }
+
+    #[tokio::test]
+    async fn test_delete_loop_stops_at_max_rows_per_run() {
+        let task = test_task("max_rows_per_run: 2500");
+        let executor = MockExecutor::new(vec![Ok(1000), Ok(1000), Ok(500), Ok(1000)]);
+        let tracker = Mutex::new(ProgressTracker::default());
+        let config = Config::default();
+
+        let summary = run_delete_loop(
+            &executor,
+            &config,
+            &task,
+            &task.template_query,
+            &BatchHooks::default(),
+            &tracker,
+        )
+        .await;
+        assert!(matches!(summary.outcome, DeleteLoopOutcome::Capped));
+        assert_eq!(summary.total_rows, 2500);
+        assert_eq!(tracker.lock().unwrap().total_rows, 2500);
+        // The last batch was lowered to the 500 rows the cap had left
+        let queries = executor.queries.lock().unwrap();
+        assert_eq!(queries.len(), 3);
+        assert!(queries[0].ends_with("LIMIT 1000"));
+        assert!(queries[2].ends_with("LIMIT 500"));
+    }
+
+    #[tokio::test]
+    async fn test_delete_loop_counts_slow_queries() {
+        let mut task = test_task("owner: data-platform");
+        task.name = "slow_task".to_string();
+        let executor = MockExecutor::new(vec![Ok(1000), Ok(1000)]);
+        let tracker = Mutex::new(ProgressTracker::default());
+        let config = Config {
+            slow_query_threshold_seconds: Some(0.001),
+            ..Config::default()
+        };
+
+        run_delete_loop(
+            &executor,
+            &config,
+            &task,
+            "DELETE",
+            &BatchHooks::default(),
+            &tracker,
+        )
+        .await;
+        assert_eq!(
+            metrics().get(
+                "kiyoshi_slow_queries_total",
+                &[
+                    ("task", "slow_task"),
+                    ("tags", ""),
+                    ("owner", "data-platform")
+                ]
+            ),
+            Some(2.0)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delete_loop_measures_server_time() {
+        let task = test_task("");
+        let tracker = Mutex::new(ProgressTracker::default());
+
+        // (measure_server_time, expected server time). Like the elapsed time, it only counts
+        // the batches that deleted rows
+        for (measure_server_time, expected) in [(true, Some(0.008)), (false, None)] {
+            let executor = MockExecutor::new(vec![Ok(1000), Ok(500)]);
+            let config = Config {
+                measure_server_time,
+                ..Config::default()
+            };
+            let summary = run_delete_loop(
+                &executor,
+                &config,
+                &task,
+                "DELETE",
+                &BatchHooks::default(),
+                &tracker,
+            )
+            .await;
+            assert_eq!(summary.total_rows, 1500);
+            assert_eq!(
+                summary
+                    .server_time
+                    .map(|time| (time * 1000.0).round() / 1000.0),
+                expected,
+                "{}",
+                measure_server_time
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delete_loop_records_pool_status() {
+        let mut task = test_task("");
+        task.name = "pool_task".to_string();
+        let executor = MockExecutor::new(vec![Ok(1000)]);
+        let tracker = Mutex::new(ProgressTracker::default());
+
+        run_delete_loop(
+            &executor,
+            &Config::default(),
+            &task,
+            "DELETE",
+            &BatchHooks::default(),
+            &tracker,
+        )
+        .await;
+        for (state, expected) in [("in_use", 3.0), ("idle", 2.0)] {
+            assert_eq!(
+                metrics().get(
+                    "kiyoshi_db_pool_connections",
+                    &[("task", "pool_task"), ("state", state)]
+                ),
+                Some(expected),
+                "{}",
+                state
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delete_loop_fails_after_retry_attempts() {
+        let task = test_task("");
+        let executor = MockExecutor::new(vec![
+            Ok(1000),
+            Err("boom".to_string()),
+            Err("boom".to_string()),
+            Err("boom".to_string()),
+        ]);
+        let tracker = Mutex::new(ProgressTracker::default());
+        let config = Config::default();
+
+        let summary = run_delete_loop(
+            &executor,
+            &config,
+            &task,
+            "DELETE",
+            &BatchHooks::default(),
+            &tracker,
+        )
+        .await;
+        assert!(matches!(summary.outcome, DeleteLoopOutcome::Failed(_)));
+        assert_eq!(summary.total_rows, 1000);
+        assert_eq!(executor.query_count(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_delete_loop_retry_budget() {
+        let flaky = || {
+            vec![
+                Err("boom".to_string()),
+                Err("boom".to_string()),
+                Ok(1000),
+                Err("boom".to_string()),
+                Err("boom".to_string()),
+                Ok(1000),
+                Err("boom".to_string()),
+                Ok(1000),
+            ]
+        };
+        // (extra yaml, expected failure, expected rows, expected queries)
+        let test_cases = vec![
+            // The per-batch count resets after each successful batch
+            ("", false, 3000, 9),
+            ("batch_retry_attempts: 2", true, 0, 2),
+            ("max_total_failures: 4", true, 1000, 5),
+            ("max_total_failures: 5", true, 2000, 7),
+            ("max_total_failures: 6", false, 3000, 9),
+        ];
+        for (extra_yaml, failed, total_rows, query_count) in test_cases {
+            let task = test_task(extra_yaml);
+            let executor = MockExecutor::new(flaky());
+            let tracker = Mutex::new(ProgressTracker::default());
+
+            let summary = run_delete_loop(
+                &executor,
+                &Config::default(),
+                &task,
+                "DELETE",
+                &BatchHooks::default(),
+                &tracker,
+            )
+            .await;
+            assert_eq!(
+                matches!(summary.outcome, DeleteLoopOutcome::Failed(_)),
+                failed,
+                "{}",
+                extra_yaml
+            );
+            assert_eq!(summary.total_rows, total_rows, "{}", extra_yaml);
+            assert_eq!(executor.query_count(), query_count, "{}", extra_yaml);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delete_loop_aborts_at_max_batches_per_run() {
+        let task = test_task("max_batches_per_run: 3");
+        let executor = MockExecutor::new(vec![Ok(1000); 10]);
+        let tracker = Mutex::new(ProgressTracker::default());
+        let config = Config::default();
+
+        let summary = run_delete_loop(
+            &executor,
+            &config,
+            &task,
+            "DELETE",
+            &BatchHooks::default(),
+            &tracker,
+        )
+        .await;
+        match summary.outcome {
+            DeleteLoopOutcome::Failed(e) => assert_eq!(
+                e.to_string(),
+                "Aborted after reaching max_batches_per_run of 3"
+            ),
+            outcome => panic!("unexpected outcome: {:?}", outcome),
+        }
+        assert_eq!(summary.total_rows, 3000);
+        assert_eq!(executor.query_count(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_delete_loop_runs_batch_hooks() {
+        let task = test_task("");
+        let executor = MockExecutor::new(vec![Ok(1000), Ok(1000)]);
+        let tracker = Mutex::new(ProgressTracker::default());
+        let config = Config::default();
+        let hooks = BatchHooks {
+            pre_batch: Some("ANALYZE TABLE events".to_string()),
+            post_batch: Some("INSERT INTO checkpoints VALUES (NOW())".to_string()),
+            completion_check: None,
+        };
+
+        let summary = run_delete_loop(&executor, &config, &task, "DELETE", &hooks, &tracker).await;
+        assert!(matches!(summary.outcome, DeleteLoopOutcome::Completed));
+        assert_eq!(summary.total_rows, 2000);
+        // Two batches plus the final empty one, each wrapped in both hooks
+        let queries = executor.queries.lock().unwrap();
+        let expected = [
+            "ANALYZE TABLE events",
+            "DELETE",
+            "INSERT INTO checkpoints VALUES (NOW())",
+        ];
+        assert_eq!(*queries, expected.repeat(3));
+    }
+
+    #[tokio::test]
+    async fn test_warmup_query_runs_once_per_run() {
+        let task = test_task(
+            "warmup_query: \"SELECT COUNT(*) FROM {{ table_name | ident }}\"\npre_batch_query: \"ANALYZE TABLE t\"",
+        );
+        let parameters = HashMap::from([("table_name".to_string(), "events".to_string())]);
+        let (sql, hooks) = render_task_templates(
+            &TemplateEngine::new(),
+            &task,
+            &parameters,
+            "2024-03-20 00:00:00",
+        )
+        .unwrap();
+        // The warmup is not a batch hook
+        assert_eq!(hooks.queries().count(), 1);
+        let executor = MockExecutor::new(vec![Ok(1000), Ok(1000)]);
+        let tracker = Mutex::new(ProgressTracker::default());
+
+        let warmup = "SELECT COUNT(*) FROM `events`";
+        run_warmup_query(&executor, &task, warmup).await.unwrap();
+        let summary =
+            run_delete_loop(&executor, &Config::default(), &task, &sql, &hooks, &tracker).await;
+        assert!(matches!(summary.outcome, DeleteLoopOutcome::Completed));
+        let queries = executor.queries.lock().unwrap();
+        assert_eq!(queries[0], warmup);
+        assert_eq!(queries.iter().filter(|query| *query == warmup).count(), 1);
+        // Three batches, each with its pre_batch_query
+        assert_eq!(
+            queries
+                .iter()
+                .filter(|query| *query == "ANALYZE TABLE t")
+                .count(),
+            3
+        );
+    }
+
+    #[tokio::test]
+    async fn test_trial_rollback_first() {
+        let task = test_task("trial_rollback_first: true");
+        let executor = MockExecutor::new(vec![Ok(700), Err("Lock wait timeout".to_string())]);
+
+        assert_eq!(run_trial(&executor, &task, "DELETE").await.unwrap(), 700);
+        let e = run_trial(&executor, &task, "DELETE").await.unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Trial run failed for task: test_task, error: Lock wait timeout"
+        );
+        assert_eq!(
+            *executor.queries.lock().unwrap(),
+            vec!["TRIAL DELETE", "TRIAL DELETE"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_child_cleanup_runs_before_parent() {
+        let task = test_task(
+            r#"child_cleanup:
+  - {name: comments, template_query: "DELETE FROM {{ table_name }}_comments LIMIT {{ batch_size }}"}
+  - {name: likes, template_query: "DELETE FROM {{ table_name }}_likes LIMIT {{ batch_size }}"}"#,
+        );
+        let config = Config::default();
+        let parameters = HashMap::from([
+            ("table_name".to_string(), "posts".to_string()),
+            ("batch_size".to_string(), "500".to_string()),
+        ]);
+        let child_queries = render_child_cleanup(
+            &config,
+            &TemplateEngine::new(),
+            &task,
+            &parameters,
+            "2024-03-20 00:00:00",
+        )
+        .unwrap();
+        assert_eq!(
+            child_queries,
+            vec![
+                (
+                    "comments".to_string(),
+                    "DELETE FROM posts_comments LIMIT 500".to_string()
+                ),
+                (
+                    "likes".to_string(),
+                    "DELETE FROM posts_likes LIMIT 500".to_string()
+                ),
+            ]
+        );
+
+        // comments: 2 then 0 rows, likes: 0 rows, parent: 3 then 0 rows
+        let executor = MockExecutor::new(vec![Ok(2), Ok(0), Ok(0), Ok(3), Ok(0)]);
+        let tracker = Mutex::new(ProgressTracker::default());
+        let (child_steps, summary) = run_with_child_cleanup(
+            &executor,
+            &config,
+            &task,
+            &child_queries,
+            "DELETE FROM posts LIMIT 500",
+            &BatchHooks::default(),
+            &tracker,
+        )
+        .await;
+        assert_eq!(
+            *executor.queries.lock().unwrap(),
+            vec![
+                "DELETE FROM posts_comments LIMIT 500",
+                "DELETE FROM posts_comments LIMIT 500",
+                "DELETE FROM posts_likes LIMIT 500",
+                "DELETE FROM posts LIMIT 500",
+                "DELETE FROM posts LIMIT 500",
+            ]
+        );
+        assert_eq!(
+            child_steps,
+            vec![
+                ChildStepRun {
+                    name: "comments".to_string(),
+                    total_rows: 2
+                },
+                ChildStepRun {
+                    name: "likes".to_string(),
+                    total_rows: 0
+                },
+            ]
+        );
+        // The run counts the rows of the steps and of the parent
+        assert_eq!(summary.total_rows, 5);
+        assert_eq!(summary.batches, 5);
+        assert_eq!(tracker.lock().unwrap().total_rows, 5);
+        assert!(matches!(summary.outcome, DeleteLoopOutcome::Completed));
+        assert_eq!(
+            child_cleanup_details(&child_steps).unwrap(),
+            "*Child Cleanup:*\n`comments`: 2 rows\n`likes`: 0 rows"
+        );
+
+        // A failed step stops the run before the parent DELETE
+        let executor = MockExecutor::new(vec![
+            Err("lock wait timeout".to_string()),
+            Err("lock wait timeout".to_string()),
+            Err("lock wait timeout".to_string()),
+        ]);
+        let (child_steps, summary) = run_with_child_cleanup(
+            &executor,
+            &config,
+            &task,
+            &child_queries,
+            "DELETE FROM posts LIMIT 500",
+            &BatchHooks::default(),
+            &tracker,
+        )
+        .await;
+        assert_eq!(child_steps.len(), 1);
+        assert_eq!(executor.query_count(), 3);
+        match summary.outcome {
+            DeleteLoopOutcome::Failed(e) => assert_eq!(
+                e.to_string(),
+                "child_cleanup step 'comments' failed: lock wait timeout"
+            ),
+            outcome => panic!("unexpected outcome {:?}", outcome),
+        }
+
+        // The steps and the parent share max_rows_per_run
+        let mut capped = task.clone();
+        capped.max_rows_per_run = Some(600);
+        let executor = MockExecutor::new(vec![Ok(500), Ok(100)]);
+        let (child_steps, summary) = run_with_child_cleanup(
+            &executor,
+            &config,
+            &capped,
+            &child_queries,
+            "DELETE FROM posts LIMIT 500",
+            &BatchHooks::default(),
+            &tracker,
+        )
+        .await;
+        assert_eq!(
+            *executor.queries.lock().unwrap(),
+            vec![
+                "DELETE FROM posts_comments LIMIT 500",
+                "DELETE FROM posts_comments LIMIT 100",
+            ]
+        );
+        assert_eq!(child_steps.len(), 1);
+        assert_eq!(summary.total_rows, 600);
+        assert!(matches!(summary.outcome, DeleteLoopOutcome::Capped));
+
+        // and max_batches_per_run, which the steps used up here
+        let mut limited = task.clone();
+        limited.max_batches_per_run = Some(3);
+        let executor = MockExecutor::new(vec![Ok(2), Ok(0), Ok(0), Ok(3)]);
+        let (child_steps, summary) = run_with_child_cleanup(
+            &executor,
+            &config,
+            &limited,
+            &child_queries,
+            "DELETE FROM posts LIMIT 500",
+            &BatchHooks::default(),
+            &tracker,
+        )
+        .await;
+        assert_eq!(executor.query_count(), 3);
+        assert_eq!(child_steps.len(), 2);
+        assert_eq!(summary.total_rows, 2);
+        match summary.outcome {
+            DeleteLoopOutcome::Failed(e) => assert_eq!(
+                e.to_string(),
+                "Aborted after reaching max_batches_per_run of 3"
+            ),
+            outcome => panic!("unexpected outcome {:?}", outcome),
+        }
+
+        // Unbounded child DELETEs are rejected
+        let mut unbounded = task.clone();
+        unbounded.child_cleanup[1].template_query = "DELETE FROM likes".to_string();
+        let e = render_child_cleanup(
+            &config,
+            &TemplateEngine::new(),
+            &unbounded,
+            &parameters,
+            "2024-03-20 00:00:00",
+        )
+        .unwrap_err();
+        assert_eq!(
+            format!("{:#}", e),
+            "child_cleanup 'likes' rejected: DELETE statement must have a LIMIT"
+        );
+    }
+
+    #[test]
+    fn test_parse_as_of() {
+        let now = DateTime::parse_from_rfc3339("2024-03-20T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        let as_of = parse_as_of("2024-01-01T08:00:00+08:00", false, now).unwrap();
+        assert_eq!(as_of.to_rfc3339(), "2024-01-01T00:00:00+00:00");
+        assert!(parse_as_of("2024-01-01 00:00:00", false, now).is_err());
+        assert!(parse_as_of("2024-04-01T00:00:00Z", false, now).is_err());
+        assert!(parse_as_of("2024-04-01T00:00:00Z", true, now).is_ok());
+    }
+
+    #[test]
+    fn test_backfill_intervals() {
+        let at = |value: &str| {
+            DateTime::parse_from_rfc3339(value)
+                .unwrap()
+                .with_timezone(&Utc)
+        };
+
+        // (cron, since, until, expected fire times)
+        let test_cases = vec![
+            (
+                "0 0 0 * * *",
+                "2024-01-01T00:00:00Z",
+                "2024-01-03T00:00:00Z",
+                vec!["2024-01-02T00:00:00+00:00", "2024-01-03T00:00:00+00:00"],
+            ),
+            (
+                "0 0 */6 * * *",
+                "2024-01-01T05:00:00Z",
+                "2024-01-01T13:00:00Z",
+                vec!["2024-01-01T06:00:00+00:00", "2024-01-01T12:00:00+00:00"],
+            ),
+        ];
+        for (cron, since, until, expected) in test_cases {
+            let intervals = backfill_intervals(cron, at(since), at(until)).unwrap();
+            let fire_times: Vec<_> = intervals
+                .iter()
+                .map(|interval| interval.data_interval_end.to_rfc3339())
+                .collect();
+            assert_eq!(fire_times, expected, "{} {} {}", cron, since, until);
+            assert_eq!(intervals[0].data_interval_start, at(since));
+        }
+
+        // Each interval starts where the previous one ended
+        let intervals = backfill_intervals(
+            "0 0 0 * * *",
+            at("2024-01-01T00:00:00Z"),
+            at("2024-01-03T00:00:00Z"),
+        )
+        .unwrap();
+        let rendered: Vec<_> = intervals
+            .iter()
+            .map(|interval| {
+                TemplateEngine::new()
+                    .with_data_interval_start(format_data_interval(
+                        interval.data_interval_start,
+                        DEFAULT_DATA_INTERVAL_FORMAT,
+                    ))
+                    .render(
+                        "created_at >= '{{ data_interval_start }}' AND created_at < '{{ data_interval_end }}'",
+                        &HashMap::new(),
+                        &format_data_interval(
+                            interval.data_interval_end,
+                            DEFAULT_DATA_INTERVAL_FORMAT,
+                        ),
+                    )
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(
+            rendered,
+            vec![
+                "created_at >= '2024-01-01 00:00:00' AND created_at < '2024-01-02 00:00:00'",
+                "created_at >= '2024-01-02 00:00:00' AND created_at < '2024-01-03 00:00:00'",
+            ]
+        );
+
+        let since = at("2024-01-01T01:00:00Z");
+        assert!(backfill_intervals("0 0 0 * * *", since, since).is_err());
+        // No fire time within the range
+        assert!(backfill_intervals("0 0 0 * * *", since, at("2024-01-01T23:00:00Z")).is_err());
+    }
+
+    #[test]
+    fn test_query_details() {
+        let task = test_task("");
+        let mut config = Config::default();
+        config.database_config.password = "s3cret".to_string();
+        let parameters = HashMap::from([
+            ("table_name".to_string(), "events".to_string()),
+            ("token".to_string(), "s3cret".to_string()),
+        ]);
+        let sql = "DELETE FROM events WHERE note = 's3cret' LIMIT 1000;";
+
+        let mut metadata = CleanupMetadata::new(&config, &task);
+        assert_eq!(query_details(&metadata), None);
+
+        metadata.sql = Some(sql);
+        metadata.parameters = Some(&parameters);
+        assert_eq!(
+            query_details(&metadata).unwrap(),
+            "*Query:*\n```DELETE FROM events WHERE note = '[REDACTED]' LIMIT 1000;```\n*Parameters:* `table_name=events`, `token=[REDACTED]`"
+        );
+
+        let long_sql = format!("DELETE FROM events WHERE id IN ({})", "1, ".repeat(1000));
+        metadata.sql = Some(&long_sql);
+        metadata.parameters = None;
+        let details = query_details(&metadata).unwrap();
+        assert!(details.ends_with("…```"));
+        assert_eq!(
+            details.chars().count(),
+            "*Query:*\n``````".chars().count() + REPORT_SQL_MAX_CHARS + 1
+        );
+    }
+
+    #[test]
+    fn test_report_owner() {
+        let config = Config::default();
+        let task = test_task("owner: data-platform\ntags: [nightly]");
+        let metadata = CleanupMetadata::new(&config, &task);
+        assert_eq!(
+            task_details(&metadata, "db.events"),
+            "*Host:* `localhost`\n*Task:* `test_task`\n*Target:* `db.events`\n*Owner:* \
+             `data-platform`\n*Tags:* `nightly`"
+        );
+        let CreateMessage::Blocks(blocks) = create_cleanup_report(metadata) else {
+            panic!("expected blocks");
+        };
+        assert!(blocks[1]["text"]["text"]
+            .as_str()
+            .unwrap()
+            .contains("*Owner:* `data-platform`"));
+
+        let template =
+            r#"[{"type": "section", "text": {"type": "mrkdwn", "text": "Owner: {{ owner }}"}}]"#
+                .to_string();
+        let metadata = CleanupMetadata::new(&config, &task);
+        let Some(CreateMessage::Blocks(blocks)) =
+            render_report_template(Some(&template), &metadata, "")
+        else {
+            panic!("expected blocks");
+        };
+        assert_eq!(blocks[0]["text"]["text"], "Owner: data-platform");
+
+        // Unowned tasks have no owner line
+        let task = test_task("");
+        assert!(
+            !task_details(&CleanupMetadata::new(&config, &task), "db.events").contains("Owner")
+        );
+    }
+
+    #[test]
+    fn test_db_clock_details() {
+        let app_time = DateTime::parse_from_rfc3339("2024-03-01T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let test_cases = vec![
+            ("2024-03-01 12:00:30", 30.0, ""),
+            ("2024-03-01 11:59:00", -60.0, ""),
+            ("2024-03-01 21:00:00", 32400.0, "\n⚠️ Clocks differ by 9h"),
+        ];
+        for (db_time, expected_skew, expected_warning) in test_cases {
+            let clock = DbClock {
+                app_time,
+                db_time: NaiveDateTime::parse_from_str(db_time, "%Y-%m-%d %H:%M:%S").unwrap(),
+            };
+            assert_eq!(clock.skew_seconds(), expected_skew);
+            assert_eq!(
+                db_clock_details(&clock, 60.0),
+                format!(
+                    "*Kiyoshi Time (UTC):*\n2024-03-01 12:00:00\n*Database NOW():*\n{}{}",
+                    db_time, expected_warning
+                )
+            );
+        }
+    }
 
     #[test]
     fn test_humanize_time() {
@@ -471,59 +4694,84 @@ mod tests {
         assert_eq!(humanize_time(3600.0), "1h");
         assert_eq!(humanize_time(3661.0), "1h 1m 1s");
         assert_eq!(humanize_time(7260.0), "2h 1m");
-        assert_eq!(humanize_time(93784.0), "26h 3m 4s");
+        assert_eq!(humanize_time(86399.0), "23h 59m 59s");
+        assert_eq!(humanize_time(86400.0), "1d");
+        assert_eq!(humanize_time(93784.0), "1d 2h 3m 4s");
+        assert_eq!(humanize_time(259260.0), "3d 1m");
+        assert_eq!(humanize_time(0.0), "0ms");
+        assert_eq!(humanize_time(-2.0), "0ms");
+        assert_eq!(humanize_time(f64::NAN), "n/a");
+        assert_eq!(humanize_time(f64::INFINITY), "n/a");
     }
-}
 
-fn create_error_report(metadata: &CleanupMetadata, error: &str) -> CreateMessage {
-    let schema_table = match (metadata.schema_name, metadata.table_name) {
-        (Some(schema), Some(table)) => format!("{}.{}", schema, table),
-        (None, Some(table)) => table.clone(),
-        (Some(schema), None) => schema.clone(),
-        (None, None) => "Unknown Target".to_string(),
-    };
-    CreateMessage::Blocks(serde_json::json!([
-        {
-            "type": "section",
-            "text": {
-                "type": "mrkdwn",
-                "text": "❌ *Cleanup Task Failed*"
-            }
-        },
-        {
-            "type": "section",
-            "text": {
-                "type": "mrkdwn",
-                "text": format!("*Host:* `{}`\n*Task:* `{}`\n*Target:* `{}`", metadata.config.database_config.host, metadata.task.name, schema_table)
-            }
-        },
-        {
-            "type": "section",
-            "text": {
-                "type": "mrkdwn",
-                "text": format!("*Error Details:*\n```\n{}\n```", error)
-            }
-        },
-        {
-            "type": "divider"
-        },
-        {
-            "type": "section",
-            "text": {
-                "type": "mrkdwn",
-                "text": "⚠️ *Action Required:* Please check the logs and investigate the issue."
-            }
-        },
-        {
-            "type": "context",
-            "elements": [
-                {
-                    "type": "mrkdwn",
-                    "text": format!("🚨 Failed: {} | 🫧 Kiyoshi Cleanup Service",
-                        chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC")
-                    )
-                }
-            ]
+    #[test]
+    fn test_humanize_duration_styles() {
+        // (seconds, verbose, clock)
+        let test_cases = vec![
+            (0.1, "100 milliseconds", "00:00:00.100"),
+            (0.001, "1 millisecond", "00:00:00.001"),
+            (1.0, "1 second", "00:00:01"),
+            (1.12345, "1.123 seconds", "00:00:01.123"),
+            (30.0, "30 seconds", "00:00:30"),
+            (60.0, "1 minute", "00:01:00"),
+            (90.5, "1 minute 30 seconds", "00:01:30"),
+            (3661.0, "1 hour 1 minute 1 second", "01:01:01"),
+            (7260.0, "2 hours 1 minute", "02:01:00"),
+            (86400.0, "1 day", "1d 00:00:00"),
+            (93784.0, "1 day 2 hours 3 minutes 4 seconds", "1d 02:03:04"),
+            (180000.0, "2 days 2 hours", "2d 02:00:00"),
+        ];
+        for (seconds, verbose, clock) in test_cases {
+            assert_eq!(
+                humanize_duration(seconds, DurationStyle::Verbose),
+                verbose,
+                "{}",
+                seconds
+            );
+            assert_eq!(
+                humanize_duration(seconds, DurationStyle::Clock),
+                clock,
+                "{}",
+                seconds
+            );
+            assert_eq!(
+                humanize_duration(seconds, DurationStyle::Compact),
+                humanize_time(seconds),
+                "{}",
+                seconds
+            );
         }
-    ]))
+    }
+
+    #[test]
+    fn test_humanize_rate() {
+        assert_eq!(humanize_rate(1000, 2.0), "500.0 rows/s");
+        assert_eq!(humanize_rate(10, 3.0), "3.3 rows/s");
+        assert_eq!(humanize_rate(0, 1.5), "0.0 rows/s");
+        assert_eq!(humanize_rate(0, 0.0), "n/a");
+        assert_eq!(humanize_rate(1000, 0.0), "n/a");
+        assert_eq!(humanize_rate(1000, -1.0), "n/a");
+        assert_eq!(humanize_rate(1000, f64::NAN), "n/a");
+        assert_eq!(humanize_rate(1000, f64::INFINITY), "n/a");
+        assert_eq!(humanize_rate(u64::MAX, f64::MIN_POSITIVE), "n/a");
+        assert_eq!(humanize_rate(12_345_678, 10.0), "1,234,567.8 rows/s");
+        assert_eq!(humanize_rate(19_999, 2.0), "9,999.5 rows/s");
+    }
+
+    #[test]
+    fn test_format_count() {
+        let test_cases = vec![
+            (0, "0"),
+            (7, "7"),
+            (999, "999"),
+            (1000, "1,000"),
+            (12345, "12,345"),
+            (1_234_567, "1,234,567"),
+            (100_000_000, "100,000,000"),
+            (u64::MAX, "18,446,744,073,709,551,615"),
+        ];
+        for (count, expected) in test_cases {
+            assert_eq!(format_count(count), expected, "{}", count);
+        }
+    }
 }
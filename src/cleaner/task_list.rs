@@ -0,0 +1,197 @@
+use chrono::Utc;
+use serde::Serialize;
+
+use crate::cleaner::{
+    config::FullConfig,
+    task::{render_task_query, task_target},
+};
+
+/// One row of `--list-tasks`.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct TaskSummary {
+    pub name: String,
+    pub enabled: bool,
+    pub cron: String,
+    pub batch_size: u32,
+    pub timeout_seconds: f64,
+    pub target: String,
+    pub valid: bool,
+    /// Why the task failed validation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Summarizes every configured task, including those disabled by lenient validation. Valid tasks
+/// are also rendered as of now and checked against safe mode.
+pub fn summarize_tasks(config: &FullConfig) -> Vec<TaskSummary> {
+    let data_interval_end = Utc::now();
+    let mut summaries = config
+        .cleanup_tasks
+        .iter()
+        .map(|task| {
+            // Partition drops have no query to render
+            let error = match task.partition_drop {
+                Some(_) => None,
+                None => render_task_query(&config.config, task, data_interval_end)
+                    .err()
+                    .map(|e| format!("{:#}", e)),
+            };
+            TaskSummary {
+                name: task.name.clone(),
+                enabled: task.enabled,
+                cron: task.schedule_description(),
+                batch_size: task.batch_size,
+                timeout_seconds: task.task_timeout_seconds,
+                target: task_target(&config.config, task),
+                valid: error.is_none(),
+                error,
+            }
+        })
+        .collect::<Vec<_>>();
+    // Tasks dropped by lenient validation only keep their name and the reason
+    summaries.extend(config.disabled_tasks.iter().map(|task| TaskSummary {
+        name: task.name.clone(),
+        enabled: false,
+        cron: String::new(),
+        batch_size: 0,
+        timeout_seconds: 0.0,
+        target: String::new(),
+        valid: false,
+        error: Some(task.reason.clone()),
+    }));
+    summaries
+}
+
+/// Formats task summaries as an aligned text table, with validation errors listed below it.
+pub fn render_table(summaries: &[TaskSummary]) -> String {
+    let header = [
+        "NAME", "ENABLED", "CRON", "BATCH", "TIMEOUT", "TARGET", "VALID",
+    ];
+    let rows = summaries
+        .iter()
+        .map(|summary| {
+            [
+                summary.name.clone(),
+                summary.enabled.to_string(),
+                summary.cron.clone(),
+                summary.batch_size.to_string(),
+                format!("{}s", summary.timeout_seconds),
+                summary.target.clone(),
+                if summary.valid { "yes" } else { "no" }.to_string(),
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    let mut widths = header.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let format_row = |cells: &[String]| {
+        cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+
+    let mut lines = vec![format_row(&header.map(String::from))];
+    lines.extend(rows.iter().map(|row| format_row(row)));
+    for summary in summaries {
+        if let Some(error) = &summary.error {
+            lines.push(format!("{}: {}", summary.name, error));
+        }
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_table() {
+        let summaries = vec![
+            TaskSummary {
+                name: "cleanup_events".to_string(),
+                enabled: true,
+                cron: "0 0 3 * * *".to_string(),
+                batch_size: 1000,
+                timeout_seconds: 3600.0,
+                target: "app.events".to_string(),
+                valid: true,
+                error: None,
+            },
+            TaskSummary {
+                name: "bad".to_string(),
+                enabled: false,
+                cron: String::new(),
+                batch_size: 0,
+                timeout_seconds: 0.0,
+                target: String::new(),
+                valid: false,
+                error: Some("Batch size must be greater than 0".to_string()),
+            },
+        ];
+
+        assert_eq!(
+            render_table(&summaries),
+            "NAME            ENABLED  CRON         BATCH  TIMEOUT  TARGET      VALID\n\
+             cleanup_events  true     0 0 3 * * *  1000   3600s    app.events  yes\n\
+             bad             false                 0      0s                   no\n\
+             bad: Batch size must be greater than 0"
+        );
+    }
+
+    #[test]
+    fn test_summarize_tasks_validates_queries() {
+        let config = FullConfig::load_from_str(
+            r#"
+config:
+  database_config:
+    host: localhost
+    username: root
+    password: secret
+    database: my_db
+  slack_config:
+    bot_token: xoxb-test
+    channel_id: C0123
+  safe_mode:
+    enabled: true
+    retention_days: 30
+cleanup_tasks:
+  - name: safe
+    description: ""
+    cron_schedule: "0 0 3 * * *"
+    enabled: true
+    template_query: "DELETE FROM {{ table_name }} WHERE created_at < DATE_SUB('{{ data_interval_end }}', INTERVAL 30 DAY) LIMIT 1"
+    parameters:
+      table_name: events
+    batch_size: 1000
+    retry_attempts: 3
+    retry_delay_seconds: 5
+  - name: too_recent
+    description: ""
+    cron_schedule: "0 0 3 * * *"
+    enabled: true
+    template_query: "DELETE FROM events WHERE created_at < DATE_SUB('{{ data_interval_end }}', INTERVAL 1 DAY) LIMIT 1"
+    parameters: {}
+    batch_size: 1000
+    retry_attempts: 3
+    retry_delay_seconds: 5
+"#,
+        )
+        .unwrap();
+
+        let summaries = summarize_tasks(&config);
+        assert_eq!(summaries.len(), 2);
+        assert!(summaries[0].valid);
+        assert_eq!(summaries[0].target, "my_db.events");
+        assert!(!summaries[1].valid);
+        assert!(summaries[1].error.as_ref().unwrap().contains("DATE_SUB"));
+    }
+}
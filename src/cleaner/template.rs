@@ -1,38 +1,193 @@
 use anyhow::Result;
-use chrono::{DateTime, Utc};
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, Timelike, Utc,
+};
 use cron::Schedule;
-use minijinja::Environment;
+use minijinja::{Environment, Error, ErrorKind, Value};
 use std::{collections::HashMap, str::FromStr};
 
+/// Quote character used by the `ident` filter when rendering identifiers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdentifierQuote {
+    /// MySQL style: `` `name` ``
+    Backtick,
+    /// Postgres/ANSI style: `"name"`
+    DoubleQuote,
+}
+
+impl IdentifierQuote {
+    fn as_char(self) -> char {
+        match self {
+            IdentifierQuote::Backtick => '`',
+            IdentifierQuote::DoubleQuote => '"',
+        }
+    }
+}
+
 pub struct TemplateEngine {
     env: Environment<'static>,
+    /// The formatted start of the run's data interval, see
+    /// [`TemplateEngine::with_data_interval_start`]
+    data_interval_start: Option<String>,
 }
 
 impl TemplateEngine {
     pub fn new() -> Self {
+        Self::with_identifier_quote(IdentifierQuote::Backtick)
+    }
+
+    pub fn with_identifier_quote(quote: IdentifierQuote) -> Self {
+        let mut env = Environment::new();
+        env.add_filter("ident", move |value: String| {
+            quote_identifier(&value, quote)
+        });
         Self {
-            env: Environment::new(),
+            env,
+            data_interval_start: None,
         }
     }
 
+    /// Makes `data_interval_start`, the formatted start of the interval ending at
+    /// `data_interval_end`, available to the rendered templates.
+    pub fn with_data_interval_start(mut self, data_interval_start: String) -> Self {
+        self.data_interval_start = Some(data_interval_start);
+        self
+    }
+
     pub fn render(
         &self,
         template: &str,
         params: &HashMap<String, String>,
         data_interval_end: &str,
     ) -> Result<String> {
-        let mut context = params.clone();
+        let mut context: HashMap<String, Value> = params
+            .iter()
+            .map(|(key, value)| (key.clone(), Value::from(value.as_str())))
+            .collect();
         context.insert(
             "data_interval_end".to_string(),
-            data_interval_end.to_string(),
+            Value::from(data_interval_end),
         );
+        if let Some(data_interval_start) = &self.data_interval_start {
+            context.insert(
+                "data_interval_start".to_string(),
+                Value::from(data_interval_start.as_str()),
+            );
+        }
+        // `now()` is the run's reference instant rather than the wall clock, so pinning
+        // `data_interval_end` (e.g. with `--as-of`) also pins it
+        let now = data_interval_end.to_string();
+        context.insert("now".to_string(), Value::from_function(move || now.clone()));
 
         let tmpl = self.env.template_from_str(template)?;
-        let rendered = tmpl.render(context)?;
+        let rendered = tmpl.render(Value::from_iter(context))?;
         Ok(rendered)
     }
 
-    #[allow(dead_code)]
+    /// Renders parameter values that reference other parameters, e.g. `table_name:
+    /// "{{ prefix }}_events"`, in dependency order. Values without template syntax are kept as
+    /// they are. Referencing an unknown parameter or a cycle of parameters is an error.
+    pub fn resolve_parameters(
+        &self,
+        params: &HashMap<String, String>,
+    ) -> Result<HashMap<String, String>> {
+        let mut names: Vec<_> = params.keys().collect();
+        names.sort();
+        let mut resolved = HashMap::with_capacity(params.len());
+        for name in names {
+            self.resolve_parameter(name, params, &mut resolved, &mut Vec::new())?;
+        }
+        Ok(resolved)
+    }
+
+    fn resolve_parameter(
+        &self,
+        name: &str,
+        params: &HashMap<String, String>,
+        resolved: &mut HashMap<String, String>,
+        path: &mut Vec<String>,
+    ) -> Result<()> {
+        if resolved.contains_key(name) {
+            return Ok(());
+        }
+        if let Some(start) = path.iter().position(|visited| visited == name) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(name.to_string());
+            return Err(anyhow::anyhow!(
+                "Parameter reference cycle: {}",
+                cycle.join(" -> ")
+            ));
+        }
+        let value = &params[name];
+        if !["{{", "{%", "{#"]
+            .iter()
+            .any(|syntax| value.contains(syntax))
+        {
+            resolved.insert(name.to_string(), value.clone());
+            return Ok(());
+        }
+
+        let tmpl = self.env.template_from_str(value)?;
+        let mut references: Vec<_> = tmpl.undeclared_variables(false).into_iter().collect();
+        references.sort();
+        path.push(name.to_string());
+        for reference in &references {
+            if !params.contains_key(reference) {
+                return Err(anyhow::anyhow!(
+                    "Parameter {} references unknown parameter {}",
+                    name,
+                    reference
+                ));
+            }
+            self.resolve_parameter(reference, params, resolved, path)?;
+        }
+        path.pop();
+
+        let context: HashMap<&str, &str> = references
+            .iter()
+            .map(|reference| (reference.as_str(), resolved[reference].as_str()))
+            .collect();
+        let rendered = tmpl.render(context)?;
+        resolved.insert(name.to_string(), rendered);
+        Ok(())
+    }
+
+    /// Checks that a template parses, without rendering it.
+    pub fn check_syntax(&self, template: &str) -> Result<()> {
+        self.env.template_from_str(template)?;
+        Ok(())
+    }
+
+    /// Renders a template whose output must be a JSON array, such as Slack blocks. Context values
+    /// are JSON-escaped so they can be placed inside JSON strings, e.g. `"text": "{{ error }}"`.
+    pub fn render_json_array(
+        &self,
+        template: &str,
+        context: &HashMap<String, String>,
+    ) -> Result<serde_json::Value> {
+        let escaped_context: HashMap<&str, String> = context
+            .iter()
+            .map(|(key, value)| {
+                let quoted = serde_json::to_string(value)?;
+                Ok((key.as_str(), quoted[1..quoted.len() - 1].to_string()))
+            })
+            .collect::<Result<_, serde_json::Error>>()?;
+
+        let tmpl = self.env.template_from_str(template)?;
+        let rendered = tmpl.render(escaped_context)?;
+        let value: serde_json::Value = serde_json::from_str(&rendered)
+            .map_err(|e| anyhow::anyhow!("Rendered template is not valid JSON: {}", e))?;
+        if !value.is_array() {
+            return Err(anyhow::anyhow!(
+                "Rendered template must be a JSON array of blocks"
+            ));
+        }
+        Ok(value)
+    }
+
+    /// Returns the most recent fire time of `schedule` at or before `now`, e.g. to compute the
+    /// start of the interval that ends at the current run.
     pub fn get_previous_schedule(
         &self,
         schedule: &str,
@@ -40,15 +195,79 @@ impl TemplateEngine {
     ) -> Result<DateTime<Utc>> {
         // Parse cron expression
         let schedule = Schedule::from_str(schedule)?;
-        let prev_run = schedule
+        let now_seconds = now.with_nanosecond(0).unwrap_or(now);
+        if schedule.includes(now_seconds) {
+            return Ok(now_seconds);
+        }
+        // Iterating an `after` iterator backwards yields fire times before `now`
+        schedule
             .after(&now)
-            .take(1)
-            .next()
-            .ok_or_else(|| anyhow::anyhow!("Could not calculate previous run time"))?;
+            .next_back()
+            .ok_or_else(|| anyhow::anyhow!("Could not calculate previous run time"))
+    }
+}
+
+/// Default format of `data_interval_end` in templates.
+pub const DEFAULT_DATA_INTERVAL_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Formats `data_interval_end` with a `chrono` format string, or as a numeric Unix timestamp for
+/// the special formats `epoch` (seconds) and `epoch_millis`.
+pub fn format_data_interval(data_interval_end: DateTime<Utc>, format: &str) -> String {
+    match format {
+        "epoch" => data_interval_end.timestamp().to_string(),
+        "epoch_millis" => data_interval_end.timestamp_millis().to_string(),
+        format => data_interval_end.format(format).to_string(),
+    }
+}
 
-        println!("prev_run: {}, now: {}", prev_run, now);
-        Ok(prev_run)
+/// Checks that a `data_interval_format` is `epoch`, `epoch_millis` or a valid `chrono` format.
+pub fn check_data_interval_format(format: &str) -> Result<()> {
+    if format == "epoch" || format == "epoch_millis" {
+        return Ok(());
+    }
+    if format.is_empty() || StrftimeItems::new(format).any(|item| item == Item::Error) {
+        return Err(anyhow::anyhow!(
+            "Invalid data_interval_format '{}', expected a chrono format string, epoch or epoch_millis",
+            format
+        ));
     }
+    Ok(())
+}
+
+/// Quotes a (possibly schema-qualified) identifier, escaping embedded quote characters by doubling
+/// them. Each dot-separated part is quoted separately so `schema.table` stays qualified.
+pub fn quote_identifier(value: &str, quote: IdentifierQuote) -> Result<String, Error> {
+    if value.trim().is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidOperation,
+            "identifier cannot be empty",
+        ));
+    }
+    if value.contains(['\n', '\r', ';', '\0']) {
+        return Err(Error::new(
+            ErrorKind::InvalidOperation,
+            format!(
+                "identifier {:?} must not contain newlines, semicolons or NUL characters",
+                value
+            ),
+        ));
+    }
+
+    let q = quote.as_char();
+    let escaped = q.to_string().repeat(2);
+    let parts = value
+        .split('.')
+        .map(|part| {
+            if part.is_empty() {
+                return Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    format!("identifier {:?} has an empty part", value),
+                ));
+            }
+            Ok(format!("{q}{}{q}", part.replace(q, &escaped)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(parts.join("."))
 }
 
 impl Default for TemplateEngine {
@@ -76,6 +295,46 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_resolve_parameters() -> Result<()> {
+        let engine = TemplateEngine::new();
+        let params = HashMap::from([
+            ("prefix".to_string(), "app".to_string()),
+            ("table_name".to_string(), "{{ prefix }}_events".to_string()),
+            (
+                "archive".to_string(),
+                "{{ table_name }}_archive".to_string(),
+            ),
+            ("quoted".to_string(), "{{ table_name | ident }}".to_string()),
+        ]);
+        let resolved = engine.resolve_parameters(&params)?;
+        assert_eq!(resolved["prefix"], "app");
+        assert_eq!(resolved["table_name"], "app_events");
+        assert_eq!(resolved["archive"], "app_events_archive");
+        assert_eq!(resolved["quoted"], "`app_events`");
+
+        let test_cases = vec![
+            (
+                vec![("a", "{{ b }}"), ("b", "{{ a }}_x")],
+                "Parameter reference cycle: a -> b -> a",
+            ),
+            (vec![("a", "{{ a }}")], "Parameter reference cycle: a -> a"),
+            (
+                vec![("a", "{{ data_interval_end }}")],
+                "Parameter a references unknown parameter data_interval_end",
+            ),
+        ];
+        for (params, expected) in test_cases {
+            let params = params
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect();
+            let error = engine.resolve_parameters(&params).unwrap_err();
+            assert_eq!(error.to_string(), expected);
+        }
+        Ok(())
+    }
+
     #[test]
     fn test_template_render_empty_params() -> Result<()> {
         let engine = TemplateEngine::new();
@@ -87,6 +346,37 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_template_render_now() -> Result<()> {
+        let engine = TemplateEngine::new();
+        let template = "WHERE created_at < DATE_SUB('{{ now() }}', INTERVAL 30 DAY)";
+        let result = engine.render(template, &HashMap::new(), "2024-01-02 03:04:05")?;
+
+        assert_eq!(
+            result,
+            "WHERE created_at < DATE_SUB('2024-01-02 03:04:05', INTERVAL 30 DAY)"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_data_interval() {
+        let data_interval_end = Utc.with_ymd_and_hms(2024, 3, 20, 6, 30, 0).unwrap();
+        let test_cases = vec![
+            (DEFAULT_DATA_INTERVAL_FORMAT, "2024-03-20 06:30:00"),
+            ("%Y-%m-%d", "2024-03-20"),
+            ("epoch", "1710916200"),
+            ("epoch_millis", "1710916200000"),
+        ];
+        for (format, expected) in test_cases {
+            check_data_interval_format(format).unwrap();
+            assert_eq!(format_data_interval(data_interval_end, format), expected);
+        }
+
+        assert!(check_data_interval_format("%Y-%m-%d %Q").is_err());
+        assert!(check_data_interval_format("").is_err());
+    }
+
     #[test]
     fn test_template_render_invalid_template() {
         let engine = TemplateEngine::new();
@@ -97,20 +387,106 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_template_render_ident_filter() -> Result<()> {
+        let engine = TemplateEngine::new();
+        let test_cases = vec![
+            ("events", "`events`"),
+            ("my table", "`my table`"),
+            ("we`ird", "`we``ird`"),
+            ("analytics.events", "`analytics`.`events`"),
+            ("`already`", "```already```"),
+        ];
+
+        for (table_name, expected) in test_cases {
+            let params = HashMap::from([("table_name".to_string(), table_name.to_string())]);
+            let result = engine.render(
+                "DELETE FROM {{ table_name | ident }}",
+                &params,
+                "2024-01-02",
+            )?;
+            assert_eq!(result, format!("DELETE FROM {}", expected));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_template_render_ident_filter_double_quote() -> Result<()> {
+        let engine = TemplateEngine::with_identifier_quote(IdentifierQuote::DoubleQuote);
+        let params = HashMap::from([("table_name".to_string(), "public.we\"ird".to_string())]);
+        let result = engine.render("{{ table_name | ident }}", &params, "2024-01-02")?;
+
+        assert_eq!(result, "\"public\".\"we\"\"ird\"");
+        Ok(())
+    }
+
+    #[test]
+    fn test_template_render_ident_filter_rejects_unsafe() {
+        let engine = TemplateEngine::new();
+        for table_name in [
+            "events; DROP TABLE users",
+            "events\nWHERE 1=1",
+            "",
+            "schema.",
+        ] {
+            let params = HashMap::from([("table_name".to_string(), table_name.to_string())]);
+            let result = engine.render("{{ table_name | ident }}", &params, "2024-01-02");
+            assert!(
+                result.is_err(),
+                "identifier {:?} should be rejected",
+                table_name
+            );
+        }
+    }
+
+    #[test]
+    fn test_template_render_json_array() -> Result<()> {
+        let engine = TemplateEngine::new();
+        let context = HashMap::from([("error".to_string(), "bad \"quote\"\nline".to_string())]);
+        let template =
+            r#"[{"type": "section", "text": {"type": "mrkdwn", "text": "{{ error }}"}}]"#;
+        let result = engine.render_json_array(template, &context)?;
+
+        assert_eq!(result[0]["text"]["text"], "bad \"quote\"\nline");
+
+        let not_array = engine.render_json_array(r#"{"type": "divider"}"#, &context);
+        assert!(not_array.is_err());
+        let not_json = engine.render_json_array("{{ error }}", &context);
+        assert!(not_json.is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_get_previous_schedule() -> Result<()> {
         let engine = TemplateEngine::new();
 
-        // Test with a fixed time for deterministic results
-        let fixed_time = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
         let schedule = "0 0 0 * * *"; // Daily at midnight
+        let test_cases = vec![
+            // A fire time exactly at `now` is the previous schedule
+            (
+                Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap(),
+                "2024-01-01 00:00:00",
+            ),
+            (
+                Utc.with_ymd_and_hms(2024, 1, 1, 12, 30, 0).unwrap(),
+                "2024-01-01 00:00:00",
+            ),
+            (
+                Utc.with_ymd_and_hms(2023, 12, 31, 23, 59, 59).unwrap(),
+                "2023-12-31 00:00:00",
+            ),
+            (
+                Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap()
+                    + chrono::Duration::milliseconds(500),
+                "2024-03-01 00:00:00",
+            ),
+        ];
 
-        let result = engine.get_previous_schedule(schedule, fixed_time)?;
-        assert_eq!(
-            result.format("%Y-%m-%d %H:%M:%S").to_string(),
-            "2024-01-02 00:00:00"
-        );
-        assert!(result > fixed_time);
+        for (now, expected) in test_cases {
+            let result = engine.get_previous_schedule(schedule, now)?;
+            assert_eq!(result.format("%Y-%m-%d %H:%M:%S").to_string(), expected);
+            assert!(result <= now);
+        }
 
         Ok(())
     }
@@ -0,0 +1,672 @@
+//! The `kiyoshi` command line: loads the config and runs, schedules or inspects its tasks.
+
+use crate::{
+    cleaner::{
+        self,
+        circuit_breaker::CircuitBreaker,
+        config::SlackStartupValidation,
+        health::{SessionStats, TaskHealth},
+        task,
+    },
+    scheduler::job::panic_message,
+    Database, Job, JobScheduleMetadata, Scheduler,
+};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use clap::{Parser, ValueEnum};
+use log::{error, info, warn};
+use std::{future::Future, pin::Pin, sync::Arc};
+use tokio::signal;
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    /// Path to the YAML configuration file, or `-` to read it from stdin
+    #[arg(short, long, default_value = "config.yaml")]
+    config_file: String,
+
+    /// Path to a JSON or dotenv file containing environment variables
+    #[arg(short, long)]
+    env_file: Option<String>,
+
+    /// Optional: Enable verbose logging. Repeat for more detail: -v debug, -vv trace, -vvv also
+    /// every SQL statement run by sqlx
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Also write logs to this file, rotating it by size
+    #[arg(long, value_name = "PATH")]
+    log_file: Option<String>,
+
+    /// Rotate the --log-file once it would grow beyond this many megabytes
+    #[arg(long, value_name = "MB", default_value_t = 100, requires = "log_file")]
+    log_rotate_size_mb: u64,
+
+    /// Number of rotated log files to keep next to the --log-file
+    #[arg(long, value_name = "N", default_value_t = 5, requires = "log_file")]
+    log_max_files: usize,
+
+    /// Only write logs to the --log-file, not to stderr
+    #[arg(long, requires = "log_file")]
+    log_file_only: bool,
+
+    /// Check the database connection and credentials, then exit without running any task
+    #[arg(long)]
+    test_connection: bool,
+
+    /// Print a summary of the configured tasks and whether they pass validation, then exit
+    #[arg(long)]
+    list_tasks: bool,
+
+    /// Output format of --list-tasks
+    #[arg(long, value_enum, default_value_t = OutputFormat::Table, requires = "list_tasks")]
+    format: OutputFormat,
+
+    /// Only schedule tasks with any of these comma-separated tags
+    #[arg(long, value_delimiter = ',')]
+    tags: Vec<String>,
+
+    /// Skip tasks with any of these comma-separated tags
+    #[arg(long, value_delimiter = ',')]
+    exclude_tags: Vec<String>,
+
+    /// Print the MySQL EXPLAIN plan of the named task's rendered query, flagging full table scans,
+    /// then exit
+    #[arg(long, value_name = "TASK")]
+    profile_query: Option<String>,
+
+    /// Run the named task once immediately, then exit
+    #[arg(long, value_name = "TASK")]
+    run_task: Option<String>,
+
+    /// With --run-task, use this RFC 3339 timestamp as the run's data_interval_end
+    #[arg(long, value_name = "RFC3339", requires = "run_task")]
+    as_of: Option<String>,
+
+    /// With --run-task, backfill by running the task once for each scheduled fire time after this
+    /// RFC 3339 timestamp, up to --until
+    #[arg(
+        long,
+        value_name = "RFC3339",
+        requires_all = ["run_task", "until"],
+        conflicts_with = "as_of"
+    )]
+    since: Option<String>,
+
+    /// End of the --since backfill range, inclusive
+    #[arg(long, value_name = "RFC3339", requires = "since")]
+    until: Option<String>,
+
+    /// Keep backfilling the remaining intervals after a failed run
+    #[arg(long, requires = "since")]
+    continue_on_error: bool,
+
+    /// With --run-task, skip the confirmation prompt. Required when stdin is not a terminal
+    #[arg(short, long, requires = "run_task")]
+    yes: bool,
+
+    /// Allow an --as-of or --until timestamp in the future
+    #[arg(long)]
+    allow_future: bool,
+
+    /// Merge this entry of the config file's `profiles` over the base settings. Defaults to the
+    /// KIYOSHI_PROFILE environment variable
+    #[arg(long)]
+    profile: Option<String>,
+
+    /// Write a commented starter config file to the given path, then exit
+    #[arg(long, value_name = "PATH")]
+    generate_config: Option<String>,
+
+    /// Print the task and setting differences between two config files as Kiyoshi loads them,
+    /// then exit
+    #[arg(long, num_args = 2, value_names = ["OLD", "NEW"])]
+    diff: Option<Vec<String>>,
+
+    /// Print every environment variable substitution in the config file and the resulting config
+    /// with secrets redacted, then exit
+    #[arg(long)]
+    explain_config: bool,
+
+    /// Print the JSON Schema of the config file, for editors and pre-commit checks, then exit
+    #[arg(long)]
+    print_schema: bool,
+
+    /// Check the config file against the JSON Schema before loading it, reporting every
+    /// violation at once
+    #[arg(long)]
+    validate_schema: bool,
+
+    /// Overwrite an existing file with --generate-config
+    #[arg(long, requires = "generate_config")]
+    force: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    Table,
+    Json,
+}
+
+/// Runs the `kiyoshi` command line with the process arguments, as the binary does.
+pub async fn run() -> Result<()> {
+    // Parse command line arguments
+    let cli = Cli::parse();
+
+    // Initialize logging with optional verbose mode
+    let (log_level, sqlx_log_level) = cleaner::log_file::log_levels(cli.verbose);
+
+    let mut logger = env_logger::Builder::new();
+    // Flushes the log file when the command returns
+    let mut _log_file_guard = None;
+    if let Some(path) = &cli.log_file {
+        let (writer, guard) = cleaner::log_file::open_log_file(
+            path,
+            cli.log_rotate_size_mb,
+            cli.log_max_files,
+            !cli.log_file_only,
+        )?;
+        logger.target(env_logger::Target::Pipe(Box::new(writer)));
+        _log_file_guard = Some(guard);
+    }
+    logger
+        .filter_level(log_level)
+        .filter_module("sqlx", sqlx_log_level)
+        .format(|buf, record| {
+            use std::io::Write;
+            // Log lines of a task run carry the run's id
+            let run_id = cleaner::run_id::current_run_id()
+                .map(|run_id| format!("[run {}] ", run_id))
+                .unwrap_or_default();
+            writeln!(
+                buf,
+                "{} {} [{}:{}] {}{}",
+                Utc::now().format("%Y-%m-%d %H:%M:%S"),
+                record.level(),
+                record.file().unwrap_or("unknown"),
+                record.line().unwrap_or(0),
+                run_id,
+                record.args()
+            )
+        })
+        .init();
+
+    if let Some(path) = &cli.generate_config {
+        cleaner::config::write_example_config(path, cli.force)?;
+        println!("Wrote example configuration to {}", path);
+        return Ok(());
+    }
+
+    if cli.print_schema {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&cleaner::schema::config_schema())?
+        );
+        return Ok(());
+    }
+
+    if let Some(env_file_path) = cli.env_file {
+        if let Err(e) = cleaner::config::load_env_from_file(&env_file_path) {
+            error!("Failed to load environment file: {}", e);
+            return Err(e);
+        }
+    }
+
+    let profile = cli
+        .profile
+        .clone()
+        .or_else(|| std::env::var("KIYOSHI_PROFILE").ok())
+        .filter(|profile| !profile.is_empty());
+
+    if let Some(paths) = &cli.diff {
+        let old = cleaner::config::FullConfig::load_from_path_with_profile(
+            &paths[0],
+            profile.as_deref(),
+        )?;
+        let new = cleaner::config::FullConfig::load_from_path_with_profile(
+            &paths[1],
+            profile.as_deref(),
+        )?;
+        let diff = cleaner::config_diff::diff_configs(&old, &new);
+        println!("{}", cleaner::config_diff::render_diff(&diff));
+        return Ok(());
+    }
+
+    if cli.explain_config {
+        let config_str = cleaner::config::read_config_str(&cli.config_file)?;
+        let (_, substitutions) = cleaner::config::explain_env_vars(&config_str);
+        println!(
+            "{}\n",
+            cleaner::config::render_env_substitutions(&substitutions)
+        );
+        let config = cleaner::config::FullConfig::load_from_str_with_profile(
+            &config_str,
+            profile.as_deref(),
+        )?;
+        print!("{}", serde_yaml::to_string(&config.redacted())?);
+        return Ok(());
+    }
+
+    // TODO: load from directory so we can run multiple config files
+    // Load configuration from specified path
+    let config_str = cleaner::config::read_config_str(&cli.config_file)?;
+    if cli.validate_schema {
+        cleaner::schema::check_config_schema(&config_str, profile.as_deref())?;
+    }
+    let mut config =
+        cleaner::config::FullConfig::load_from_str_with_profile(&config_str, profile.as_deref())?;
+    if cli.config_file == cleaner::config::STDIN_CONFIG_PATH {
+        info!("Configuration loaded successfully from stdin");
+    } else {
+        info!("Configuration loaded successfully from {}", cli.config_file);
+    }
+
+    if !cli.tags.is_empty() || !cli.exclude_tags.is_empty() {
+        let task_count = config.cleanup_tasks.len();
+        config
+            .cleanup_tasks
+            .retain(|task| task.matches_tags(&cli.tags, &cli.exclude_tags));
+        info!(
+            "Selected {} of {} task(s) by tags (include: {:?}, exclude: {:?})",
+            config.cleanup_tasks.len(),
+            task_count,
+            cli.tags,
+            cli.exclude_tags
+        );
+    }
+
+    if cli.list_tasks {
+        let summaries = cleaner::task_list::summarize_tasks(&config);
+        match cli.format {
+            OutputFormat::Table => println!("{}", cleaner::task_list::render_table(&summaries)),
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&summaries)?),
+        }
+        return Ok(());
+    }
+
+    if cli.test_connection {
+        return test_connection(&config.config).await;
+    }
+
+    if let Some(task_name) = &cli.profile_query {
+        let task = find_task(&config, task_name)?;
+        print!(
+            "{}",
+            cleaner::query_plan::profile_query(&config.config, task).await?
+        );
+        return Ok(());
+    }
+
+    if let (Some(task_name), Some(since), Some(until)) = (&cli.run_task, &cli.since, &cli.until) {
+        let since = DateTime::parse_from_rfc3339(since)
+            .map_err(|e| anyhow::anyhow!("Invalid --since timestamp '{}': {}", since, e))?
+            .with_timezone(&Utc);
+        let until = task::parse_as_of(until, cli.allow_future, Utc::now())?;
+        if !cli.yes {
+            let task = find_task(&config, task_name)?;
+            cleaner::confirm::confirm_run(&config.config, task, until).await?;
+        }
+        return run_backfill(&config, task_name, since, until, cli.continue_on_error).await;
+    }
+
+    if let Some(task_name) = &cli.run_task {
+        let as_of = match &cli.as_of {
+            Some(as_of) => task::parse_as_of(as_of, cli.allow_future, Utc::now())?,
+            None => Utc::now(),
+        };
+        if !cli.yes {
+            let task = find_task(&config, task_name)?;
+            cleaner::confirm::confirm_run(&config.config, task, as_of).await?;
+        }
+        return run_task_once(&config, task_name, as_of).await;
+    }
+
+    let slack_config = &config.config.slack_config;
+    if slack_config.enabled && slack_config.validate_slack_on_startup != SlackStartupValidation::Off
+    {
+        if let Err(e) = cleaner::slack::validate_slack_config(slack_config).await {
+            if slack_config.validate_slack_on_startup == SlackStartupValidation::Fail {
+                error!("Slack validation failed: {:#}", e);
+                return Err(e);
+            }
+            error!(
+                "!!! Slack validation failed, notifications will not be delivered: {:#}",
+                e
+            );
+        }
+    }
+
+    if !config.disabled_tasks.is_empty() {
+        for disabled_task in &config.disabled_tasks {
+            error!(
+                "Task '{}' disabled by lenient validation: {}",
+                disabled_task.name, disabled_task.reason
+            );
+        }
+        task::notify_disabled_tasks(&config.config, &config.disabled_tasks).await;
+    }
+
+    let exit_nonzero_on_failures = config.config.exit_nonzero_on_failures;
+    let task_health = Arc::new(TaskHealth::default());
+    let session_stats = Arc::new(SessionStats::default());
+    let shutdown_config = config.config.clone();
+    let circuit_breaker = Arc::new(CircuitBreaker::new(config.config.circuit_breaker.clone()));
+    cleaner::run_history::init(&config.config.run_history)?;
+    let dead_letters = cleaner::dead_letter::init(&config.config.dead_letter)?;
+    for (task_name, dead_letter) in dead_letters.snapshot() {
+        warn!(
+            "Task {} stays disabled after {} consecutive failed runs since {}, re-enable it \
+             through the admin server",
+            task_name, dead_letter.consecutive_failures, dead_letter.disabled_at
+        );
+    }
+    if config.config.admin_server.enabled {
+        cleaner::admin::start(&config.config.admin_server.listen_address, &config).await?;
+    }
+
+    let mut scheduler = Scheduler::default();
+    let full_configs = vec![config];
+    for full_config in full_configs {
+        for task in full_config.cleanup_tasks {
+            if !task.enabled {
+                info!("Not scheduling disabled task: {}", task.name);
+                continue;
+            }
+            let min_interval = task.min_interval(&full_config.config);
+            let initial_delay = task.initial_delay(&full_config.config);
+            if let Some(initial_delay) = initial_delay {
+                info!(
+                    "Task `{}` will not run in the first {:?} after startup",
+                    task.name, initial_delay
+                );
+            }
+            let config_clone = full_config.config.clone();
+            let task_clone = task.clone();
+            let task_health = Arc::clone(&task_health);
+            let session_stats = Arc::clone(&session_stats);
+            let circuit_breaker = Arc::clone(&circuit_breaker);
+            let completion_interval = task.completion_interval();
+            let function = move |metadata| {
+                let config = config_clone.clone();
+                let task = task_clone.clone();
+                let task_health = Arc::clone(&task_health);
+                let session_stats = Arc::clone(&session_stats);
+                let circuit_breaker = Arc::clone(&circuit_breaker);
+                Box::pin(async move {
+                    if dead_letters.is_disabled(&task.name) {
+                        info!(
+                            "Skipping task {}: disabled after repeated failures",
+                            task.name
+                        );
+                        return;
+                    }
+                    if !circuit_breaker.allow_run(&task.name, Utc::now()) {
+                        info!("Skipping task {}: circuit breaker is open", task.name);
+                        return;
+                    }
+                    // Run on its own tokio task, so a panic fails this run like an error
+                    // instead of going unnoticed
+                    let run = {
+                        let config = config.clone();
+                        let task = task.clone();
+                        let session_stats = Arc::clone(&session_stats);
+                        tokio::spawn(async move {
+                            task::process_cleanup_task(&metadata, &config, &task, &session_stats)
+                                .await
+                        })
+                    };
+                    let result = match run.await {
+                        Ok(result) => result,
+                        Err(e) => {
+                            let message = if e.is_panic() {
+                                panic_message(e.into_panic().as_ref())
+                            } else {
+                                e.to_string()
+                            };
+                            session_stats.record_run(0, false);
+                            task::notify_task_panic(&config, &task, &message).await;
+                            Err(anyhow::anyhow!("Task panicked: {}", message))
+                        }
+                    };
+                    let transition = match result {
+                        Ok(()) => {
+                            task_health.record_success(&task.name);
+                            dead_letters.record_success(&task.name);
+                            circuit_breaker.record_success(&task.name)
+                        }
+                        Err(e) => {
+                            task_health.record_failure(&task.name);
+                            warn!("Error running cleanup tasks: {}", e);
+                            let error = config.redact_secrets(&format!("{:#}", e));
+                            if let Some(dead_letter) =
+                                dead_letters.record_failure(&task.name, &error, Utc::now())
+                            {
+                                task::notify_dead_letter(&config, &task, &dead_letter).await;
+                                return;
+                            }
+                            circuit_breaker.record_failure(&task.name, Utc::now())
+                        }
+                    };
+                    if let Some(transition) = transition {
+                        task::notify_circuit_breaker(&config, &task, transition).await;
+                    }
+                }) as Pin<Box<dyn Future<Output = ()> + Send>>
+            };
+            let job = match completion_interval {
+                Some(interval) => Job::after_completion(task.name, interval, function),
+                None => Job::new(task.name, &task.cron_schedule, function).unwrap(),
+            };
+            scheduler.add(
+                job.with_min_interval(min_interval)
+                    .with_initial_delay(initial_delay)
+                    .with_priority(task.priority),
+            );
+        }
+    }
+    // scheduler.add(
+    //     Job::new("every 2", "*/2 * * * * *", move |_| {
+    //         Box::pin(async {
+    //             println!("{:?} - Every 2 seconds", Utc::now());
+    //         })
+    //     })
+    //     .unwrap(),
+    // );
+
+    if scheduler.jobs().is_empty() {
+        warn!("No enabled cleanup tasks to schedule, exiting");
+        return Ok(());
+    }
+
+    info!("Scheduled {} task(s):", scheduler.jobs().len());
+    for job in scheduler.jobs() {
+        match job.next_run() {
+            Some(next_run) => info!(
+                "  Task `{}` [{}] next run at {}",
+                job.name(),
+                job.cron(),
+                next_run
+            ),
+            None => warn!(
+                "  Task `{}` [{}] has no upcoming run",
+                job.name(),
+                job.cron()
+            ),
+        }
+    }
+
+    // Start the scheduler in the background
+    let scheduler_handle = tokio::spawn(async move {
+        scheduler.start().await;
+    });
+
+    // Wait for shutdown signal (Ctrl+C or SIGTERM)
+    info!("Server running. Press Ctrl+C or send SIGTERM to stop");
+    shutdown_signal().await;
+    info!("Shutdown signal received, stopping gracefully...");
+
+    // Cancel the scheduler task
+    scheduler_handle.abort();
+    info!("Scheduler stopped");
+
+    let summary = session_stats.summary(&task_health, Utc::now());
+    task::notify_shutdown(&shutdown_config, &summary).await;
+    let failing_tasks = summary.failing_tasks;
+    info!("Shutdown complete");
+
+    if exit_nonzero_on_failures && !failing_tasks.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Exiting with failure: tasks failing since their last success: {}",
+            failing_tasks.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+fn find_task<'a>(
+    config: &'a cleaner::config::FullConfig,
+    task_name: &str,
+) -> Result<&'a cleaner::config::CleanupTask> {
+    config
+        .cleanup_tasks
+        .iter()
+        .find(|task| task.name == task_name)
+        .ok_or_else(|| anyhow::anyhow!("No valid task named '{}' in the configuration", task_name))
+}
+
+async fn run_task_once(
+    config: &cleaner::config::FullConfig,
+    task_name: &str,
+    as_of: DateTime<Utc>,
+) -> Result<()> {
+    let task = find_task(config, task_name)?;
+    info!("Running task `{}` once as of {}", task.name, as_of);
+    task::process_cleanup_task(
+        &JobScheduleMetadata::new(task.data_interval_start(as_of), as_of),
+        &config.config,
+        task,
+        &SessionStats::default(),
+    )
+    .await
+}
+
+/// Runs the task once per scheduled interval between `since` and `until`, oldest first, stopping
+/// at the first failure unless `continue_on_error` is set.
+async fn run_backfill(
+    config: &cleaner::config::FullConfig,
+    task_name: &str,
+    since: DateTime<Utc>,
+    until: DateTime<Utc>,
+    continue_on_error: bool,
+) -> Result<()> {
+    let task = find_task(config, task_name)?;
+    if task.interval_after_completion.is_some() {
+        return Err(anyhow::anyhow!(
+            "Task `{}` runs on interval_after_completion, backfill needs a cron_schedule",
+            task.name
+        ));
+    }
+    let intervals = task::backfill_intervals(&task.cron_schedule, since, until)?;
+    info!(
+        "Backfilling task `{}` over {} interval(s) from {} to {}",
+        task.name,
+        intervals.len(),
+        since,
+        until
+    );
+
+    let session_stats = SessionStats::default();
+    let mut failed = Vec::new();
+    for (index, interval) in intervals.iter().enumerate() {
+        let fire_time = interval.data_interval_end;
+        info!(
+            "Backfill run {}/{} of task `{}`: {} to {}",
+            index + 1,
+            intervals.len(),
+            task.name,
+            interval.data_interval_start,
+            fire_time
+        );
+        let result =
+            task::process_cleanup_task(interval, &config.config, task, &session_stats).await;
+        if let Err(e) = result {
+            if !continue_on_error {
+                return Err(e.context(format!(
+                    "Backfill of task `{}` stopped at the interval ending {}, pass \
+                     --continue-on-error to run the remaining {} interval(s) anyway",
+                    task.name,
+                    fire_time,
+                    intervals.len() - index - 1
+                )));
+            }
+            error!(
+                "Backfill run of task `{}` ending {} failed, continuing: {:#}",
+                task.name, fire_time, e
+            );
+            failed.push(fire_time.to_rfc3339());
+        }
+    }
+
+    if !failed.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Backfill of task `{}` failed for {}/{} interval(s) ending: {}",
+            task.name,
+            failed.len(),
+            intervals.len(),
+            failed.join(", ")
+        ));
+    }
+    info!(
+        "Backfill of task `{}` finished {} interval(s)",
+        task.name,
+        intervals.len()
+    );
+    Ok(())
+}
+
+async fn test_connection(config: &cleaner::config::Config) -> Result<()> {
+    let result = async {
+        let db = Database::new(&config.database_config).await?;
+        db.fetch_scalar::<i64>("SELECT 1").await?;
+        db.fetch_scalar::<String>("SELECT VERSION()").await
+    }
+    .await;
+
+    match result {
+        Ok(version) => {
+            println!(
+                "Connection to {} succeeded, server version: {}",
+                config.database_config.database, version
+            );
+            Ok(())
+        }
+        Err(e) => {
+            let message = config.redact_secrets(&format!("{:#}", e));
+            eprintln!("Connection failed: {}", message);
+            Err(anyhow::anyhow!("Connection failed: {}", message))
+        }
+    }
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("failed to install signal handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
@@ -0,0 +1,35 @@
+//! Kiyoshi runs templated, batched SQL cleanup tasks on a cron schedule.
+//!
+//! The binary is a thin wrapper over this library, so the config loader, validator, template
+//! engine, scheduler and cleanup engine can all be embedded in another service.
+
+pub mod cli;
+
+pub(crate) mod cleaner;
+pub(crate) mod scheduler;
+
+pub use cleaner::{
+    config::{CleanupTask, Config, FullConfig},
+    db::Database,
+    health::SessionStats,
+    sql_validate::SqlValidator,
+    task::process_cleanup_task,
+    template::TemplateEngine,
+};
+// The types of the public fields and methods of the types above
+pub use cleaner::{
+    config::{
+        AdminServerConfig, ChildCleanup, CircuitBreakerConfig, DatabaseConfig, DeadLetterConfig,
+        DeleteOrder, DisabledTask, DurationStyle, LookupConfig, OptimizeAfterCleanup,
+        PartitionDrop, QueryIntervalMode, RunHistoryConfig, SafeMode, SafeModeEnforcement,
+        SlackChannels, SlackConfig, SlackEvent, SlackStartupValidation, SlackTemplates, SqlDialect,
+        SshTunnelConfig, TableReport, TruncateQueries, ValidationMode,
+    },
+    db::PoolStatus,
+    health::{SessionSummary, TaskHealth},
+    template::IdentifierQuote,
+};
+pub use scheduler::{
+    core::Scheduler,
+    job::{Job, JobScheduleMetadata},
+};
@@ -1,49 +1,129 @@
-use std::{ops::Add, time::Duration};
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+use tokio::{sync::Notify, time::Instant};
 
 use super::job::Job;
 
 #[derive(Default)]
 pub struct Scheduler {
     jobs: Vec<Job>,
+    /// Notified when a run of a job running after completion finishes, so its next run is
+    /// scheduled without waiting for the other jobs
+    wake: Arc<Notify>,
 }
 
 impl Scheduler {
-    pub fn add(&mut self, job: Job) {
+    pub fn add(&mut self, mut job: Job) {
+        job.set_wake(Arc::clone(&self.wake));
         self.jobs.push(job);
     }
 
-    pub fn until(&mut self) -> Option<(Vec<&mut Job>, Duration)> {
-        let mut next_jobs = Vec::new();
-        let mut next_job_duration = None; // the time delta until the next run
-        for job in &mut self.jobs {
-            if let Some(duration) = job.until() {
-                let duration_millis = duration.as_millis();
-                if next_job_duration.is_none() || duration_millis < next_job_duration.unwrap() {
-                    next_job_duration = Some(duration_millis);
-                    next_jobs.clear();
-                    next_jobs.push(job);
-                } else if duration_millis == next_job_duration.unwrap() {
-                    next_jobs.push(job);
-                }
+    pub fn jobs(&self) -> &[Job] {
+        &self.jobs
+    }
+
+    /// The jobs due next as seen at `now`, highest priority first, and the time they are due.
+    pub fn next_due(&mut self, now: DateTime<Utc>) -> Option<(Vec<&mut Job>, DateTime<Utc>)> {
+        let next_run = self
+            .jobs
+            .iter()
+            .filter_map(|job| job.next_run_from(now))
+            .min()?;
+        let mut next_jobs: Vec<_> = self
+            .jobs
+            .iter_mut()
+            .filter(|job| job.next_run_from(now) == Some(next_run))
+            .collect();
+        // Stable, so jobs of equal priority keep the order they were added in
+        next_jobs.sort_by_key(|job| std::cmp::Reverse(job.priority()));
+        Some((next_jobs, next_run))
+    }
+
+    pub async fn start(&mut self) {
+        let wake = Arc::clone(&self.wake);
+        while !self.jobs.is_empty() {
+            let now = Utc::now();
+            let started = Instant::now();
+            let Some((jobs, next_run)) = self.next_due(now) else {
+                // Every job is waiting for its run to finish
+                wake.notified().await;
+                continue;
+            };
+            // Sleep until a monotonic deadline derived from the fire time once, so time spent
+            // computing the next run does not delay it. Waking slightly early is fine, see
+            // `Job::run`
+            let until = (next_run - now).to_std().unwrap_or_default();
+            tokio::select! {
+                _ = tokio::time::sleep_until(started + until) => {}
+                // A finished run may be due before the jobs we are waiting for
+                _ = wake.notified() => continue,
+            }
+            for job in jobs {
+                job.run().await;
             }
         }
-        if let Some(duration) = next_job_duration {
-            return Some((next_jobs, Duration::from_millis(duration as u64)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Mutex, time::Duration};
+
+    #[tokio::test]
+    async fn test_simultaneous_jobs_run_by_priority() {
+        let dispatched = Arc::new(Mutex::new(Vec::new()));
+        let mut scheduler = Scheduler::default();
+        for (name, priority) in [("low", -1), ("default", 0), ("critical", 10)] {
+            let dispatched = Arc::clone(&dispatched);
+            scheduler.add(
+                Job::new(name, "0 0 0 1 1 *", move |_| {
+                    dispatched.lock().unwrap().push(name);
+                    Box::pin(async {})
+                })
+                .unwrap()
+                .with_priority(priority),
+            );
+        }
+
+        let (jobs, _) = scheduler.next_due(Utc::now()).unwrap();
+        let names: Vec<_> = jobs.iter().map(|job| job.name().to_string()).collect();
+        assert_eq!(names, vec!["critical", "default", "low"]);
+        for job in jobs {
+            job.run().await;
         }
-        None
+        assert_eq!(
+            *dispatched.lock().unwrap(),
+            vec!["critical", "default", "low"]
+        );
     }
 
-    pub async fn start(&mut self) {
-        loop {
-            if let Some((jobs, duration)) = self.until() {
-                // a hack to make sure we don't fire a job a few microseconds early
-                tokio::time::sleep(duration.add(std::time::Duration::from_micros(700))).await;
-                for job in jobs {
-                    job.run().await;
-                }
-            } else {
-                return;
-            }
+    #[tokio::test]
+    async fn test_after_completion_runs_do_not_overlap() {
+        let started = Arc::new(Mutex::new(Vec::new()));
+        let mut scheduler = Scheduler::default();
+        let runs = Arc::clone(&started);
+        scheduler.add(Job::after_completion(
+            "purge",
+            Duration::from_millis(100),
+            move |_| {
+                runs.lock().unwrap().push(Instant::now());
+                Box::pin(tokio::time::sleep(Duration::from_millis(150)))
+            },
+        ));
+        // A cron job far in the future must not hold the after completion job back
+        scheduler.add(Job::new("yearly", "0 0 0 1 1 *", |_| Box::pin(async {})).unwrap());
+
+        let handle = tokio::spawn(async move { scheduler.start().await });
+        tokio::time::sleep(Duration::from_millis(900)).await;
+        handle.abort();
+
+        let started = started.lock().unwrap();
+        assert!(started.len() >= 2, "only {} runs", started.len());
+        for runs in started.windows(2) {
+            let gap = runs[1] - runs[0];
+            // The 150ms run plus the 100ms interval
+            assert!(gap >= Duration::from_millis(245), "runs {:?} apart", gap);
         }
     }
 }
@@ -1,32 +1,106 @@
-use std::{future::Future, pin::Pin, str::FromStr, time::Duration};
+use std::{
+    any::Any,
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use chrono::{DateTime, Utc};
 use cron::Schedule;
-use log::info;
+use log::{error, info, warn};
+use tokio::sync::Notify;
 
 type JobFunction =
     (dyn FnMut(JobScheduleMetadata) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync);
 
+/// When a job fires.
+enum JobSchedule {
+    Cron(Box<Schedule>),
+    /// `interval` after the previous run finished, see [`Job::after_completion`]
+    AfterCompletion {
+        interval: chrono::Duration,
+        description: String,
+    },
+}
+
+/// Whether a job's run is in progress and when its last run finished, updated by the run itself.
+#[derive(Debug, Default)]
+struct RunState {
+    running: bool,
+    finished_at: Option<DateTime<Utc>>,
+}
+
+/// Marks a run as finished when dropped, so a run that panics still counts as finished, and wakes
+/// the scheduler to compute the job's next run.
+struct FinishOnDrop {
+    state: Arc<Mutex<RunState>>,
+    wake: Arc<Notify>,
+}
+
+impl Drop for FinishOnDrop {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.running = false;
+        state.finished_at = Some(Utc::now());
+        // Stores a permit if the scheduler is not waiting yet, so the wake-up is never lost
+        self.wake.notify_one();
+    }
+}
+
 pub struct Job {
     name: String,
-    schedule: Schedule,
+    schedule: JobSchedule,
     function: Box<JobFunction>,
     last_run: Option<DateTime<Utc>>,
+    min_interval: Option<Duration>,
+    /// The first run is the first fire time after this, see [`Job::with_initial_delay`]
+    not_before: Option<DateTime<Utc>>,
+    priority: i32,
     schedule_metadata: JobScheduleMetadata,
+    run_state: Arc<Mutex<RunState>>,
+    /// Notified when a run of an [`Job::after_completion`] job finishes, shared with the scheduler
+    wake: Arc<Notify>,
 }
 
-#[derive(Clone, Copy)]
+/// The data interval a run covers, from the previous fire time to its own.
+#[derive(Clone, Copy, Debug)]
 pub struct JobScheduleMetadata {
+    pub data_interval_start: DateTime<Utc>,
     pub data_interval_end: DateTime<Utc>,
 }
 
-impl JobScheduleMetadata {
-    pub fn new(data_interval_end: DateTime<Utc>) -> Self {
-        Self { data_interval_end }
+/// An interval in its largest whole unit, e.g. `30m` or `90s`.
+fn format_interval(interval: chrono::Duration) -> String {
+    let seconds = interval.num_seconds();
+    match seconds {
+        0 => "0s".to_string(),
+        _ if seconds % 86400 == 0 => format!("{}d", seconds / 86400),
+        _ if seconds % 3600 == 0 => format!("{}h", seconds / 3600),
+        _ if seconds % 60 == 0 => format!("{}m", seconds / 60),
+        _ => format!("{}s", seconds),
     }
+}
 
-    pub fn update(&mut self, data_interval_end: DateTime<Utc>) {
-        self.data_interval_end = data_interval_end;
+/// The message of a caught panic, for panics with a string payload as raised by `panic!`.
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic payload".to_string()
+    }
+}
+
+impl JobScheduleMetadata {
+    pub fn new(data_interval_start: DateTime<Utc>, data_interval_end: DateTime<Utc>) -> Self {
+        Self {
+            data_interval_start,
+            data_interval_end,
+        }
     }
 }
 
@@ -42,28 +116,191 @@ impl Job {
         let schedule = Schedule::from_str(schedule)?;
         let now = Utc::now();
         let upcoming = Self::get_next_schedule(&schedule, now);
+        let previous = Self::get_previous_schedule(&schedule, upcoming);
 
         Ok(Self {
             name: name.into(),
-            schedule,
+            schedule: JobSchedule::Cron(Box::new(schedule)),
             function: Box::new(function),
             last_run: None,
-            schedule_metadata: JobScheduleMetadata::new(upcoming),
+            min_interval: None,
+            not_before: None,
+            priority: 0,
+            schedule_metadata: JobScheduleMetadata::new(previous, upcoming),
+            run_state: Arc::default(),
+            wake: Arc::default(),
         })
     }
 
+    /// A job that first runs right away, then `interval` after each run finishes instead of on a
+    /// cron schedule, so its runs never overlap however long they take.
+    pub fn after_completion<T, S>(name: S, interval: Duration, function: T) -> Self
+    where
+        S: Into<String>,
+        T: FnMut(JobScheduleMetadata) -> Pin<Box<dyn Future<Output = ()> + Send>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        let interval = chrono::Duration::from_std(interval).unwrap_or(chrono::Duration::MAX);
+        let now = Utc::now();
+        Self {
+            name: name.into(),
+            schedule: JobSchedule::AfterCompletion {
+                interval,
+                description: format!("{} after completion", format_interval(interval)),
+            },
+            function: Box::new(function),
+            last_run: None,
+            min_interval: None,
+            not_before: None,
+            priority: 0,
+            schedule_metadata: JobScheduleMetadata::new(
+                now.checked_sub_signed(interval)
+                    .unwrap_or(DateTime::<Utc>::MIN_UTC),
+                now,
+            ),
+            run_state: Arc::default(),
+            wake: Arc::default(),
+        }
+    }
+
+    /// Shares the scheduler's wake-up with the job, see [`Job::after_completion`].
+    pub(super) fn set_wake(&mut self, wake: Arc<Notify>) {
+        self.wake = wake;
+    }
+
+    /// Sets a floor on the time between two consecutive runs, regardless of the cron schedule.
+    pub fn with_min_interval(mut self, min_interval: Option<Duration>) -> Self {
+        self.min_interval = min_interval;
+        self
+    }
+
+    /// Skips the fire times within `initial_delay` from now, so the job first runs at the first
+    /// fire time after the delay.
+    pub fn with_initial_delay(mut self, initial_delay: Option<Duration>) -> Self {
+        let Some(initial_delay) = initial_delay.and_then(|d| chrono::Duration::from_std(d).ok())
+        else {
+            return self;
+        };
+        let now = Utc::now();
+        self.not_before = Some(now + initial_delay);
+        if let Some(upcoming) = self.next_run_from(now) {
+            self.set_next_interval(upcoming);
+        }
+        self
+    }
+
+    /// Sets the job's priority. Among jobs due at the same time, higher priorities run first.
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Pushes a cron fire time forward to the first fire time respecting `min_interval` since the
+    /// last run.
+    fn apply_min_interval(&self, upcoming: DateTime<Utc>) -> DateTime<Utc> {
+        let (Some(last_run), Some(min_interval)) = (self.last_run, self.min_interval) else {
+            return upcoming;
+        };
+        let Ok(min_interval) = chrono::Duration::from_std(min_interval) else {
+            return upcoming;
+        };
+        let earliest = last_run + min_interval;
+        if upcoming >= earliest {
+            return upcoming;
+        }
+        match &self.schedule {
+            JobSchedule::Cron(schedule) => {
+                Self::get_next_schedule(schedule, earliest - chrono::Duration::nanoseconds(1))
+            }
+            JobSchedule::AfterCompletion { .. } => earliest,
+        }
+    }
+
     pub fn get_next_schedule(schedule: &Schedule, now: DateTime<Utc>) -> DateTime<Utc> {
         schedule.after(&now).next().unwrap_or(now)
     }
 
+    /// The last fire time of `schedule` before `fire_time`, where the interval ending at
+    /// `fire_time` starts.
+    pub fn get_previous_schedule(schedule: &Schedule, fire_time: DateTime<Utc>) -> DateTime<Utc> {
+        // Iterating an `after` iterator backwards yields fire times before `fire_time`
+        schedule.after(&fire_time).next_back().unwrap_or(fire_time)
+    }
+
+    /// Sets the interval of the next run, which ends at `fire_time` and starts at the previous
+    /// fire time of a cron job. A job running after completion has no fixed schedule, so its
+    /// interval starts at its previous run, or `interval` earlier before the first one.
+    fn set_next_interval(&mut self, fire_time: DateTime<Utc>) {
+        let data_interval_start = match &self.schedule {
+            JobSchedule::Cron(schedule) => Self::get_previous_schedule(schedule, fire_time),
+            JobSchedule::AfterCompletion { interval, .. } => self.last_run.unwrap_or_else(|| {
+                fire_time
+                    .checked_sub_signed(*interval)
+                    .unwrap_or(DateTime::<Utc>::MIN_UTC)
+            }),
+        };
+        self.schedule_metadata = JobScheduleMetadata::new(data_interval_start, fire_time);
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The cron expression the job was created with, or its interval after completion.
+    pub fn cron(&self) -> &str {
+        match &self.schedule {
+            JobSchedule::Cron(schedule) => schedule.source(),
+            JobSchedule::AfterCompletion { description, .. } => description,
+        }
+    }
+
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    /// The next time the job will fire.
+    pub fn next_run(&self) -> Option<DateTime<Utc>> {
+        self.next_run_from(Utc::now())
+    }
+
+    /// The next time the job will fire, as seen at `now`. The scheduler passes the same `now` to
+    /// every job so that jobs due at the same instant compare equal.
+    /// A job running after completion has no next run while its run is in progress.
+    pub fn next_run_from(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let schedule = match &self.schedule {
+            JobSchedule::Cron(schedule) => schedule,
+            JobSchedule::AfterCompletion { interval, .. } => {
+                let state = self.run_state.lock().unwrap_or_else(|e| e.into_inner());
+                if state.running {
+                    return None;
+                }
+                let upcoming = match (state.finished_at, self.not_before) {
+                    (Some(finished_at), _) => finished_at + *interval,
+                    (None, Some(not_before)) => now.max(not_before),
+                    (None, None) => now,
+                };
+                return Some(self.apply_min_interval(upcoming));
+            }
+        };
+        let from = match (self.last_run, self.not_before) {
+            (Some(last_run), _) => last_run,
+            // Fire times exactly at the end of the delay still count
+            (None, Some(not_before)) => now.max(not_before - chrono::Duration::nanoseconds(1)),
+            (None, None) => now,
+        };
+        schedule
+            .after(&from)
+            .next()
+            .map(|upcoming| self.apply_min_interval(upcoming))
+    }
+
     #[must_use]
     pub fn until(&self) -> Option<Duration> {
-        if let Some(upcoming) = self
-            .schedule
-            .after(&self.last_run.unwrap_or_else(Utc::now))
-            .next()
-        {
-            return if let Ok(duration_until) = upcoming.signed_duration_since(Utc::now()).to_std() {
+        let now = Utc::now();
+        if let Some(upcoming) = self.next_run_from(now) {
+            return if let Ok(duration_until) = upcoming.signed_duration_since(now).to_std() {
                 Some(duration_until)
             } else {
                 Some(Duration::from_secs(0))
@@ -72,20 +309,85 @@ impl Job {
         None
     }
 
+    /// Starts the job's next run in the background. A panic while creating the run is caught and
+    /// logged, so it never stops the scheduler, and the job still runs at its next fire time.
     pub async fn run(&mut self) {
-        let now = Utc::now();
+        let metadata = self.advance(Utc::now());
+        // Recorded once the run is done, or right away if it fails to start
+        let finish =
+            matches!(self.schedule, JobSchedule::AfterCompletion { .. }).then(|| FinishOnDrop {
+                state: Arc::clone(&self.run_state),
+                wake: Arc::clone(&self.wake),
+            });
+        let function = &mut self.function;
+        match panic::catch_unwind(AssertUnwindSafe(|| function(metadata))) {
+            Ok(fut) => {
+                tokio::spawn(async move {
+                    let _finish = finish;
+                    fut.await;
+                });
+            }
+            Err(payload) => error!(
+                "Task `{}` panicked while starting, skipping this run: {}",
+                self.name,
+                panic_message(payload.as_ref())
+            ),
+        }
+    }
+
+    /// Records a run woken at `now` and schedules the next one, returning this run's metadata.
+    /// A wake-up slightly before the fire time counts as the fire time, so a cron tick never fires
+    /// twice, and a late wake-up skips the ticks it missed instead of catching up on them.
+    fn advance(&mut self, now: DateTime<Utc>) -> JobScheduleMetadata {
+        let schedule = match &self.schedule {
+            JobSchedule::Cron(schedule) => schedule,
+            JobSchedule::AfterCompletion { interval, .. } => {
+                let interval = *interval;
+                return self.advance_after_completion(now, interval);
+            }
+        };
+        let metadata = self.schedule_metadata;
         info!("Task `{}` firing at {}", self.name, now);
-        self.last_run = Some(now);
+        let last_run = now.max(metadata.data_interval_end);
+        self.last_run = Some(last_run);
 
-        let fut = (self.function)(self.schedule_metadata);
-        tokio::spawn(async move {
-            fut.await;
-        });
-        let next =
-            Self::get_next_schedule(&self.schedule, self.schedule_metadata.data_interval_end);
-        self.schedule_metadata.update(next);
+        let scheduled_next = Self::get_next_schedule(schedule, last_run);
+        let next = self.apply_min_interval(scheduled_next);
+        if next != scheduled_next {
+            warn!(
+                "Task `{}` run at {} deferred to {} to respect the minimum interval between runs",
+                self.name, scheduled_next, next
+            );
+        }
+        self.set_next_interval(next);
         info!("Task `{}`, next run will be at {}", self.name, next);
+        metadata
     }
+
+    /// Like [`Job::advance`] for a job running after completion: marks its run as in progress,
+    /// the next run is only known once it finishes.
+    fn advance_after_completion(
+        &mut self,
+        now: DateTime<Utc>,
+        interval: chrono::Duration,
+    ) -> JobScheduleMetadata {
+        let fire_time = self.next_run_from(now).unwrap_or(now);
+        info!("Task `{}` firing at {}", self.name, now);
+        // Before `last_run` moves on, as the interval starts at the previous run
+        self.set_next_interval(fire_time);
+        self.last_run = Some(now.max(fire_time));
+        self.run_state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .running = true;
+        info!(
+            "Task `{}`, next run will be {} after this run finishes",
+            self.name,
+            format_interval(interval)
+        );
+        self.schedule_metadata
+    }
+
     #[allow(dead_code)]
     pub fn get_schedule_metadata(&self) -> &JobScheduleMetadata {
         &self.schedule_metadata
@@ -152,4 +454,179 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_job_accessors() {
+        let job = Job::new("hourly", "0 0 * * * *", |_| Box::pin(async {})).unwrap();
+        assert_eq!(job.name(), "hourly");
+        assert_eq!(job.cron(), "0 0 * * * *");
+
+        let next_run = job.next_run().unwrap();
+        assert!(next_run > Utc::now());
+        assert!(next_run <= Utc::now() + chrono::Duration::hours(1));
+    }
+
+    #[test]
+    fn test_fire_times_do_not_drift() {
+        let mut job = Job::new("every second", "* * * * * *", |_| Box::pin(async {})).unwrap();
+        let first = job.next_run().unwrap();
+        for i in 0..1000 {
+            let fire_time = job.next_run().unwrap();
+            assert_eq!(fire_time, first + chrono::Duration::seconds(i));
+            // Wake up a little early or late, as a real timer would
+            let jitter = chrono::Duration::microseconds(if i % 2 == 0 { -700 } else { 5000 });
+            let metadata = job.advance(fire_time + jitter);
+            assert_eq!(metadata.data_interval_end, fire_time);
+            // The interval starts at the previous fire time
+            assert_eq!(
+                metadata.data_interval_start,
+                fire_time - chrono::Duration::seconds(1)
+            );
+        }
+    }
+
+    #[test]
+    fn test_late_wake_up_skips_missed_ticks() {
+        let mut job = Job::new("every second", "* * * * * *", |_| Box::pin(async {})).unwrap();
+        let fire_time = job.next_run().unwrap();
+        job.advance(fire_time + chrono::Duration::milliseconds(2500));
+        assert_eq!(
+            job.get_schedule_metadata().data_interval_end,
+            fire_time + chrono::Duration::seconds(3)
+        );
+    }
+
+    #[test]
+    fn test_initial_delay_skips_early_fire_times() {
+        let job = Job::new("every second", "* * * * * *", |_| Box::pin(async {})).unwrap();
+        let started = Utc::now();
+        let mut job = job.with_initial_delay(Some(Duration::from_secs(30)));
+        let first = job.next_run().unwrap();
+        assert!(
+            first >= started + chrono::Duration::seconds(30),
+            "got {}",
+            first
+        );
+        assert!(
+            first <= Utc::now() + chrono::Duration::seconds(31),
+            "got {}",
+            first
+        );
+        // The first run's data_interval_end is its actual fire time
+        assert_eq!(job.advance(first).data_interval_end, first);
+        assert_eq!(
+            job.next_run().unwrap(),
+            first + chrono::Duration::seconds(1)
+        );
+
+        let job = Job::new("every second", "* * * * * *", |_| Box::pin(async {})).unwrap();
+        let job = job.with_initial_delay(None);
+        assert!(job.until().unwrap() <= Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_panicking_job_keeps_its_schedule() {
+        let calls = std::sync::Arc::new(std::sync::atomic::AtomicU32::new(0));
+        let counter = std::sync::Arc::clone(&calls);
+        let mut job = Job::new("every second", "* * * * * *", move |_| {
+            if counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0 {
+                panic!("boom");
+            }
+            Box::pin(async {})
+        })
+        .unwrap();
+
+        let first = job.get_schedule_metadata().data_interval_end;
+        job.run().await;
+        assert!(job.get_schedule_metadata().data_interval_end > first);
+        job.run().await;
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+        assert_eq!(panic_message(&"boom"), "boom");
+        assert_eq!(panic_message(&"boom".to_string()), "boom");
+        assert_eq!(panic_message(&1), "unknown panic payload");
+    }
+
+    #[test]
+    fn test_after_completion_spaces_runs_by_run_duration() {
+        let mut job =
+            Job::after_completion("purge", Duration::from_secs(1800), |_| Box::pin(async {}));
+        assert_eq!(job.cron(), "30m after completion");
+        let start = Utc::now();
+        // The first run is right away
+        assert_eq!(job.next_run_from(start), Some(start));
+
+        let mut fire_time = start;
+        // The first interval is one `interval` long, later ones start at the previous run
+        let mut previous_fire_time = start - chrono::Duration::minutes(30);
+        for run_minutes in [90, 5, 240] {
+            let metadata = job.advance(fire_time);
+            assert_eq!(metadata.data_interval_end, fire_time);
+            assert_eq!(metadata.data_interval_start, previous_fire_time);
+            previous_fire_time = fire_time;
+            // No next run while the run is in progress, however long it takes
+            assert_eq!(
+                job.next_run_from(fire_time + chrono::Duration::minutes(run_minutes + 60)),
+                None
+            );
+            let finished_at = fire_time + chrono::Duration::minutes(run_minutes);
+            *job.run_state.lock().unwrap() = RunState {
+                running: false,
+                finished_at: Some(finished_at),
+            };
+            let next = job.next_run_from(finished_at).unwrap();
+            assert_eq!(
+                next - fire_time,
+                chrono::Duration::minutes(run_minutes + 30)
+            );
+            fire_time = next;
+        }
+
+        // min_interval still applies on top
+        let mut job = job.with_min_interval(Some(Duration::from_secs(3 * 3600)));
+        job.advance(fire_time);
+        *job.run_state.lock().unwrap() = RunState {
+            running: false,
+            finished_at: Some(fire_time + chrono::Duration::minutes(10)),
+        };
+        assert_eq!(
+            job.next_run_from(fire_time),
+            Some(fire_time + chrono::Duration::hours(3))
+        );
+        assert_eq!(format_interval(chrono::Duration::seconds(90)), "90s");
+        assert_eq!(format_interval(chrono::Duration::days(2)), "2d");
+    }
+
+    #[tokio::test]
+    async fn test_after_completion_run_finishes_even_on_panic() {
+        let mut job = Job::after_completion("purge", Duration::from_secs(60), |_| {
+            Box::pin(async { panic!("boom") })
+        });
+        let wake = Arc::new(Notify::new());
+        job.set_wake(Arc::clone(&wake));
+        job.run().await;
+        wake.notified().await;
+        let state = job.run_state.lock().unwrap();
+        assert!(!state.running);
+        assert!(state.finished_at.is_some());
+    }
+
+    #[test]
+    fn test_min_interval_defers_until() {
+        let job = Job::new("every second", "* * * * * *", |_| Box::pin(async {})).unwrap();
+        let mut job = job.with_min_interval(Some(Duration::from_secs(60)));
+        assert!(job.until().unwrap() <= Duration::from_secs(1));
+
+        let last_run = Utc::now();
+        job.last_run = Some(last_run);
+        let until = job.until().unwrap();
+        assert!(until > Duration::from_secs(58), "got {:?}", until);
+        assert!(until <= Duration::from_secs(61), "got {:?}", until);
+
+        let JobSchedule::Cron(schedule) = &job.schedule else {
+            unreachable!()
+        };
+        let cron_next = Job::get_next_schedule(schedule, last_run);
+        assert!(job.apply_min_interval(cron_next) >= last_run + chrono::Duration::seconds(60));
+    }
 }
@@ -1,2 +1,2 @@
-pub mod core;
-pub mod job;
+pub(crate) mod core;
+pub(crate) mod job;